@@ -0,0 +1,105 @@
+use crate::colorize::StyleDef;
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashMap;
+
+// The names, patterns and default colors of tokens detected without config.
+// Later tokens are drawn over earlier ones like IP addresses over numbers.
+static TOKENS: [(&str, &str, &str); 7] = [
+    ("hex", r"\b0x[0-9a-fA-F]+\b|\b[0-9a-f]{8,}\b", "Magenta"),
+    ("number", r"\b\d+(?:\.\d+)?\b", "Cyan"),
+    ("string", r#""[^"\n]*"|\B'[^'\n]*'\B"#, "Yellow"),
+    (
+        "timestamp",
+        r"\b\d{4}-\d\d-\d\d(?:[T ]\d\d:\d\d:\d\d(?:[.,]\d+)?(?:Z|[+-]\d\d:?\d\d)?)?\b|\b\d\d:\d\d:\d\d(?:[.,]\d+)?\b",
+        "Blue",
+    ),
+    (
+        "uuid",
+        r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b",
+        "LightMagenta",
+    ),
+    ("ip", r"\b\d{1,3}(?:\.\d{1,3}){3}(?::\d+)?\b", "LightGreen"),
+    (
+        "url",
+        r#"\b[a-zA-Z][a-zA-Z0-9+.-]*://[^\s"'<>]+"#,
+        "LightBlue+Underline",
+    ),
+];
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// The highlights of the detected tokens.
+// The colors can be changed by the styles named like "auto_ip".
+pub fn highlights(styles: &HashMap<String, StyleDef>) -> Result<Vec<(Regex, String)>> {
+    let mut ret = Vec::new();
+    for (name, pat, default) in &TOKENS {
+        let style = format!("auto_{}", name);
+        let color = if styles.contains_key(&style) {
+            format!("@{}", style)
+        } else {
+            default.to_string()
+        };
+        ret.push((Regex::new(pat)?, color));
+    }
+    Ok(ret)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlights() {
+        let tokens = highlights(&HashMap::new()).unwrap();
+        let find = |name: &str, s: &str| {
+            let i = TOKENS.iter().position(|x| x.0 == name).unwrap();
+            tokens[i]
+                .0
+                .find_iter(s)
+                .map(|x| x.as_str().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(find("number", "took 12.5 ms, retry 3"), ["12.5", "3"]);
+        assert_eq!(
+            find("string", r#"msg="disk full" it's 'x'"#),
+            ["\"disk full\"", "'x'"]
+        );
+        assert_eq!(
+            find("hex", "commit 1a2b3c4d5e ok 0xff"),
+            ["1a2b3c4d5e", "0xff"]
+        );
+        assert_eq!(
+            find("timestamp", "2021-03-04T05:06:07.123Z at 05:06:07"),
+            ["2021-03-04T05:06:07.123Z", "05:06:07"]
+        );
+        assert_eq!(
+            find("uuid", "id=123e4567-e89b-12d3-a456-426614174000"),
+            ["123e4567-e89b-12d3-a456-426614174000"]
+        );
+        assert_eq!(find("ip", "from 192.168.0.1:8080"), ["192.168.0.1:8080"]);
+        assert_eq!(
+            find("url", "see https://example.com/a?b=c."),
+            ["https://example.com/a?b=c."]
+        );
+
+        let mut styles = HashMap::new();
+        styles.insert(
+            String::from("auto_ip"),
+            StyleDef {
+                fg: Some(String::from("Red")),
+                bold: false,
+                italic: false,
+                underline: false,
+            },
+        );
+        let tokens = highlights(&styles).unwrap();
+        assert_eq!(tokens[5].1, "@auto_ip");
+    }
+}
@@ -1,37 +1,578 @@
-use anyhow::{bail, Result};
-use regex::Regex;
+use crate::error::Error;
+use crate::tokenize::{get_field, Tokenizer};
+use anyhow::{anyhow, bail, Result};
+use regex::{Captures, Regex, RegexSet};
 use serde_derive::Deserialize;
-use termion::color;
-use termion::color::Color;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::path::PathBuf;
 
 // -------------------------------------------------------------------------------------------------
 // Config
 // -------------------------------------------------------------------------------------------------
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Config {
+    #[serde(default)]
     pub lines: Vec<Line>,
+
+    #[serde(default)]
+    pub stderr_lines: Option<Vec<Line>>,
+
+    #[serde(default)]
+    pub styles: HashMap<String, StyleDef>,
+
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+
+    #[serde(default)]
+    pub on_eof: Option<OnEof>,
+
+    #[serde(default)]
+    pub banner: bool,
+
+    #[serde(default, with = "regex_opt_serde")]
+    pub dedupe_strip: Option<Regex>,
+
+    #[serde(default)]
+    pub tokenizer: Option<Tokenizer>,
+
+    // Lines not matching the pattern are joined to the previous record like stack traces
+    #[serde(default, with = "regex_opt_serde")]
+    pub record_start: Option<Regex>,
+
+    // Named captures year, month, day, hour, minute, second and offset of the time of lines
+    #[serde(default, with = "regex_opt_serde")]
+    pub timestamp: Option<Regex>,
+
+    // The markers of matched lines by --markers, which are "»" and "«" if omitted
+    #[serde(default)]
+    pub markers: Option<(String, String)>,
+
+    // How the rule is chosen if several line rules match the line
+    #[serde(default)]
+    pub match_mode: Option<MatchMode>,
+
+    // Config files merged with lower priority at load time, relative to this file.
+    // `extends` is merged after all of `include`.
+    #[serde(default)]
+    pub include: Vec<PathBuf>,
+
+    #[serde(default)]
+    pub extends: Option<PathBuf>,
+
+    #[serde(skip)]
+    pub depth: ColorDepth,
+
+    // Wrap matched lines by markers
+    #[serde(skip)]
+    pub show_markers: bool,
+
+    // Literal words and the colors highlighted at all occurrences in any line
+    #[serde(skip)]
+    pub highlights: Vec<(Regex, String)>,
+
+    // Match all patterns case-insensitively regardless of the flags of the rules
+    #[serde(skip)]
+    pub ignore_case: bool,
+
+    // The color of lines matched by no rule
+    #[serde(skip)]
+    pub others: Option<String>,
+
+    // The patterns of all line rules to find candidate rules in one pass
+    #[serde(skip)]
+    pub line_set: Option<RegexSet>,
+}
+
+fn default_markers() -> (String, String) {
+    (String::from("»"), String::from("«"))
+}
+
+pub type Rgb = (u8, u8, u8);
+
+// The tables of colors by the captured values, keyed by the groups.
+// The names of groups are replaced by the indexes when the config is resolved.
+pub type ValueColors = HashMap<String, HashMap<String, String>>;
+
+#[derive(Clone, Deserialize)]
+pub struct StyleDef {
+    pub fg: Option<String>,
+
+    #[serde(default)]
+    pub bold: bool,
+
+    #[serde(default)]
+    pub italic: bool,
+
+    #[serde(default)]
+    pub underline: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Line {
     #[serde(with = "regex_serde")]
     pub pat: Regex,
 
-    #[serde(with = "colors_serde")]
+    #[serde(flatten)]
+    pub flags: Flags,
+
+    // Colors can be omitted if the lines are dropped
+    #[serde(default, with = "colors_serde")]
     pub colors: Vec<String>,
 
+    // The color of the whole line drawn under the colors of groups and tokens
+    #[serde(default)]
+    pub line_color: Option<String>,
+
+    // The colors by the captured values for each group by the name or the index
+    #[serde(default)]
+    pub value_colors: ValueColors,
+
+    #[serde(default)]
+    pub action: Action,
+
     #[serde(default)]
     pub tokens: Vec<Token>,
+
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    // The rule with the highest priority is applied by match_mode = "priority"
+    #[serde(default)]
+    pub priority: i64,
+
+    // The following rules are tried after the rule matches, and drawn over it
+    #[serde(default, rename = "continue")]
+    pub continues: bool,
+
+    // Colorize all occurrences of the pattern instead of the first one
+    #[serde(default)]
+    pub global: bool,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    #[serde(default)]
+    pub field: Option<String>,
+
+    #[serde(default)]
+    pub marker: Option<(String, String)>,
+
+    #[serde(default)]
+    pub trigger: bool,
+
+    // Lines not matching any rule with filter are not output if there is any of them
+    #[serde(default)]
+    pub filter: bool,
+
+    #[serde(default)]
+    pub max_matches_per_line: Option<usize>,
+
+    #[serde(default)]
+    pub max_matches_total: Option<usize>,
+
+    // The number of lines matched so far
+    #[serde(skip)]
+    pub matches: Cell<usize>,
+}
+
+// What is done to lines matched by the rule
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Color,
+    Drop,
+}
+
+// Flags of the pattern instead of writing like "(?i)" in it
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub struct Flags {
+    #[serde(default)]
+    pub ignore_case: bool,
+
+    // '.' matches newline
+    #[serde(default)]
+    pub dotall: bool,
+
+    // '^' and '$' match at the beginning and the end of each line
+    #[serde(default)]
+    pub multiline: bool,
+}
+
+// Which of the line rules matching the line is applied
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    // The first rule in the config, and the following rules if it has continue = true
+    #[default]
+    First,
+    // The rule with the highest priority, or the first one of them
+    Priority,
+    // The rule with the longest match, or the first one of them
+    Longest,
+    // All rules in the order of the config
+    All,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Token {
     #[serde(with = "regex_serde")]
     pub pat: Regex,
 
-    #[serde(with = "colors_serde")]
+    #[serde(flatten)]
+    pub flags: Flags,
+
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(default, with = "colors_serde")]
     pub colors: Vec<String>,
+
+    // The colors by the captured values for each group by the name or the index
+    #[serde(default)]
+    pub value_colors: ValueColors,
+
+    // Colorize all occurrences of the pattern instead of the first one
+    #[serde(default)]
+    pub global: bool,
+
+    // The named group of the line pattern where the token is matched
+    #[serde(default)]
+    pub scope: Option<String>,
+
+    #[serde(default, with = "gradient_serde")]
+    pub gradient: Option<Vec<Rgb>>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct OnEof {
+    pub exec: Option<String>,
+    pub print: Option<String>,
+}
+
+impl Config {
+    // Merge the other config with lower priority
+    pub fn merge(&mut self, other: Config) {
+        self.lines.extend(other.lines);
+        match (&mut self.stderr_lines, other.stderr_lines) {
+            (Some(x), Some(y)) => x.extend(y),
+            (x @ None, y) => *x = y,
+            _ => (),
+        }
+        for (k, v) in other.styles {
+            self.styles.entry(k).or_insert(v);
+        }
+        for (k, v) in other.aliases {
+            self.aliases.entry(k).or_insert(v);
+        }
+        if self.on_eof.is_none() {
+            self.on_eof = other.on_eof;
+        }
+        self.banner |= other.banner;
+        if self.dedupe_strip.is_none() {
+            self.dedupe_strip = other.dedupe_strip;
+        }
+        if self.tokenizer.is_none() {
+            self.tokenizer = other.tokenizer;
+        }
+        if self.record_start.is_none() {
+            self.record_start = other.record_start;
+        }
+        if self.timestamp.is_none() {
+            self.timestamp = other.timestamp;
+        }
+        if self.match_mode.is_none() {
+            self.match_mode = other.match_mode;
+        }
+        if self.markers.is_none() {
+            self.markers = other.markers;
+        }
+    }
+
+    // Expand "@name" references in colors to the color spec of the named style,
+    // and color aliases to the colors
+    pub fn resolve_styles(&mut self) -> Result<()> {
+        let stderr_lines = self.stderr_lines.iter_mut().flatten();
+        for line in self.lines.iter_mut().chain(stderr_lines) {
+            let colorless = line.line_color.is_none() && line.value_colors.is_empty();
+            if line.colors.is_empty() && colorless && line.action != Action::Drop {
+                bail!("no color of rule '{}'", line.pat);
+            }
+            resolve_colors(line.line_color.as_mut_slice(), &self.styles, &self.aliases)?;
+            for color in line.value_colors.values_mut().flat_map(|x| x.values_mut()) {
+                resolve_colors(std::slice::from_mut(color), &self.styles, &self.aliases)?;
+            }
+            apply_flags(&mut line.pat, line.flags, self.ignore_case)?;
+            expand_group_colors(&mut line.colors, &line.pat)?;
+            expand_value_colors(&mut line.value_colors, &line.pat)?;
+            let rule = line.label();
+            check_colors(&line.colors, &line.pat, &rule)?;
+            resolve_colors(&mut line.colors, &self.styles, &self.aliases)?;
+            for token in &mut line.tokens {
+                if let Some(ref name) = token.scope {
+                    if !line.pat.capture_names().any(|x| x == Some(name)) {
+                        bail!("no group named '{}' in rule '{}'", name, line.pat);
+                    }
+                }
+                let colorless = token.value_colors.is_empty() && token.gradient.is_none();
+                if token.colors.is_empty() && colorless {
+                    let label = token.name.as_deref().unwrap_or(token.pat.as_str());
+                    bail!("no color of token '{}' in rule '{}'", label, rule);
+                }
+                apply_flags(&mut token.pat, token.flags, self.ignore_case)?;
+                expand_group_colors(&mut token.colors, &token.pat)?;
+                expand_value_colors(&mut token.value_colors, &token.pat)?;
+                let label = token.name.as_deref().unwrap_or(token.pat.as_str());
+                check_colors(&token.colors, &token.pat, label)?;
+                resolve_colors(&mut token.colors, &self.styles, &self.aliases)?;
+                for color in token.value_colors.values_mut().flat_map(|x| x.values_mut()) {
+                    resolve_colors(std::slice::from_mut(color), &self.styles, &self.aliases)?;
+                }
+            }
+        }
+        resolve_colors(self.others.as_mut_slice(), &self.styles, &self.aliases)?;
+        for (pat, color) in &mut self.highlights {
+            apply_flags(pat, Flags::default(), self.ignore_case)?;
+            resolve_colors(std::slice::from_mut(color), &self.styles, &self.aliases)?;
+        }
+        self.build_line_set();
+        Ok(())
+    }
+
+    // Build the set of line patterns. It should be rebuilt after changing the line rules.
+    // The rules are tried one by one if the set is too large to build.
+    pub fn build_line_set(&mut self) {
+        self.line_set = RegexSet::new(self.lines.iter().map(|x| x.pat.as_str())).ok();
+    }
+
+    // Parse the config, and resolve named styles and color aliases
+    pub fn from_toml_str(s: &str) -> std::result::Result<Config, Error> {
+        let mut config: Config = toml::from_str(s).map_err(|e| Error::Config(e.to_string()))?;
+        config
+            .resolve_styles()
+            .map_err(|e| Error::Color(e.to_string()))?;
+        Ok(config)
+    }
+
+    // The config to colorize stderr of a wrapped command
+    pub fn stderr_config(&self) -> Config {
+        let mut ret = self.clone();
+        if let Some(x) = ret.stderr_lines.take() {
+            ret.lines = x;
+            ret.build_line_set();
+        }
+        ret
+    }
+}
+
+impl Line {
+    pub fn new(pat: Regex, colors: Vec<String>) -> Self {
+        Line {
+            pat,
+            flags: Flags::default(),
+            colors,
+            line_color: None,
+            value_colors: HashMap::new(),
+            action: Action::default(),
+            tokens: Vec::new(),
+            name: None,
+            tags: Vec::new(),
+            priority: 0,
+            continues: false,
+            global: false,
+            description: None,
+            field: None,
+            marker: None,
+            trigger: false,
+            filter: false,
+            max_matches_per_line: None,
+            max_matches_total: None,
+            matches: Cell::new(0),
+        }
+    }
+
+    // Parse the rule like "PATTERN:COLOR,COLOR..." given from the command line.
+    // The pattern is split at the last ':' because colors have no ':'.
+    pub fn parse(s: &str) -> Result<Self> {
+        let (pat, colors) = match s.rfind(':') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => bail!("failed to parse rule '{}': no color", s),
+        };
+        let pat = Regex::new(pat).map_err(|e| anyhow!(regex_serde::diagnose(pat, e)))?;
+        let colors: Vec<_> = colors.split(',').map(|x| x.trim().to_string()).collect();
+        if colors.iter().any(|x| x.is_empty()) {
+            bail!("failed to parse rule '{}': empty color", s);
+        }
+        Ok(Line::new(pat, colors))
+    }
+
+    // The name of the rule shown in reports
+    pub fn label(&self) -> String {
+        self.name
+            .clone()
+            .or_else(|| self.description.clone())
+            .unwrap_or_else(|| self.pat.as_str().to_string())
+    }
+
+    // Whether the rule reached max_matches_total
+    pub fn is_exhausted(&self) -> bool {
+        self.max_matches_total
+            .is_some_and(|x| self.matches.get() >= x)
+    }
+}
+
+impl StyleDef {
+    fn spec(&self) -> String {
+        let mut ret = self.fg.clone().unwrap_or_default();
+        if self.bold {
+            ret.push_str("+Bold");
+        }
+        if self.italic {
+            ret.push_str("+Italic");
+        }
+        if self.underline {
+            ret.push_str("+Underline");
+        }
+        ret
+    }
+}
+
+// Parse the highlight like "WORD=COLOR" given from the command line.
+// The word is matched literally, and split at the last '=' because colors have no '='.
+pub fn parse_highlight(s: &str) -> Result<(Regex, String)> {
+    match s.rfind('=') {
+        Some(i) if i > 0 && i + 1 < s.len() => {
+            Ok((Regex::new(&regex::escape(&s[..i]))?, s[i + 1..].to_string()))
+        }
+        _ => bail!("failed to parse highlight '{}'", s),
+    }
+}
+
+// Prepend the flags to the pattern. The pattern string keeps them for the set of line patterns.
+fn apply_flags(pat: &mut Regex, flags: Flags, ignore_case: bool) -> Result<()> {
+    let mut prefix = String::new();
+    if flags.ignore_case || ignore_case {
+        prefix.push('i');
+    }
+    if flags.dotall {
+        prefix.push('s');
+    }
+    if flags.multiline {
+        prefix.push('m');
+    }
+    if !prefix.is_empty() {
+        *pat = Regex::new(&format!("(?{}){}", prefix, pat.as_str()))?;
+    }
+    Ok(())
+}
+
+// Expand the colors of groups like "NAME=COLOR" to the colors in the order of the groups.
+// NAME may be the index of the group like "0" for the whole match.
+// Groups without color inherit the color of the enclosing group.
+fn expand_group_colors(colors: &mut Vec<String>, pat: &Regex) -> Result<()> {
+    if !colors.iter().any(|x| x.contains('=')) {
+        return Ok(());
+    }
+    let mut ret = vec![String::from("Inherit"); pat.captures_len()];
+    for x in colors.iter() {
+        let (name, color) = x.split_once('=').unwrap_or(("0", x));
+        ret[group_index(name, pat)?] = color.to_string();
+    }
+    *colors = ret;
+    Ok(())
+}
+
+// Replace the names of groups in value_colors by the indexes of the groups
+fn expand_value_colors(value_colors: &mut ValueColors, pat: &Regex) -> Result<()> {
+    let mut ret = HashMap::new();
+    for (name, colors) in value_colors.drain() {
+        ret.insert(group_index(&name, pat)?.to_string(), colors);
+    }
+    *value_colors = ret;
+    Ok(())
+}
+
+// The index of the group by the name, or the index itself like "0" for the whole match
+fn group_index(name: &str, pat: &Regex) -> Result<usize> {
+    match name.parse::<usize>() {
+        Ok(i) if i < pat.captures_len() => Ok(i),
+        _ => pat
+            .capture_names()
+            .position(|x| x == Some(name))
+            .ok_or_else(|| anyhow!("no group named '{}' in rule '{}'", name, pat)),
+    }
+}
+
+// Colors more than the groups are never used, and likely a mistake of the pattern.
+// Groups more than the colors inherit the color of the enclosing group.
+fn check_colors(colors: &[String], pat: &Regex, label: &str) -> Result<()> {
+    if colors.len() > pat.captures_len() {
+        let groups = match pat.captures_len() - 1 {
+            0 => String::new(),
+            1 => String::from("1 group and "),
+            n => format!("{} groups and ", n),
+        };
+        bail!(
+            "rule '{}' has {} colors, but the pattern has only {}the whole match",
+            label,
+            colors.len(),
+            groups
+        );
+    }
+    Ok(())
+}
+
+fn resolve_colors(
+    colors: &mut [String],
+    styles: &HashMap<String, StyleDef>,
+    aliases: &HashMap<String, String>,
+) -> Result<()> {
+    for color in colors {
+        if let Some(x) = color.strip_prefix('@') {
+            let (name, rest) = x.split_at(x.find('+').unwrap_or(x.len()));
+            let style = styles
+                .get(name)
+                .ok_or_else(|| anyhow!("failed to find style '@{}'", name))?;
+            *color = format!("{}{}", style.spec(), rest);
+        }
+        *color = expand_alias(color, aliases)?;
+    }
+    Ok(())
+}
+
+// Replace the color of the spec by the alias. The alias may refer to another alias.
+fn expand_alias(spec: &str, aliases: &HashMap<String, String>) -> Result<String> {
+    let mut ret = spec.to_string();
+    for _ in 0..=aliases.len() {
+        let (name, rest) = ret.split_at(ret.find('+').unwrap_or(ret.len()));
+        match aliases.get(name) {
+            Some(x) => ret = format!("{}{}", x, rest),
+            None => return Ok(ret),
+        }
+    }
+    bail!("failed to resolve circular color alias in '{}'", spec)
+}
+
+// Accept alternative spellings of color names used by other tools
+fn canonical_color(name: &str) -> String {
+    let name = name.replace("Grey", "Gray").replace("Purple", "Magenta");
+    let name = match name.strip_prefix("Bright") {
+        Some(x) => format!("Light{}", x),
+        None => name,
+    };
+    match name.as_ref() {
+        "Gray" | "DarkGray" => String::from("LightBlack"),
+        "LightGray" => String::from("White"),
+        _ => name,
+    }
 }
 
 mod regex_serde {
@@ -43,19 +584,106 @@ mod regex_serde {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        let r = Regex::new(&s).map_err(serde::de::Error::custom)?;
+        let r = Regex::new(&s).map_err(|e| serde::de::Error::custom(diagnose(&s, e)))?;
         Ok(r)
     }
+
+    static UNSUPPORTED: [(&str, &str, &str); 8] = [
+        (
+            "(?=",
+            "look-ahead",
+            "match the following text by a capture group",
+        ),
+        (
+            "(?!",
+            "negative look-ahead",
+            "use another rule for the excluded text",
+        ),
+        (
+            "(?<=",
+            "look-behind",
+            "match the preceding text by a capture group",
+        ),
+        (
+            "(?<!",
+            "negative look-behind",
+            "use another rule for the excluded text",
+        ),
+        ("(?>", "atomic group", "use a non-capturing group '(?:'"),
+        (
+            "\\k<",
+            "named backreference",
+            "repeat the referenced pattern",
+        ),
+        ("++", "possessive quantifier", "use a greedy quantifier '+'"),
+        ("*+", "possessive quantifier", "use a greedy quantifier '*'"),
+    ];
+
+    // Name the unsupported construct in the pattern instead of the raw error of regex crate
+    pub fn diagnose(pat: &str, err: regex::Error) -> String {
+        for (construct, name, hint) in &UNSUPPORTED {
+            if pat.contains(construct) {
+                return format!(
+                    "unsupported {} '{}' in pattern '{}': {}",
+                    name, construct, pat, hint
+                );
+            }
+        }
+        let mut chars = pat.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some(d) if d.is_ascii_digit() => {
+                        return format!(
+                            "unsupported backreference '\\{}' in pattern '{}': repeat the referenced pattern",
+                            d, pat
+                        );
+                    }
+                    _ => (),
+                }
+            }
+        }
+        err.to_string()
+    }
+}
+
+mod regex_opt_serde {
+    use regex::Regex;
+    use serde::{self, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let r = Regex::new(&s)
+            .map_err(|e| serde::de::Error::custom(super::regex_serde::diagnose(&s, e)))?;
+        Ok(Some(r))
+    }
 }
 
 mod colors_serde {
     use serde::{self, Deserialize, Deserializer};
+    use std::collections::BTreeMap;
 
+    // Colors in the order of groups, or the table of colors by the names of groups
+    #[derive(serde_derive::Deserialize)]
+    #[serde(untagged)]
+    enum Colors {
+        List(Vec<String>),
+        Groups(BTreeMap<String, String>),
+    }
+
+    // The colors of the table are kept like "NAME=COLOR" until the groups of the pattern are
+    // known by Config::resolve_styles
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let s = Vec::<String>::deserialize(deserializer)?;
+        let s = match Colors::deserialize(deserializer)? {
+            Colors::List(x) => x,
+            Colors::Groups(x) => x.into_iter().map(|(k, v)| format!("{}={}", k, v)).collect(),
+        };
         if s.is_empty() {
             Err(serde::de::Error::custom("no color"))
         } else {
@@ -64,110 +692,942 @@ mod colors_serde {
     }
 }
 
-// -------------------------------------------------------------------------------------------------
-// Functions
-// -------------------------------------------------------------------------------------------------
+mod gradient_serde {
+    use super::Rgb;
+    use serde::{self, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<Rgb>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = Vec::<String>::deserialize(deserializer)?;
+        if s.len() < 2 {
+            return Err(serde::de::Error::custom(
+                "gradient needs two colors at least",
+            ));
+        }
+        let mut ret = Vec::new();
+        for x in &s {
+            let rgb = super::parse_rgb(x).ok_or_else(|| {
+                serde::de::Error::custom(format!("failed to parse gradient color '{}'", x))
+            })?;
+            ret.push(rgb);
+        }
+        Ok(Some(ret))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Colorizer
+// -------------------------------------------------------------------------------------------------
+
+// Colorize lines by the rules of the config
+pub struct Colorizer {
+    config: Config,
+    // The partial line waiting for the rest fed later
+    pending: Vec<u8>,
+}
+
+// A colorized line with the index of the matched rule
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColoredLine {
+    pub text: String,
+    pub rule: Option<usize>,
+}
+
+impl Colorizer {
+    pub fn new(config: Config) -> Self {
+        Colorizer {
+            config,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Colorize the line. The line terminator is kept as is.
+    pub fn colorize_line(&self, line: &str) -> std::result::Result<String, Error> {
+        self.colorize_bytes(line.as_bytes()).map(|x| x.text)
+    }
+
+    // Return the styled spans of the line instead of escape sequences
+    pub fn spans(&self, line: &str) -> std::result::Result<Spans, Error> {
+        spans(line, &self.config).map_err(|e| Error::Color(e.to_string()))
+    }
+
+    // Push a chunk of any size, and return the lines completed by it.
+    // The partial line at the end is kept until the rest is fed.
+    pub fn feed(&mut self, buf: &[u8]) -> Feed<'_> {
+        self.pending.extend_from_slice(buf);
+        let end = memchr::memrchr(b'\n', &self.pending).map_or(0, |x| x + 1);
+        let lines = self.pending.drain(..end).collect();
+        Feed {
+            colorizer: self,
+            lines,
+            pos: 0,
+        }
+    }
+
+    // Return the partial line remaining at the end of input
+    pub fn finish(&mut self) -> Option<std::result::Result<ColoredLine, Error>> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let line = std::mem::take(&mut self.pending);
+        Some(self.colorize_bytes(&line))
+    }
+
+    fn colorize_bytes(&self, line: &[u8]) -> std::result::Result<ColoredLine, Error> {
+        let s = String::from_utf8_lossy(line);
+        let (text, rule) = colorize(&s, &self.config).map_err(|e| Error::Color(e.to_string()))?;
+        Ok(ColoredLine {
+            text: text.into_owned(),
+            rule,
+        })
+    }
+}
+
+// The lines completed by `Colorizer::feed`, colorized lazily
+pub struct Feed<'a> {
+    colorizer: &'a Colorizer,
+    lines: Vec<u8>,
+    pos: usize,
+}
+
+impl Iterator for Feed<'_> {
+    type Item = std::result::Result<ColoredLine, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.lines[self.pos..];
+        let len = memchr::memchr(b'\n', rest)? + 1;
+        self.pos += len;
+        Some(self.colorizer.colorize_bytes(&rest[..len]))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// The line matching no rule is returned as is without allocation
+pub fn colorize<'a>(s: &'a str, config: &Config) -> Result<(Cow<'a, str>, Option<usize>)> {
+    colorize_with_bg(s, config, None)
+}
+
+// Colorize with the background color spread over the whole line except the line terminator.
+pub fn colorize_with_bg<'a>(
+    s: &'a str,
+    config: &Config,
+    bg: Option<&str>,
+) -> Result<(Cow<'a, str>, Option<usize>)> {
+    let bg = bg.map(|x| expand_alias(x, &config.aliases)).transpose()?;
+    match spans_with_bg(s, config, bg.as_deref())? {
+        Some(spans) => {
+            let ret = render_ansi(s, &spans, bg.as_deref(), config.depth)?;
+            Ok((Cow::Owned(ret), spans.rule))
+        }
+        None => Ok((Cow::Borrowed(s), None)),
+    }
+}
+
+// Colorize the line including invalid UTF-8 bytes.
+// The rules are matched with invalid bytes replaced by U+FFFD, and the bytes are output as is.
+pub fn colorize_bytes<'a>(
+    buf: &'a [u8],
+    config: &Config,
+    bg: Option<&str>,
+) -> Result<(Cow<'a, [u8]>, Option<usize>)> {
+    if let Ok(s) = std::str::from_utf8(buf) {
+        let (ret, i) = colorize_with_bg(s, config, bg)?;
+        let ret = match ret {
+            Cow::Borrowed(x) => Cow::Borrowed(x.as_bytes()),
+            Cow::Owned(x) => Cow::Owned(x.into_bytes()),
+        };
+        return Ok((ret, i));
+    }
+
+    // The position in the bytes of each position in the string, and the end of them
+    let mut s = String::new();
+    let mut pos = Vec::new();
+    let mut p = 0;
+    for chunk in buf.utf8_chunks() {
+        s.push_str(chunk.valid());
+        pos.extend(p..p + chunk.valid().len());
+        p += chunk.valid().len();
+        if !chunk.invalid().is_empty() {
+            s.push(char::REPLACEMENT_CHARACTER);
+            pos.extend([p; 3]);
+            p += chunk.invalid().len();
+        }
+    }
+    pos.push(p);
+
+    let bg = bg.map(|x| expand_alias(x, &config.aliases)).transpose()?;
+    match spans_with_bg(&s, config, bg.as_deref())? {
+        Some(spans) => {
+            let ret = render_ansi_bytes(buf, &spans, bg.as_deref(), config.depth, |x| pos[x])?;
+            Ok((Cow::Owned(ret), spans.rule))
+        }
+        None => Ok((Cow::Borrowed(buf), None)),
+    }
+}
+
+// Styled spans of a line matched by the rules
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spans {
+    // Consecutive spans covering the whole line
+    pub spans: Vec<(Range<usize>, Style)>,
+    // Text inserted before the span of the index like markers
+    pub inserts: Vec<(usize, String)>,
+    // The index of the matched line rule
+    pub rule: Option<usize>,
+}
+
+pub fn spans(s: &str, config: &Config) -> Result<Spans> {
+    let ret = spans_with_bg(s, config, None)?.unwrap_or_else(|| Spans {
+        spans: vec![(0..s.len(), Style::default())],
+        inserts: Vec::new(),
+        rule: None,
+    });
+    Ok(ret)
+}
+
+// The index of the line rule matching the line without counting the match
+pub fn match_rule(s: &str, config: &Config) -> Option<usize> {
+    find_rules(s, config).first().map(|(i, _, _)| *i)
+}
+
+// Return the index of the line rules applied to the line by match_mode, the range of the
+// matched field, and the captures in the field
+fn find_rules<'a>(s: &'a str, config: &Config) -> Vec<(usize, Range<usize>, Captures<'a>)> {
+    let mode = config.match_mode.unwrap_or_default();
+    let mut ret = Vec::new();
+    let fields = config.tokenizer.map(|x| x.tokenize(s));
+
+    // The set is ignored if it is stale.
+    // Most lines match no pattern, so check it first without collecting the matches.
+    let set = config
+        .line_set
+        .as_ref()
+        .filter(|x| x.len() == config.lines.len());
+    let candidates = set.filter(|x| x.is_match(s)).map(|x| x.matches(s));
+
+    for (i, line) in config.lines.iter().enumerate() {
+        if line.is_exhausted() {
+            continue;
+        }
+        let candidate = candidates.as_ref().map_or(set.is_none(), |x| x.matched(i));
+        if line.field.is_none() && !candidate {
+            continue;
+        }
+
+        // A rule with field matches the field only
+        let range = match line.field {
+            Some(ref name) => fields.as_ref().and_then(|x| get_field(x, name)),
+            None => Some(0..s.len()),
+        };
+        let cap = range.clone().and_then(|x| line.pat.captures(&s[x]));
+        if let (Some(range), Some(cap)) = (range, cap) {
+            ret.push((i, range, cap));
+            if mode == MatchMode::First && !line.continues {
+                break;
+            }
+        }
+    }
+
+    // min_by_key returns the first one of the equal elements
+    let best = match mode {
+        MatchMode::Priority => ret
+            .into_iter()
+            .min_by_key(|(i, _, _)| Reverse(config.lines[*i].priority)),
+        MatchMode::Longest => ret
+            .into_iter()
+            .min_by_key(|(_, _, cap)| Reverse(cap.get(0).unwrap().len())),
+        MatchMode::First | MatchMode::All => return ret,
+    };
+    best.into_iter().collect()
+}
+
+// Return None if the line has nothing to style
+fn spans_with_bg(s: &str, config: &Config, bg: Option<&str>) -> Result<Option<Spans>> {
+    // The ranges colored in the order drawn, and the markers inserted at the positions
+    let mut layers: Vec<(Range<usize>, String)> = Vec::new();
+    let mut markers = Vec::new();
+    let mut line_idx = None;
+
+    for (i, range, cap) in find_rules(s, config) {
+        let line = &config.lines[i];
+        line_idx = line_idx.or(Some(i));
+        line.matches.set(line.matches.get() + 1);
+
+        if let Some(ref color) = line.line_color {
+            let end = s.trim_end_matches(&['\r', '\n'][..]).len();
+            layers.push((0..end, color.clone()));
+        }
+
+        // A global rule colorizes all occurrences in the line or the field
+        let offset = range.start;
+        let caps: Vec<_> = if line.global {
+            line.pat.captures_iter(&s[range]).collect()
+        } else {
+            vec![cap]
+        };
+        for cap in &caps {
+            if config.show_markers {
+                let mat = cap.get(0).unwrap();
+                let (open, close) = line
+                    .marker
+                    .clone()
+                    .or_else(|| config.markers.clone())
+                    .unwrap_or_else(default_markers);
+                markers.push((offset + mat.start(), true, open));
+                markers.push((offset + mat.end(), false, close));
+            }
+            for (j, mat) in cap.iter().enumerate() {
+                // Rules dropping lines may have no color
+                let mat = mat.map(|x| (x, group_color(&line.colors, &line.value_colors, j, &x)));
+                if let Some((mat, Some(color))) = mat {
+                    layers.push((offset + mat.start()..offset + mat.end(), color));
+                }
+            }
+        }
+        // The continuation lines of the record have the color of the whole match
+        if config.record_start.is_some() {
+            let end = s.trim_end_matches(&['\r', '\n'][..]).len();
+            if let (Some(p), Some(color)) = (s[..end].find('\n'), line.colors.first()) {
+                layers.push((p + 1..end, color.clone()));
+            }
+        }
+        let max_tokens = line.max_matches_per_line.unwrap_or(usize::MAX);
+        let mut tokens = 0;
+        for token in &line.tokens {
+            // A token with scope matches only in the group of the line pattern
+            let ranges: Vec<_> = match token.scope {
+                Some(ref name) => caps
+                    .iter()
+                    .filter_map(|x| x.name(name))
+                    .map(|x| (offset + x.start(), x.as_str()))
+                    .collect(),
+                None => vec![(0, s)],
+            };
+            let mut token_caps = Vec::new();
+            for (start, hay) in ranges {
+                if token.global {
+                    token_caps.extend(token.pat.captures_iter(hay).map(|x| (start, x)));
+                } else {
+                    token_caps.extend(token.pat.captures(hay).map(|x| (start, x)));
+                }
+            }
+            for (base, cap) in token_caps {
+                if tokens >= max_tokens {
+                    break;
+                }
+                tokens += 1;
+                if let Some(gradient) = &token.gradient {
+                    let mat = cap.get(0).unwrap();
+                    let chars: Vec<_> = mat.as_str().char_indices().collect();
+                    for (k, (p, c)) in chars.iter().enumerate() {
+                        let color = gradient_color(gradient, k, chars.len());
+                        let start = base + mat.start() + p;
+                        layers.push((start..start + c.len_utf8(), color));
+                    }
+                } else {
+                    for (j, mat) in cap.iter().enumerate() {
+                        let mat = mat
+                            .map(|x| (x, group_color(&token.colors, &token.value_colors, j, &x)));
+                        if let Some((mat, Some(color))) = mat {
+                            layers.push((base + mat.start()..base + mat.end(), color));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Lines matched by no rule are drawn under highlights
+    if let (None, Some(color)) = (line_idx, &config.others) {
+        let end = s.trim_end_matches(&['\r', '\n'][..]).len();
+        layers.push((0..end, color.clone()));
+    }
+
+    for (pat, color) in &config.highlights {
+        for mat in pat.find_iter(s) {
+            layers.push((mat.range(), color.clone()));
+        }
+    }
+
+    if line_idx.is_none() && bg.is_none() && layers.is_empty() && markers.is_empty() {
+        return Ok(None);
+    }
+
+    // Split the line at all boundaries of the layers and the markers.
+    // Each span has the style applying the colors of the layers covering it in the drawn order,
+    // so tokens are drawn over groups even if they overlap, and the enclosing style is restored
+    // at the end of each layer.
+    let base = Style {
+        bg: bg.map(String::from),
+        ..Default::default()
+    };
+    let mut bounds: Vec<_> = layers
+        .iter()
+        .flat_map(|(x, _)| [x.start, x.end])
+        .chain(markers.iter().map(|x| x.0))
+        .chain([0, s.len()])
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    // The layers covering each span are tracked by sweeping the bounds, so a long gradient with a
+    // layer per character doesn't scan all layers at each span
+    let mut starts: Vec<_> = layers
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.0.start, i))
+        .collect();
+    let mut ends: Vec<_> = layers
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.0.end, i))
+        .collect();
+    starts.sort_unstable();
+    ends.sort_unstable();
+    let mut starts = starts.into_iter().peekable();
+    let mut ends = ends.into_iter().peekable();
+    let mut active = BTreeSet::new();
+    let mut spans: Vec<(Range<usize>, Style)> = Vec::new();
+    for w in bounds.windows(2) {
+        while let Some((_, i)) = ends.next_if(|x| x.0 <= w[0]) {
+            active.remove(&i);
+        }
+        while let Some((_, i)) = starts.next_if(|x| x.0 <= w[0]) {
+            // Empty layers cover no span
+            if layers[i].0.end > w[0] {
+                active.insert(i);
+            }
+        }
+        let mut style = base.clone();
+        for i in &active {
+            style = style.apply(&layers[*i].1)?;
+        }
+        // Spans of the same style are joined unless a marker is inserted between them
+        match spans.last_mut() {
+            Some(last) if last.1 == style && markers.iter().all(|x| x.0 != w[0]) => {
+                last.0.end = w[1];
+            }
+            _ => spans.push((w[0]..w[1], style)),
+        }
+    }
+    if spans.is_empty() {
+        spans.push((0..s.len(), base));
+    }
+
+    // Markers at the same position are closed before opened, and nested in the drawn order
+    let mut markers: Vec<_> = markers.into_iter().enumerate().collect();
+    markers.sort_by_key(|(i, (p, open, _))| {
+        (*p, *open, if *open { *i as isize } else { -(*i as isize) })
+    });
+    let inserts = markers
+        .into_iter()
+        .map(|(_, (p, _, text))| {
+            let i = spans.iter().position(|x| x.0.start >= p && !x.0.is_empty());
+            (i.unwrap_or(spans.len()), text)
+        })
+        .collect();
+
+    Ok(Some(Spans {
+        spans,
+        inserts,
+        rule: line_idx,
+    }))
+}
+
+// The color of the group by the captured value, or by the index of the group.
+// Groups more than the colors have no color to inherit the color of the enclosing group.
+fn group_color(
+    colors: &[String],
+    value_colors: &ValueColors,
+    j: usize,
+    mat: &regex::Match,
+) -> Option<String> {
+    let by_value = if value_colors.is_empty() {
+        None
+    } else {
+        value_colors
+            .get(&j.to_string())
+            .and_then(|x| x.get(mat.as_str()))
+    };
+    let color = by_value.or_else(|| colors.get(j))?;
+    Some(hash_color(color, mat.as_str()))
+}
+
+// Colors distinguishable on both dark and light backgrounds
+const HASH_PALETTE: [&str; 10] = [
+    "Red",
+    "Green",
+    "Yellow",
+    "Blue",
+    "Magenta",
+    "Cyan",
+    "LightRed",
+    "LightGreen",
+    "LightBlue",
+    "LightMagenta",
+];
+
+// Replace the pseudo-color "hash" by the color of the palette selected by the hash of the value,
+// so the same values like thread IDs always have the same color
+fn hash_color(spec: &str, value: &str) -> String {
+    let (name, rest) = spec.split_at(spec.find('+').unwrap_or(spec.len()));
+    if !name.eq_ignore_ascii_case("hash") {
+        return spec.to_string();
+    }
+    // FNV-1a is stable across runs and platforms unlike the hasher of std
+    let hash = value.bytes().fold(0xcbf29ce484222325u64, |h, x| {
+        (h ^ x as u64).wrapping_mul(0x100000001b3)
+    });
+    let color = HASH_PALETTE[(hash % HASH_PALETTE.len() as u64) as usize];
+    format!("{}{}", color, rest)
+}
+
+// Render the spans as ANSI escape sequences.
+// The background color is spread over the whole line except the line terminator.
+pub fn render_ansi(s: &str, spans: &Spans, bg: Option<&str>, depth: ColorDepth) -> Result<String> {
+    let ret = render_ansi_bytes(s.as_bytes(), spans, bg, depth, |x| x)?;
+    // The spans split the line at char boundaries
+    Ok(String::from_utf8(ret)?)
+}
+
+// Render the spans over the bytes. `pos` maps positions of the spans to positions of the bytes.
+fn render_ansi_bytes(
+    s: &[u8],
+    spans: &Spans,
+    bg: Option<&str>,
+    depth: ColorDepth,
+    pos: impl Fn(usize) -> usize,
+) -> Result<Vec<u8>> {
+    let mut ret = Vec::new();
+    let mut body = s;
+    let mut eol = Vec::new();
+    if let Some(bg) = bg {
+        let len = s.len()
+            - s.iter()
+                .rev()
+                .take_while(|x| **x == b'\r' || **x == b'\n')
+                .count();
+        body = &s[..len];
+        if depth != ColorDepth::Dumb {
+            ret.extend_from_slice(conv_color(&Some(&String::from(bg)), depth)?.bg().as_bytes());
+            eol.extend_from_slice(Color::Reset.bg().as_bytes());
+        }
+        eol.extend_from_slice(&s[len..]);
+    }
+
+    // The escape sequences are output only where the style changes, and the line ends with the
+    // base style
+    let base = Style {
+        bg: bg.map(String::from),
+        ..Default::default()
+    };
+    let mut inserts = spans.inserts.iter().peekable();
+    let mut prev = &base;
+    for (i, (range, style)) in spans.spans.iter().enumerate() {
+        while let Some((_, text)) = inserts.next_if(|x| x.0 <= i) {
+            ret.extend_from_slice(text.as_bytes());
+        }
+        if style != prev {
+            ret.extend_from_slice(prev.transition(style, depth)?.as_bytes());
+        }
+        let end = pos(range.end).min(body.len());
+        ret.extend_from_slice(&body[pos(range.start).min(end)..end]);
+        prev = style;
+    }
+    for (_, text) in inserts {
+        ret.extend_from_slice(text.as_bytes());
+    }
+    if *prev != base {
+        ret.extend_from_slice(prev.transition(&base, depth)?.as_bytes());
+    }
+
+    ret.extend_from_slice(&eol);
+    Ok(ret)
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Attrs {
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+// The style of a span. Colors are the names like "Red", "#ff8000" or "Default".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Style {
+    pub color: String,
+    pub bg: Option<String>,
+    pub attrs: Attrs,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            color: String::from("Default"),
+            bg: None,
+            attrs: Attrs::default(),
+        }
+    }
+}
+
+impl Style {
+    // The style of the color spec over the default style
+    pub fn from_spec(spec: &str) -> Result<Style> {
+        Style::default().apply(spec)
+    }
+
+    // A color spec is an optional color followed by '+'-separated attributes.
+    // The color may be absolute ("Red"), relative to the enclosing style
+    // ("Darker", "Lighter", "Inherit") or omitted to inherit it ("+Bold").
+    fn apply(&self, spec: &str) -> Result<Style> {
+        let mut parts = spec.split('+');
+        let mut ret = self.clone();
+        ret.color = match parts.next().unwrap_or("") {
+            "" | "Inherit" => self.color.clone(),
+            "Darker" => self
+                .color
+                .strip_prefix("Light")
+                .unwrap_or(&self.color)
+                .to_string(),
+            "Lighter" if self.color.starts_with("Light") || self.color == "Default" => {
+                self.color.clone()
+            }
+            "Lighter" => canonical_color(&format!("Light{}", self.color)),
+            x => canonical_color(x),
+        };
+        for attr in parts {
+            match attr.to_ascii_lowercase().as_ref() {
+                "bold" => ret.attrs.bold = true,
+                "dim" => ret.attrs.dim = true,
+                "italic" => ret.attrs.italic = true,
+                "underline" => ret.attrs.underline = true,
+                _ => bail!(format!("failed to parse style name '{}'", attr)),
+            }
+        }
+        Ok(ret)
+    }
+
+    fn transition(&self, next: &Style, depth: ColorDepth) -> Result<String> {
+        if depth == ColorDepth::Dumb {
+            return Ok(String::new());
+        }
+        let mut ret = String::new();
+        if self.attrs != next.attrs {
+            ret.push_str(SGR_RESET);
+            if next.attrs.bold {
+                ret.push_str(SGR_BOLD);
+            }
+            if next.attrs.dim {
+                ret.push_str(SGR_DIM);
+            }
+            if next.attrs.italic {
+                ret.push_str(SGR_ITALIC);
+            }
+            if next.attrs.underline {
+                ret.push_str(SGR_UNDERLINE);
+            }
+        }
+        if self.attrs != next.attrs || self.bg != next.bg {
+            if let Some(ref bg) = next.bg {
+                ret.push_str(&conv_color(&Some(bg), depth)?.bg());
+            } else if self.attrs == next.attrs {
+                ret.push_str(&Color::Reset.bg());
+            }
+        }
+        ret.push_str(&conv_color(&Some(&next.color), depth)?.fg());
+        Ok(ret)
+    }
+}
+
+// Colorize the whole string by the color spec
+pub fn paint(s: &str, color: &str, depth: ColorDepth) -> Result<String> {
+    paint_with_bg(s, color, None, depth)
+}
+
+// Colorize the whole string by the color spec over the background color
+pub fn paint_with_bg(s: &str, color: &str, bg: Option<&str>, depth: ColorDepth) -> Result<String> {
+    let base = Style::default();
+    let mut style = base.apply(color)?;
+    style.bg = bg.map(String::from);
+    Ok(format!(
+        "{}{}{}",
+        base.transition(&style, depth)?,
+        s,
+        style.transition(&base, depth)?
+    ))
+}
+
+pub fn has_sgr(s: &str) -> bool {
+    sgr_spans(s.as_bytes()).next().is_some()
+}
+
+pub fn strip_sgr(s: &str) -> String {
+    let mut ret = String::new();
+    let mut idx = 0;
+    for (start, end) in sgr_spans(s.as_bytes()) {
+        ret.push_str(&s[idx..start]);
+        idx = end;
+    }
+    ret.push_str(&s[idx..]);
+    ret
+}
+
+// Strip color sequences from bytes which may not be valid UTF-8
+pub fn strip_sgr_bytes(s: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::new();
+    let mut idx = 0;
+    for (start, end) in sgr_spans(s) {
+        ret.extend_from_slice(&s[idx..start]);
+        idx = end;
+    }
+    ret.extend_from_slice(&s[idx..]);
+    ret
+}
+
+// Cut the string to the width of visible characters keeping color sequences before the cut.
+// The number of the hidden bytes is returned with it, or None if the string is not longer.
+pub fn truncate_sgr(s: &str, width: usize) -> Option<(String, usize)> {
+    let mut ret = String::new();
+    let mut idx = 0;
+    let mut chars = 0;
+    let mut spans = sgr_spans(s.as_bytes()).peekable();
+    while idx < s.len() {
+        if let Some(&(start, end)) = spans.peek() {
+            if start == idx {
+                ret.push_str(&s[start..end]);
+                idx = end;
+                spans.next();
+                continue;
+            }
+        }
+        let c = s[idx..].chars().next().unwrap();
+        if chars == width {
+            let hidden = strip_sgr(&s[idx..]).len();
+            return Some((ret, hidden));
+        }
+        ret.push(c);
+        idx += c.len_utf8();
+        chars += 1;
+    }
+    None
+}
+
+// The byte ranges of color sequences. They are ASCII, so the ranges are on char boundaries of str.
+fn sgr_spans(s: &[u8]) -> impl Iterator<Item = (usize, usize)> + '_ {
+    (0..s.len())
+        .filter(move |&i| s[i..].starts_with(b"\x1b["))
+        .filter_map(move |start| {
+            let body = &s[start + 2..];
+            let len = body
+                .iter()
+                .position(|c| !(c.is_ascii_digit() || *c == b';'))
+                .filter(|&i| body[i] == b'm')?;
+            Some((start, start + 2 + len + 1))
+        })
+}
+
+fn gradient_color(gradient: &[Rgb], i: usize, len: usize) -> String {
+    let t = if len > 1 {
+        i as f64 / (len - 1) as f64
+    } else {
+        0.0
+    };
+    let t = t * (gradient.len() - 1) as f64;
+    let k = (t.floor() as usize).min(gradient.len() - 2);
+    let t = t - k as f64;
+    let (r0, g0, b0) = gradient[k];
+    let (r1, g1, b1) = gradient[k + 1];
+    let mix = |x: u8, y: u8| (x as f64 + (y as f64 - x as f64) * t).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", mix(r0, r1), mix(g0, g1), mix(b0, b1))
+}
+
+fn parse_rgb(s: &str) -> Option<Rgb> {
+    let s = s.strip_prefix('#')?;
+    if s.len() != 6 || !s.is_ascii() {
+        return None;
+    }
+    let r = u8::from_str_radix(&s[0..2], 16).ok()?;
+    let g = u8::from_str_radix(&s[2..4], 16).ok()?;
+    let b = u8::from_str_radix(&s[4..6], 16).ok()?;
+    Some((r, g, b))
+}
+
+fn conv_color(s: &Option<&String>, depth: ColorDepth) -> Result<Color> {
+    let ret = if let &Some(ref s) = s {
+        if let Some(rgb) = parse_rgb(s) {
+            return Ok(match depth {
+                ColorDepth::TrueColor => Color::Rgb(rgb),
+                ColorDepth::Ansi256 => Color::Ansi256(nearest_ansi256(rgb)),
+                _ => Color::Ansi16(nearest_ansi16(rgb)),
+            });
+        }
+        let idx = match canonical_color(s).as_ref() {
+            "Black" => 0,
+            "Blue" => 4,
+            "Cyan" => 6,
+            "Default" => return Ok(Color::Reset),
+            "Green" => 2,
+            "LightBlack" => 8,
+            "LightBlue" => 12,
+            "LightCyan" => 14,
+            "LightGreen" => 10,
+            "LightMagenta" => 13,
+            "LightRed" => 9,
+            "LightWhite" => 15,
+            "LightYellow" => 11,
+            "Magenta" => 5,
+            "Red" => 1,
+            "White" => 7,
+            "Yellow" => 3,
+            _ => {
+                bail!(format!("failed to parse color name '{}'", s));
+            }
+        };
+        match depth {
+            ColorDepth::Dumb | ColorDepth::Ansi16 => Color::Ansi16(idx),
+            _ => Color::Ansi256(idx),
+        }
+    } else {
+        Color::Reset
+    };
+    Ok(ret)
+}
+
+// The terminal color of the color name like "Red" or "#ff8000" at the depth
+pub fn term_color(s: &str, depth: ColorDepth) -> Result<Color> {
+    conv_color(&Some(&s.to_string()), depth)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Escape sequence
+// -------------------------------------------------------------------------------------------------
+
+const SGR_RESET: &str = "\u{1b}[m";
+const SGR_BOLD: &str = "\u{1b}[1m";
+const SGR_DIM: &str = "\u{1b}[2m";
+const SGR_ITALIC: &str = "\u{1b}[3m";
+const SGR_UNDERLINE: &str = "\u{1b}[4m";
+
+// The color emitted to terminals, which is also used by renderers other than render_ansi
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Reset,
+    // The basic 16 colors emitted by SGR 30-37 and 90-97 instead of the 256 color form
+    Ansi16(u8),
+    Ansi256(u8),
+    Rgb(Rgb),
+}
+
+impl Color {
+    fn fg(&self) -> String {
+        match self {
+            Color::Reset => String::from("\u{1b}[39m"),
+            Color::Ansi16(x) if *x < 8 => format!("\u{1b}[{}m", 30 + x),
+            Color::Ansi16(x) => format!("\u{1b}[{}m", 82 + x),
+            Color::Ansi256(x) => format!("\u{1b}[38;5;{}m", x),
+            Color::Rgb((r, g, b)) => format!("\u{1b}[38;2;{};{};{}m", r, g, b),
+        }
+    }
+
+    fn bg(&self) -> String {
+        match self {
+            Color::Reset => String::from("\u{1b}[49m"),
+            Color::Ansi16(x) if *x < 8 => format!("\u{1b}[{}m", 40 + x),
+            Color::Ansi16(x) => format!("\u{1b}[{}m", 92 + x),
+            Color::Ansi256(x) => format!("\u{1b}[48;5;{}m", x),
+            Color::Rgb((r, g, b)) => format!("\u{1b}[48;2;{};{};{}m", r, g, b),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Color degradation
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorDepth {
+    Dumb,
+    Ansi16,
+    #[default]
+    Ansi256,
+    TrueColor,
+}
+
+pub fn detect_color_depth() -> ColorDepth {
+    if let Ok(x) = std::env::var("COLORTERM") {
+        if x == "truecolor" || x == "24bit" {
+            return ColorDepth::TrueColor;
+        }
+    }
+    match std::env::var("TERM") {
+        Ok(x) if x == "dumb" => ColorDepth::Dumb,
+        Ok(x) if x.contains("256color") => ColorDepth::Ansi256,
+        Ok(_) => ColorDepth::Ansi16,
+        Err(_) => ColorDepth::Ansi256,
+    }
+}
+
+static ANSI16_PALETTE: [Rgb; 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
 
-pub fn colorize(mut s: String, config: &Config) -> Result<(String, Option<usize>)> {
-    #[derive(Debug)]
-    enum PosType {
-        Start,
-        End,
-    }
+static CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
 
-    let mut pos = Vec::new();
-    let mut line_idx = None;
+fn distance(x: Rgb, y: Rgb) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(x.0, y.0) + d(x.1, y.1) + d(x.2, y.2)
+}
 
-    for (i, line) in config.lines.iter().enumerate() {
-        let cap = line.pat.captures(&s);
-        if let Some(cap) = cap {
-            line_idx = Some(i);
-            for (j, mat) in cap.iter().enumerate() {
-                if let Some(mat) = mat {
-                    let color = line
-                        .colors
-                        .get(j)
-                        .unwrap_or_else(|| line.colors.last().unwrap());
-                    pos.push((PosType::Start, mat.start(), color.clone()));
-                    pos.push((PosType::End, mat.end(), color.clone()));
-                }
-            }
-            for token in &line.tokens {
-                let cap = token.pat.captures(&s);
-                if let Some(cap) = cap {
-                    for (j, mat) in cap.iter().enumerate() {
-                        if let Some(mat) = mat {
-                            let color = token
-                                .colors
-                                .get(j)
-                                .unwrap_or_else(|| token.colors.last().unwrap());
-                            pos.push((PosType::Start, mat.start(), color.clone()));
-                            pos.insert(0, (PosType::End, mat.end(), color.clone()));
-                        }
-                    }
-                }
-            }
-            break;
-        }
-    }
+fn nearest_ansi16(rgb: Rgb) -> u8 {
+    (0..16)
+        .min_by_key(|&i| distance(rgb, ANSI16_PALETTE[i]))
+        .unwrap() as u8
+}
 
-    pos.sort_by_key(|&(_, p, _)| p);
+fn nearest_ansi256(rgb: Rgb) -> u8 {
+    let level = |x: u8| {
+        (0..6)
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - x as i32).abs())
+            .unwrap()
+    };
+    let (r, g, b) = (level(rgb.0), level(rgb.1), level(rgb.2));
+    let cube = (CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
 
-    let mut current_color = vec![String::from("Default")];
-    let mut ret = String::new();
-    let mut idx = 0;
-    for (t, p, color) in pos {
-        match t {
-            PosType::Start => {
-                current_color.push(color);
-            }
-            PosType::End => {
-                current_color.pop();
-            }
-        }
-        let rest = s.split_off(p - idx);
+    let avg = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let gray_idx = (avg.saturating_sub(3) / 10).min(23) as u8;
+    let gray_level = 8 + 10 * gray_idx;
+    let gray = (gray_level, gray_level, gray_level);
 
-        ret.push_str(&format!(
-            "{}{}",
-            s,
-            color::Fg(&*conv_color(&current_color.last())?)
-        ));
-        idx += s.len();
-        s = rest;
-    }
-
-    ret.push_str(&s);
-    Ok((ret, line_idx))
-}
-
-fn conv_color(s: &Option<&String>) -> Result<Box<dyn Color>> {
-    let ret: Box<dyn Color> = if let &Some(ref s) = s {
-        match s.as_ref() {
-            "Black" => Box::new(color::Black),
-            "Blue" => Box::new(color::Blue),
-            "Cyan" => Box::new(color::Cyan),
-            "Default" => Box::new(color::Reset),
-            "Green" => Box::new(color::Green),
-            "LightBlack" => Box::new(color::LightBlack),
-            "LightBlue" => Box::new(color::LightBlue),
-            "LightCyan" => Box::new(color::LightCyan),
-            "LightGreen" => Box::new(color::LightGreen),
-            "LightMagenta" => Box::new(color::LightMagenta),
-            "LightRed" => Box::new(color::LightRed),
-            "LightWhite" => Box::new(color::LightWhite),
-            "LightYellow" => Box::new(color::LightYellow),
-            "Magenta" => Box::new(color::Magenta),
-            "Red" => Box::new(color::Red),
-            "White" => Box::new(color::White),
-            "Yellow" => Box::new(color::Yellow),
-            _ => {
-                bail!(format!("failed to parse color name '{}'", s));
-            }
-        }
+    if distance(rgb, gray) < distance(rgb, cube) {
+        232 + gray_idx
     } else {
-        Box::new(color::Reset)
-    };
-    Ok(ret)
+        16 + 36 * r as u8 + 6 * g as u8 + b as u8
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -216,23 +1676,23 @@ mod tests {
     #[test]
     fn test_colorize() {
         let config: Config = toml::from_str(TEST_CONFIG).unwrap();
-        let (ret, idx) = colorize(String::from("A123 456 789 xyz"), &config).unwrap();
-        assert_eq!(ret, "\u{1b}[38;5;0m\u{1b}[38;5;2mA\u{1b}[38;5;0m\u{1b}[38;5;4m123\u{1b}[38;5;0m \u{1b}[38;5;6m456\u{1b}[38;5;0m \u{1b}[39m789\u{1b}[38;5;0m xyz\u{1b}[39m");
+        let (ret, idx) = colorize("A123 456 789 xyz", &config).unwrap();
+        assert_eq!(ret, "\u{1b}[38;5;2mA\u{1b}[38;5;4m123\u{1b}[38;5;0m \u{1b}[38;5;6m456\u{1b}[38;5;0m \u{1b}[39m789\u{1b}[38;5;0m xyz\u{1b}[39m");
         assert_eq!(idx, Some(0));
 
-        let (ret, idx) = colorize(String::from("B123 456 789 xyz"), &config).unwrap();
+        let (ret, idx) = colorize("B123 456 789 xyz", &config).unwrap();
         assert_eq!(ret, "\u{1b}[38;5;8mB\u{1b}[38;5;12m123\u{1b}[38;5;8m \u{1b}[38;5;14m456\u{1b}[38;5;8m \u{1b}[38;5;10m789\u{1b}[38;5;8m xyz\u{1b}[39m");
         assert_eq!(idx, Some(1));
 
-        let (ret, idx) = colorize(String::from("C123 456 789 xyz"), &config).unwrap();
+        let (ret, idx) = colorize("C123 456 789 xyz", &config).unwrap();
         assert_eq!(ret, "\u{1b}[38;5;13mC\u{1b}[38;5;9m123\u{1b}[38;5;13m \u{1b}[38;5;15m456\u{1b}[38;5;13m \u{1b}[38;5;11m789\u{1b}[38;5;13m xyz\u{1b}[39m");
         assert_eq!(idx, Some(2));
 
-        let (ret, idx) = colorize(String::from("D123 456 789 xyz"), &config).unwrap();
+        let (ret, idx) = colorize("D123 456 789 xyz", &config).unwrap();
         assert_eq!(ret, "\u{1b}[38;5;5mD\u{1b}[38;5;1m123\u{1b}[38;5;5m \u{1b}[38;5;7m456\u{1b}[38;5;5m \u{1b}[38;5;3m789\u{1b}[38;5;5m xyz\u{1b}[39m");
         assert_eq!(idx, Some(3));
 
-        let (ret, idx) = colorize(String::from("E123 456 789 xyz"), &config).unwrap();
+        let (ret, idx) = colorize("E123 456 789 xyz", &config).unwrap();
         assert_eq!(ret, "E123 456 789 xyz");
         assert_eq!(idx, None);
     }
@@ -240,16 +1700,934 @@ mod tests {
     #[test]
     fn test_colorize_fail() {
         let config: Config = toml::from_str(TEST_CONFIG2).unwrap();
-        let ret = colorize(String::from("A123 456 789 xyz"), &config);
+        let ret = colorize("A123 456 789 xyz", &config);
         assert_eq!(
             &format!("{:?}", ret)[0..37],
             "Err(failed to parse color name \'xxx\')"
         );
     }
 
+    pub static TEST_CONFIG4: &'static str = r#"
+    [[lines]]
+        pat   = "A(.*) .*"
+        colors = ["LightRed", "+Bold"]
+        [[lines.tokens]]
+            pat   = "x"
+            colors = ["Darker"]
+    "#;
+
+    #[test]
+    fn test_relative_style() {
+        let config: Config = toml::from_str(TEST_CONFIG4).unwrap();
+        let (ret, _) = colorize("A12 xyz", &config).unwrap();
+        assert_eq!(ret, "\u{1b}[38;5;9mA\u{1b}[m\u{1b}[1m\u{1b}[38;5;9m12\u{1b}[m\u{1b}[38;5;9m \u{1b}[38;5;1mx\u{1b}[38;5;9myz\u{1b}[39m");
+    }
+
+    pub static TEST_CONFIG5: &'static str = r##"
+    [[lines]]
+        pat   = ".*"
+        colors = ["Default"]
+        [[lines.tokens]]
+            pat   = "#+"
+            gradient = ["#00ff00", "#ff0000"]
+    "##;
+
+    #[test]
+    fn test_gradient() {
+        let mut config: Config = toml::from_str(TEST_CONFIG5).unwrap();
+        config.depth = ColorDepth::TrueColor;
+        let (ret, _) = colorize("[###]", &config).unwrap();
+        assert_eq!(
+            ret,
+            "[\u{1b}[38;2;0;255;0m#\u{1b}[38;2;128;128;0m#\u{1b}[38;2;255;0;0m#\u{1b}[39m]"
+        );
+
+        config.depth = ColorDepth::Ansi256;
+        let (ret, _) = colorize("[#]", &config).unwrap();
+        assert_eq!(ret, "[\u{1b}[38;5;46m#\u{1b}[39m]");
+
+        // A long gradient has a layer per character, and characters of the same color are joined
+        let line = format!("[{}]", "#".repeat(100_000));
+        let ret = spans(&line, &config).unwrap();
+        assert_eq!(ret.spans.len(), 256 + 2);
+        assert_eq!(ret.spans[1].1, Style::from_spec("#00ff00").unwrap());
+        assert_eq!(ret.spans[256].1, Style::from_spec("#ff0000").unwrap());
+        assert_eq!(ret.spans[257], (100_001..100_002, Style::default()));
+    }
+
+    #[test]
+    fn test_degradation() {
+        let mut config: Config = toml::from_str(TEST_CONFIG4).unwrap();
+        config.depth = ColorDepth::Ansi16;
+        let (ret, _) = colorize("A1 x", &config).unwrap();
+        assert_eq!(
+            ret,
+            "\u{1b}[91mA\u{1b}[m\u{1b}[1m\u{1b}[91m1\u{1b}[m\u{1b}[91m \u{1b}[31mx\u{1b}[39m"
+        );
+
+        config.depth = ColorDepth::Dumb;
+        let (ret, _) = colorize("A1 x", &config).unwrap();
+        assert_eq!(ret, "A1 x");
+
+        assert_eq!(nearest_ansi16((250, 10, 10)), 9);
+        assert_eq!(nearest_ansi256((0, 255, 0)), 46);
+        assert_eq!(nearest_ansi256((128, 128, 128)), 244);
+    }
+
+    #[test]
+    fn test_colorize_with_bg() {
+        let config: Config = toml::from_str(TEST_CONFIG4).unwrap();
+        let (ret, _) = colorize_with_bg("E1\n", &config, Some("Blue")).unwrap();
+        assert_eq!(ret, "\u{1b}[48;5;4mE1\u{1b}[49m\n");
+
+        let (ret, _) = colorize_with_bg("A1 x\n", &config, Some("Blue")).unwrap();
+        assert_eq!(ret, "\u{1b}[48;5;4m\u{1b}[38;5;9mA\u{1b}[m\u{1b}[1m\u{1b}[48;5;4m\u{1b}[38;5;9m1\u{1b}[m\u{1b}[48;5;4m\u{1b}[38;5;9m \u{1b}[38;5;1mx\u{1b}[39m\u{1b}[49m\n");
+    }
+
+    pub static TEST_CONFIG6: &'static str = r#"
+    [styles]
+        error  = { fg = "Red", bold = true }
+        accent = { underline = true }
+    [[lines]]
+        pat   = "A(.*) .*"
+        colors = ["@error", "@accent"]
+        [[lines.tokens]]
+            pat   = "x"
+            colors = ["@error+Italic"]
+    "#;
+
+    #[test]
+    fn test_stderr_config() {
+        let config = r#"
+        [styles]
+            error = { fg = "Red" }
+        [[lines]]
+            pat   = "Info"
+            colors = ["Green"]
+        [[stderr_lines]]
+            pat   = "Error"
+            colors = ["@error"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+        let stderr_config = config.stderr_config();
+        assert_eq!(config.lines[0].pat.as_str(), "Info");
+        assert_eq!(stderr_config.lines[0].pat.as_str(), "Error");
+        assert_eq!(stderr_config.lines[0].colors, vec!["Red"]);
+
+        let config: Config = toml::from_str(TEST_CONFIG).unwrap();
+        assert_eq!(config.stderr_config().lines.len(), config.lines.len());
+    }
+
+    #[test]
+    fn test_named_style() {
+        let mut config: Config = toml::from_str(TEST_CONFIG6).unwrap();
+        config.resolve_styles().unwrap();
+        assert_eq!(config.lines[0].colors, vec!["Red+Bold", "+Underline"]);
+        assert_eq!(config.lines[0].tokens[0].colors, vec!["Red+Bold+Italic"]);
+
+        config.lines[0].colors[0] = String::from("@warning");
+        let ret = config.resolve_styles();
+        assert_eq!(
+            format!("{}", ret.unwrap_err()),
+            "failed to find style '@warning'"
+        );
+    }
+
+    #[test]
+    fn test_alias() {
+        let config = r#"
+        [aliases]
+            Danger = "LightRed"
+            Alert  = "Danger+Bold"
+            Loop   = "Loop"
+        [[lines]]
+            pat   = "(Error)"
+            colors = ["Default", "Alert+Underline"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+        assert_eq!(
+            config.lines[0].colors,
+            vec!["Default", "LightRed+Bold+Underline"]
+        );
+
+        let (s, _) = colorize_with_bg("a", &config, Some("Danger")).unwrap();
+        assert_eq!(s, "\u{1b}[48;5;9ma\u{1b}[49m");
+
+        config.lines[0].colors[0] = String::from("Loop");
+        let ret = config.resolve_styles();
+        assert_eq!(
+            format!("{}", ret.unwrap_err()),
+            "failed to resolve circular color alias in 'Loop'"
+        );
+    }
+
+    #[test]
+    fn test_alternative_color_names() {
+        for (x, y) in [
+            ("Grey", "LightBlack"),
+            ("DarkGray", "LightBlack"),
+            ("LightGrey", "White"),
+            ("Purple", "Magenta"),
+            ("BrightPurple", "LightMagenta"),
+            ("BrightRed", "LightRed"),
+        ] {
+            assert_eq!(
+                paint("a", x, ColorDepth::Ansi256).unwrap(),
+                paint("a", y, ColorDepth::Ansi256).unwrap()
+            );
+        }
+        assert_eq!(
+            paint("a", "Purple+Bold", ColorDepth::Ansi16).unwrap(),
+            paint("a", "Magenta+Bold", ColorDepth::Ansi16).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_paint() {
+        let ret = paint("[a.log]", "Cyan+Bold", ColorDepth::Ansi256).unwrap();
+        assert_eq!(
+            ret,
+            "\u{1b}[m\u{1b}[1m\u{1b}[38;5;6m[a.log]\u{1b}[m\u{1b}[39m"
+        );
+        let ret = paint("[a.log]", "Cyan", ColorDepth::Dumb).unwrap();
+        assert_eq!(ret, "[a.log]");
+        let ret = paint_with_bg("[1 more]", "Yellow", Some("Blue"), ColorDepth::Ansi16).unwrap();
+        assert_eq!(ret, "\u{1b}[44m\u{1b}[33m[1 more]\u{1b}[49m\u{1b}[39m");
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let config = r#"
+        [[lines]]
+            pat   = "Error(?= .*)"
+            colors = ["Red"]
+        "#;
+        let ret = toml::from_str::<Config>(config);
+        assert!(format!("{}", ret.err().unwrap()).contains(
+            "unsupported look-ahead '(?=' in pattern 'Error(?= .*)': match the following text by a capture group"
+        ));
+
+        let config = r#"
+        [[lines]]
+            pat   = '(\w+) \1'
+            colors = ["Red"]
+        "#;
+        let ret = toml::from_str::<Config>(config);
+        assert!(format!("{}", ret.err().unwrap())
+            .contains("unsupported backreference '\\1' in pattern '(\\w+) \\1'"));
+    }
+
+    #[test]
+    fn test_sgr() {
+        let s = "\u{1b}[38;5;1mError\u{1b}[39m: [x] failed\n";
+        assert!(has_sgr(s));
+        assert!(!has_sgr("Error: [x] failed\n"));
+        assert_eq!(strip_sgr(s), "Error: [x] failed\n");
+        assert_eq!(
+            strip_sgr_bytes(b"\x1b[31m\xffError\x1b[0m\xfe\n"),
+            b"\xffError\xfe\n"
+        );
+    }
+
+    #[test]
+    fn test_truncate_sgr() {
+        let s = "\u{1b}[38;5;1mError\u{1b}[39m: [x] failed";
+        assert_eq!(
+            truncate_sgr(s, 3),
+            Some((String::from("\u{1b}[38;5;1mErr"), 14))
+        );
+        assert_eq!(
+            truncate_sgr(s, 7),
+            Some((String::from("\u{1b}[38;5;1mError\u{1b}[39m: "), 10))
+        );
+        assert_eq!(truncate_sgr(s, 20), None);
+        assert_eq!(truncate_sgr("αβγ", 2), Some((String::from("αβ"), 2)));
+    }
+
     #[test]
     fn test_omit_token() {
         let config = toml::from_str::<Config>(TEST_CONFIG3);
         assert!(config.is_ok());
     }
+
+    #[test]
+    fn test_field() {
+        let config = r#"
+        tokenizer = "logfmt"
+        [[lines]]
+            pat   = "^5"
+            field = "kv.status"
+            colors = ["Red"]
+        "#;
+        let config: Config = toml::from_str(config).unwrap();
+        let s = String::from("status=500 msg=500\n");
+        let (ret, i) = colorize(&s, &config).unwrap();
+        assert_eq!(i, Some(0));
+        assert_eq!(ret, "status=\u{1b}[38;5;1m5\u{1b}[39m00 msg=500\n");
+
+        let s = String::from("msg=500 status=200\n");
+        let (_, i) = colorize(&s, &config).unwrap();
+        assert_eq!(i, None);
+    }
+
+    #[test]
+    fn test_record_start() {
+        let config = r#"
+        record_start = '^\S'
+        [[lines]]
+            pat    = "^Exception.*"
+            colors = ["Red"]
+            tokens = [{ pat = "B.java", colors = ["Cyan"] }]
+        "#;
+        let config: Config = toml::from_str(config).unwrap();
+        let s = "Exception: x\n  at A.java\n  at B.java\n";
+        let (ret, i) = colorize(s, &config).unwrap();
+        assert_eq!(i, Some(0));
+        assert_eq!(
+            ret,
+            "\u{1b}[38;5;1mException: x\u{1b}[39m\n\u{1b}[38;5;1m  at A.java\n  at \
+             \u{1b}[38;5;6mB.java\u{1b}[39m\n"
+        );
+    }
+
+    #[test]
+    fn test_match_rule() {
+        let config = r#"
+        [[lines]]
+            pat    = "a"
+            colors = ["Red"]
+        [[lines]]
+            pat    = "b"
+            colors = ["Blue"]
+            max_matches_total = 1
+        "#;
+        let config: Config = toml::from_str(config).unwrap();
+        assert_eq!(match_rule("ab", &config), Some(0));
+        assert_eq!(match_rule("b", &config), Some(1));
+        assert_eq!(match_rule("c", &config), None);
+        // The match is not counted
+        assert_eq!(match_rule("b", &config), Some(1));
+        assert_eq!(config.lines[1].matches.get(), 0);
+    }
+
+    #[test]
+    fn test_action() {
+        let config = r#"
+        [[lines]]
+            pat    = "health"
+            action = "drop"
+        [[lines]]
+            pat    = "a"
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        assert_eq!(config.lines[0].action, Action::Drop);
+        assert_eq!(config.lines[1].action, Action::Color);
+        assert!(config.resolve_styles().is_err());
+
+        config.lines.pop();
+        config.resolve_styles().unwrap();
+        let (ret, i) = colorize("GET /health", &config).unwrap();
+        assert_eq!(ret, "GET /health");
+        assert_eq!(i, Some(0));
+    }
+
+    #[test]
+    fn test_highlight() {
+        let config = r#"
+        [[lines]]
+            pat    = "^Error.*"
+            colors = ["Red"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.highlights.push(parse_highlight("a.b=Cyan").unwrap());
+        config.resolve_styles().unwrap();
+        assert!(parse_highlight("=Cyan").is_err());
+        assert!(parse_highlight("a.b").is_err());
+
+        // All occurrences are highlighted literally with or without the line rule
+        let (ret, i) = colorize("a.b axb a.b", &config).unwrap();
+        assert_eq!(i, None);
+        assert_eq!(
+            ret,
+            "\u{1b}[38;5;6ma.b\u{1b}[39m axb \u{1b}[38;5;6ma.b\u{1b}[39m"
+        );
+        let (ret, i) = colorize("Error a.b", &config).unwrap();
+        assert_eq!(i, Some(0));
+        assert_eq!(ret, "\u{1b}[38;5;1mError \u{1b}[38;5;6ma.b\u{1b}[39m");
+    }
+
+    #[test]
+    fn test_match_mode() {
+        let config = r#"
+        [[lines]]
+            pat    = "a"
+            colors = ["Red"]
+        [[lines]]
+            pat    = "b+"
+            colors = ["Green"]
+            priority = 1
+        [[lines]]
+            pat    = "c"
+            colors = ["Blue"]
+            priority = 1
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+        let rule = |config: &Config, mode| {
+            let mut config = config.clone();
+            config.match_mode = mode;
+            match_rule("a bb c", &config)
+        };
+        assert_eq!(rule(&config, None), Some(0));
+        assert_eq!(rule(&config, Some(MatchMode::Priority)), Some(1));
+        assert_eq!(rule(&config, Some(MatchMode::Longest)), Some(1));
+        assert_eq!(rule(&config, Some(MatchMode::All)), Some(0));
+
+        // All matching rules are applied, and the matches are counted
+        config.match_mode = Some(MatchMode::All);
+        let (ret, i) = colorize("a bb", &config).unwrap();
+        assert_eq!(i, Some(0));
+        assert_eq!(ret, "\u{1b}[38;5;1ma\u{1b}[39m \u{1b}[38;5;2mbb\u{1b}[39m");
+        assert_eq!(config.lines[1].matches.get(), 1);
+        assert_eq!(config.lines[2].matches.get(), 0);
+    }
+
+    #[test]
+    fn test_continue() {
+        let config = r#"
+        [[lines]]
+            pat      = "^\\d\\d:\\d\\d"
+            colors   = ["Blue"]
+            continue = true
+        [[lines]]
+            pat    = "error"
+            colors = ["Red"]
+        [[lines]]
+            pat    = "e"
+            colors = ["Green"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+
+        // The rules are applied until the rule without continue
+        let (ret, i) = colorize("12:34 error", &config).unwrap();
+        assert_eq!(i, Some(0));
+        assert_eq!(
+            ret,
+            "\u{1b}[38;5;4m12:34\u{1b}[39m \u{1b}[38;5;1merror\u{1b}[39m"
+        );
+        assert_eq!(config.lines[1].matches.get(), 1);
+        assert_eq!(config.lines[2].matches.get(), 0);
+
+        // The later rule is drawn over the earlier one
+        config.lines[0].pat = Regex::new(".*").unwrap();
+        config.build_line_set();
+        let (ret, _) = colorize("an error", &config).unwrap();
+        assert_eq!(ret, "\u{1b}[38;5;4man \u{1b}[38;5;1merror\u{1b}[39m");
+        assert_eq!(match_rule("12:34", &config), Some(0));
+    }
+
+    #[test]
+    fn test_global() {
+        let config = r#"
+        [[lines]]
+            pat    = "from"
+            colors = ["Default"]
+            [[lines.tokens]]
+                pat    = "\\d+\\.\\d+"
+                colors = ["Cyan"]
+                global = true
+        [[lines]]
+            pat    = "id=(\\d+)"
+            colors = ["Default", "Yellow"]
+            global = true
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+
+        let (ret, _) = colorize("from 1.2 to 3.4", &config).unwrap();
+        assert_eq!(
+            ret,
+            "from \u{1b}[38;5;6m1.2\u{1b}[39m to \u{1b}[38;5;6m3.4\u{1b}[39m"
+        );
+        let (ret, _) = colorize("id=1 id=2", &config).unwrap();
+        assert_eq!(
+            ret,
+            "id=\u{1b}[38;5;3m1\u{1b}[39m id=\u{1b}[38;5;3m2\u{1b}[39m"
+        );
+        assert_eq!(config.lines[1].matches.get(), 1);
+
+        // The limit counts all occurrences
+        config.lines[0].max_matches_per_line = Some(1);
+        let (ret, _) = colorize("from 1.2 to 3.4", &config).unwrap();
+        assert_eq!(ret, "from \u{1b}[38;5;6m1.2\u{1b}[39m to 3.4");
+    }
+
+    #[test]
+    fn test_group_colors() {
+        let config = r#"
+        [[lines]]
+            pat    = "^(?P<time>\\S+) (?P<level>\\S+) (?P<msg>.*)"
+            colors = { time = "Cyan", level = "Red" }
+            [[lines.tokens]]
+                pat    = "id=(?P<id>\\d+)"
+                colors = { 0 = "Yellow", id = "Blue" }
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+        assert_eq!(
+            config.lines[0].colors,
+            ["Inherit", "Cyan", "Red", "Inherit"]
+        );
+        assert_eq!(config.lines[0].tokens[0].colors, ["Yellow", "Blue"]);
+
+        let config = r#"
+        [[lines]]
+            pat    = "^(?P<time>\\S+)"
+            colors = { level = "Red" }
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        assert!(config.resolve_styles().is_err());
+    }
+
+    #[test]
+    fn test_check_colors() {
+        let text = r#"
+        [[lines]]
+            pat    = "(a)(b)"
+            colors = ["Red", "Green", "Blue", "Cyan"]
+            name   = "ab"
+        "#;
+        let mut config: Config = toml::from_str(text).unwrap();
+        let err = config.resolve_styles().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rule 'ab' has 4 colors, but the pattern has only 2 groups and the whole match"
+        );
+
+        let mut one: Config = toml::from_str(&text.replace("(a)(b)", "(a)b")).unwrap();
+        let err = one.resolve_styles().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rule 'ab' has 4 colors, but the pattern has only 1 group and the whole match"
+        );
+        let mut none: Config = toml::from_str(&text.replace("(a)(b)", "ab")).unwrap();
+        let err = none.resolve_styles().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "rule 'ab' has 4 colors, but the pattern has only the whole match"
+        );
+
+        // The groups without color inherit the enclosing color
+        config.lines[0].colors.truncate(2);
+        config.resolve_styles().unwrap();
+        let (ret, _) = colorize("ab", &config).unwrap();
+        assert_eq!(ret, "\u{1b}[38;5;2ma\u{1b}[38;5;1mb\u{1b}[39m");
+    }
+
+    #[test]
+    fn test_token_without_color() {
+        let config = r#"
+        [[lines]]
+            pat    = "error"
+            colors = ["Red"]
+            name   = "error"
+            [[lines.tokens]]
+                pat  = "\\d+"
+                name = "number"
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        let err = config.resolve_styles().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no color of token 'number' in rule 'error'"
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        let config = r#"
+        [[lines]]
+            pat         = "^error"
+            colors      = ["Red"]
+            ignore_case = true
+            multiline   = true
+            [[lines.tokens]]
+                pat    = "a.b"
+                colors = ["Cyan"]
+                dotall = true
+        [[lines]]
+            pat    = "warn"
+            colors = ["Yellow"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+        assert_eq!(config.lines[0].pat.as_str(), "(?im)^error");
+        assert_eq!(match_rule("x\nERROR", &config), Some(0));
+        assert!(config.lines[0].tokens[0].pat.is_match("a\nb"));
+        assert_eq!(match_rule("WARN", &config), None);
+
+        // The global flag makes all patterns case-insensitive
+        let config = r#"
+        [[lines]]
+            pat    = "warn"
+            colors = ["Yellow"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.ignore_case = true;
+        config.highlights.push(parse_highlight("id=Cyan").unwrap());
+        config.resolve_styles().unwrap();
+        assert_eq!(match_rule("WARN", &config), Some(0));
+        assert!(config.highlights[0].0.is_match("ID"));
+    }
+
+    #[test]
+    fn test_scope() {
+        let config = r#"
+        [[lines]]
+            pat    = "^(?P<time>\\S+) (?P<msg>.*)"
+            colors = ["Default"]
+            [[lines.tokens]]
+                pat    = "\\d+"
+                colors = ["Cyan"]
+                scope  = "msg"
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+        let spans = spans("12:00 code 3", &config).unwrap();
+        let cyan: Vec<_> = spans
+            .spans
+            .iter()
+            .filter(|(_, x)| x.color == "Cyan")
+            .map(|(x, _)| (x.start, x.end))
+            .collect();
+        assert_eq!(cyan, [(11, 12)]);
+
+        config.lines[0].tokens[0].scope = Some(String::from("level"));
+        assert!(config.resolve_styles().is_err());
+    }
+
+    #[test]
+    fn test_overlap() {
+        let config = r#"
+        [[lines]]
+            pat    = "x(ab)c"
+            colors = ["Default", "LightRed"]
+            [[lines.tokens]]
+                pat    = "bc"
+                colors = ["Darker"]
+            [[lines.tokens]]
+                pat    = "xa"
+                colors = ["Blue"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+
+        // The later layer is drawn over the earlier one, and the enclosing style is restored
+        // at the end of each layer regardless of the order of the ends
+        let colors = |s: &str| {
+            let spans = spans(s, &config).unwrap();
+            let mut ret = Vec::new();
+            for (range, style) in &spans.spans {
+                ret.extend(range.clone().map(|_| style.color.clone()));
+            }
+            ret
+        };
+        assert_eq!(
+            colors("xabcd"),
+            ["Blue", "Blue", "Red", "Default", "Default"]
+        );
+        let spans = spans("xabcd", &config).unwrap();
+        let ranges: Vec<_> = spans.spans.iter().map(|x| (x.0.start, x.0.end)).collect();
+        assert_eq!(ranges, [(0, 2), (2, 3), (3, 5)]);
+    }
+
+    #[test]
+    fn test_line_color() {
+        let config = r#"
+        [aliases]
+        dim = "Red"
+        [[lines]]
+            pat        = "error"
+            line_color = "dim"
+            [[lines.tokens]]
+                pat    = "\\d+"
+                colors = ["LightYellow"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+        let (ret, _) = colorize("disk error 28\n", &config).unwrap();
+        assert_eq!(
+            ret,
+            "\u{1b}[38;5;1mdisk error \u{1b}[38;5;11m28\u{1b}[39m\n"
+        );
+    }
+
+    #[test]
+    fn test_others() {
+        let config = r#"
+        [[lines]]
+            pat    = "error"
+            colors = ["Red"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.others = Some(String::from("LightBlack"));
+        config
+            .highlights
+            .push(parse_highlight("disk=Cyan").unwrap());
+        config.resolve_styles().unwrap();
+        let (ret, i) = colorize("disk full\n", &config).unwrap();
+        assert_eq!(i, None);
+        assert_eq!(ret, "\u{1b}[38;5;6mdisk\u{1b}[38;5;8m full\u{1b}[39m\n");
+
+        // The faint attribute keeps the color of the terminal
+        config.others = Some(String::from("+Dim"));
+        let (ret, _) = colorize("disk full\n", &config).unwrap();
+        assert_eq!(
+            ret,
+            "\u{1b}[m\u{1b}[2m\u{1b}[38;5;6mdisk\u{1b}[39m full\u{1b}[m\u{1b}[39m\n"
+        );
+        let (ret, _) = colorize("error\n", &config).unwrap();
+        assert_eq!(ret, "\u{1b}[38;5;1merror\u{1b}[39m\n");
+    }
+
+    #[test]
+    fn test_value_colors() {
+        let config = r#"
+        [[lines]]
+            pat          = "^(?P<method>\\S+) (\\S+)"
+            colors       = ["Default"]
+            value_colors = { method = { GET = "Green", DELETE = "Red" }, 2 = { GET = "Blue" } }
+            [[lines.tokens]]
+                pat          = "\\d{3}$"
+                value_colors = { 0 = { "200" = "Cyan" } }
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+        let (ret, _) = colorize("GET /a 200", &config).unwrap();
+        assert_eq!(
+            ret,
+            "\u{1b}[38;5;2mGET\u{1b}[39m /a \u{1b}[38;5;6m200\u{1b}[39m"
+        );
+        let (ret, _) = colorize("DELETE /a 404", &config).unwrap();
+        assert_eq!(ret, "\u{1b}[38;5;1mDELETE\u{1b}[39m /a 404");
+        let (ret, _) = colorize("PUT /a 404", &config).unwrap();
+        assert_eq!(ret, "PUT /a 404");
+
+        // The table of each group is applied to the group only
+        let (ret, _) = colorize("PUT GET 404", &config).unwrap();
+        assert_eq!(ret, "PUT \u{1b}[38;5;4mGET\u{1b}[39m 404");
+
+        let mut config: Config = toml::from_str(
+            r#"
+            [[lines]]
+                pat          = "(a)"
+                colors       = ["Red"]
+                value_colors = { b = { a = "Blue" } }
+            "#,
+        )
+        .unwrap();
+        let err = config.resolve_styles().unwrap_err();
+        assert_eq!(err.to_string(), "no group named 'b' in rule '(a)'");
+    }
+
+    #[test]
+    fn test_hash_color() {
+        let config = r#"
+        [[lines]]
+            pat    = "\\[(\\S+)\\]"
+            colors = ["Default", "hash+Bold"]
+        "#;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_styles().unwrap();
+        let color = |s: &str| {
+            let spans = spans(s, &config).unwrap();
+            let style = spans
+                .spans
+                .iter()
+                .find(|x| &s[x.0.clone()] == "t1")
+                .unwrap();
+            assert!(style.1.attrs.bold);
+            style.1.color.clone()
+        };
+        assert_eq!(color("[t1] a"), color("b [t1]"));
+        assert!(HASH_PALETTE.contains(&color("[t1] a").as_str()));
+        assert_eq!(hash_color("Red", "t1"), "Red");
+        assert_ne!(hash_color("hash", "t1"), hash_color("hash", "t2"));
+    }
+
+    #[test]
+    fn test_multibyte() {
+        let config = r##"
+        tokenizer = "logfmt"
+        [[lines]]
+            pat    = "^(エラー)(.)"
+            colors = ["Red", "Blue", "Green"]
+            tokens = [
+                { pat = "[0-9]+件", colors = ["Yellow"] },
+                { pat = "😀+", gradient = ["#ff0000", "#0000ff"] },
+                { pat = "", colors = ["Cyan"] },
+            ]
+        [[lines]]
+            pat    = "^.$"
+            field  = "kv.user"
+            colors = ["Magenta"]
+        "##;
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.depth = ColorDepth::Ansi16;
+        config.show_markers = true;
+
+        let (ret, i) = colorize("エラー: 3件 😀😀", &config).unwrap();
+        assert_eq!(i, Some(0));
+        assert_eq!(
+            strip_sgr(&ret),
+            "»エラー:« 3件 😀😀",
+            "markers are inserted at char boundaries"
+        );
+        assert!(ret.contains("\u{1b}[33m3件"));
+
+        let (ret, i) = colorize("user=山 msg=\"未終了🍣", &config).unwrap();
+        assert_eq!(i, Some(1));
+        assert_eq!(strip_sgr(&ret), "user=»山« msg=\"未終了🍣");
+
+        let (ret, _) = colorize_with_bg("エラー🍣 😀\r\n", &config, Some("Blue")).unwrap();
+        assert!(ret.starts_with("\u{1b}[44m"));
+        assert!(ret.ends_with("\u{1b}[49m\r\n"));
+
+        for line in &[
+            "エラー",
+            "エラー😀",
+            "😀エラー",
+            "ログ",
+            "user=",
+            "user=\"🍣",
+        ] {
+            let spans = spans(line, &config).unwrap();
+            for (range, _) in &spans.spans {
+                assert!(line.is_char_boundary(range.start) && line.is_char_boundary(range.end));
+            }
+            assert!(colorize(line, &config).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_colorize_bytes() {
+        let config = r#"
+        [[lines]]
+            pat   = "(Error) .*(x)"
+            colors = ["Red", "Blue", "Green"]
+            tokens = [{ pat = "d.", colors = ["Yellow"] }]
+        "#;
+        let config: Config = toml::from_str(config).unwrap();
+        let buf = b"Error \xff\xe3\x81 d\xfe x\n";
+        let lossy = String::from_utf8_lossy(buf);
+        for bg in &[None, Some("Blue")] {
+            let (ret, i) = colorize_bytes(buf, &config, *bg).unwrap();
+            let (expected, _) = colorize_with_bg(&lossy, &config, *bg).unwrap();
+            assert_eq!(i, Some(0));
+            assert_eq!(String::from_utf8_lossy(&ret), expected);
+            assert!(ret.windows(3).any(|x| x == b"\xff\xe3\x81"));
+            assert!(ret.windows(2).any(|x| x == b"d\xfe"));
+        }
+
+        let (ret, i) = colorize_bytes(b"Info \xff\n", &config, None).unwrap();
+        assert_eq!(i, None);
+        assert!(matches!(ret, Cow::Borrowed(b"Info \xff\n")));
+    }
+
+    #[test]
+    fn test_line_set() {
+        let mut config = String::from("tokenizer = \"logfmt\"\n");
+        for i in 0..200 {
+            config.push_str(&format!(
+                "[[lines]]\npat = \"^id={}( .*)\"\ncolors = [\"Red\", \"Blue\"]\n",
+                i
+            ));
+        }
+        config.push_str("[[lines]]\npat = \"^x\"\nfield = \"kv.name\"\ncolors = [\"Green\"]\n");
+        let mut config: Config = toml::from_str(&config).unwrap();
+        let inputs = [
+            "id=150 a\n",
+            "id=1500\n",
+            "id=7 name=xyz\n",
+            "id=x name=xyz\n",
+        ];
+        let expected: Vec<_> = inputs
+            .iter()
+            .map(|x| colorize(x, &config).unwrap())
+            .collect();
+
+        config.resolve_styles().unwrap();
+        assert!(config.line_set.is_some());
+        for (x, y) in inputs.iter().zip(&expected) {
+            assert_eq!(&colorize(x, &config).unwrap(), y);
+        }
+        assert_eq!(expected[0].1, Some(150));
+        assert_eq!(expected[1].1, None);
+        assert_eq!(expected[2].1, Some(7));
+        assert_eq!(expected[3].1, Some(200));
+        assert!(matches!(expected[1].0, Cow::Borrowed(_)));
+        assert!(matches!(
+            colorize(inputs[1], &config).unwrap().0,
+            Cow::Borrowed(_)
+        ));
+
+        // A stale set is not used
+        config.lines.remove(0);
+        assert_eq!(colorize(inputs[0], &config).unwrap().1, Some(149));
+    }
+
+    #[test]
+    fn test_colorizer() {
+        let config = r#"
+        [styles]
+        err = { fg = "Red", bold = true }
+        [[lines]]
+            pat   = "(Error).*"
+            colors = ["@err"]
+        "#;
+        let colorizer = Colorizer::new(Config::from_toml_str(config).unwrap());
+        assert_eq!(
+            colorizer.colorize_line("Error\n").unwrap(),
+            "\u{1b}[m\u{1b}[1m\u{1b}[38;5;1mError\u{1b}[m\u{1b}[39m\n"
+        );
+        assert_eq!(colorizer.colorize_line("Info").unwrap(), "Info");
+
+        let mut colorizer = colorizer;
+        let lines: Vec<_> = colorizer.feed(b"Info\nErr").collect();
+        assert_eq!(
+            lines,
+            vec![Ok(ColoredLine {
+                text: String::from("Info\n"),
+                rule: None
+            })]
+        );
+        assert_eq!(colorizer.feed(b"or").count(), 0);
+        let lines: Vec<_> = colorizer.feed(b"\n\nx").map(|x| x.unwrap().rule).collect();
+        assert_eq!(lines, vec![Some(0), None]);
+        assert_eq!(colorizer.finish().unwrap().unwrap().text, "x");
+        assert!(colorizer.finish().is_none());
+
+        let spans = colorizer.spans("Error: x").unwrap();
+        assert_eq!(spans.rule, Some(0));
+        let ranges: Vec<_> = spans.spans.iter().map(|x| x.0.clone()).collect();
+        assert_eq!(ranges, vec![0..8]);
+        assert_eq!(spans.spans[0].1.color, "Red");
+        assert!(spans.spans[0].1.attrs.bold);
+        assert!(colorizer.spans("Info").unwrap().rule.is_none());
+
+        assert!(matches!(
+            Config::from_toml_str("[[lines]]"),
+            Err(Error::Config(_))
+        ));
+        assert_eq!(
+            Config::from_toml_str(TEST_CONFIG2)
+                .and_then(|x| Colorizer::new(x).colorize_line("A1 2 3 4")),
+            Err(Error::Color(String::from(
+                "failed to parse color name 'xxx'"
+            )))
+        );
+    }
 }
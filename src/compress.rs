@@ -0,0 +1,110 @@
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{Read, Result, Seek, SeekFrom};
+use xz2::read::XzDecoder;
+
+// -------------------------------------------------------------------------------------------------
+// Compression
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+static MAGICS: [(&[u8], Compression); 3] = [
+    (&[0x1f, 0x8b], Compression::Gzip),
+    (&[0xfd, b'7', b'z', b'X', b'Z', 0x00], Compression::Xz),
+    (&[0x28, 0xb5, 0x2f, 0xfd], Compression::Zstd),
+];
+
+// Detect the compression format by the magic bytes, and rewind the file
+pub fn detect(file: &mut File) -> Result<Option<Compression>> {
+    let mut head = Vec::new();
+    file.by_ref().take(6).read_to_end(&mut head)?;
+    file.seek(SeekFrom::Start(0))?;
+    // The magic of bzip2 is followed by the block size from '1' to '9',
+    // so plain text starting with "BZh" is not detected
+    if let [b'B', b'Z', b'h', b'1'..=b'9', ..] = head[..] {
+        return Ok(Some(Compression::Bzip2));
+    }
+    Ok(MAGICS
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+        .map(|&(_, x)| x))
+}
+
+pub fn decoder(file: File, compression: Compression) -> Result<Box<dyn Read + Send>> {
+    Ok(match compression {
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(file)),
+        Compression::Xz => Box::new(XzDecoder::new_multi_decoder(file)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn roundtrip(name: &str, data: &[u8], expected: Compression) {
+        let path = std::env::temp_dir().join(format!("pipecolor_{}_{}", name, std::process::id()));
+        File::create(&path).unwrap().write_all(data).unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        let compression = detect(&mut file).unwrap();
+        assert_eq!(compression, Some(expected));
+        let mut buf = String::new();
+        decoder(file, expected)
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "Error: test\n");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_decompress() {
+        let text = b"Error: test\n";
+
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(text).unwrap();
+        roundtrip("gz", &enc.finish().unwrap(), Compression::Gzip);
+
+        let mut enc = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        enc.write_all(text).unwrap();
+        roundtrip("bz2", &enc.finish().unwrap(), Compression::Bzip2);
+
+        let mut enc = xz2::write::XzEncoder::new(Vec::new(), 6);
+        enc.write_all(text).unwrap();
+        roundtrip("xz", &enc.finish().unwrap(), Compression::Xz);
+
+        roundtrip(
+            "zst",
+            &zstd::encode_all(&text[..], 0).unwrap(),
+            Compression::Zstd,
+        );
+    }
+
+    #[test]
+    fn test_detect_plain() {
+        let mut file = File::open("sample/maillog").unwrap();
+        assert_eq!(detect(&mut file).unwrap(), None);
+
+        let path = std::env::temp_dir().join(format!("pipecolor_bzh_{}", std::process::id()));
+        std::fs::write(&path, "BZhello\n").unwrap();
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(detect(&mut file).unwrap(), None);
+        let _ = std::fs::remove_file(&path);
+    }
+}
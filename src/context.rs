@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+
+// -------------------------------------------------------------------------------------------------
+// Context
+// -------------------------------------------------------------------------------------------------
+
+// Keep the lines filtered out to output them around matched lines like `grep -B` and `grep -A`.
+pub struct Context {
+    before: usize,
+    after: usize,
+    lines: VecDeque<Vec<u8>>,
+    // The number of the following lines output as the context
+    remaining: usize,
+    // Whether any line is output, and any line is skipped after it
+    started: bool,
+    skipped: bool,
+}
+
+impl Context {
+    pub fn new(before: usize, after: usize) -> Self {
+        Context {
+            before,
+            after,
+            lines: VecDeque::new(),
+            remaining: 0,
+            started: false,
+            skipped: false,
+        }
+    }
+
+    // Return whether the filtered line is output as the context after a matched line
+    pub fn push(&mut self, line: &[u8]) -> bool {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            return true;
+        }
+        self.lines.push_back(line.to_vec());
+        if self.lines.len() > self.before {
+            self.lines.pop_front();
+            self.skipped = true;
+        }
+        false
+    }
+
+    // Return whether a separator is needed before the matched line, and the lines before it
+    pub fn matched(&mut self) -> (bool, Vec<Vec<u8>>) {
+        let separator = self.started && self.skipped;
+        self.started = true;
+        self.skipped = false;
+        self.remaining = self.after;
+        (separator, self.lines.drain(..).collect())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let mut context = Context::new(1, 1);
+        assert!(!context.push(b"a"));
+        assert!(!context.push(b"b"));
+        assert_eq!(context.matched(), (false, vec![b"b".to_vec()]));
+        assert!(context.push(b"c"));
+        assert!(!context.push(b"d"));
+        assert_eq!(context.matched(), (false, vec![b"d".to_vec()]));
+        assert!(context.push(b"e"));
+        assert!(!context.push(b"f"));
+        assert!(!context.push(b"g"));
+        assert_eq!(context.matched(), (true, vec![b"g".to_vec()]));
+        assert_eq!(context.matched(), (false, vec![]));
+    }
+}
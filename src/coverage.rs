@@ -0,0 +1,166 @@
+use crate::colorize::{colorize, Config};
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+// -------------------------------------------------------------------------------------------------
+// Coverage
+// -------------------------------------------------------------------------------------------------
+
+// Hits of rules and lines matching no rule in a corpus
+pub struct Coverage {
+    // Lines matched by each line rule
+    pub lines: Vec<usize>,
+    // Lines matched by each token of each line rule
+    pub tokens: Vec<Vec<usize>>,
+    // The location and text of lines matching no rule
+    pub unmatched: Vec<(String, String)>,
+    pub total: usize,
+}
+
+impl Coverage {
+    pub fn new(config: &Config) -> Self {
+        Coverage {
+            lines: vec![0; config.lines.len()],
+            tokens: config
+                .lines
+                .iter()
+                .map(|x| vec![0; x.tokens.len()])
+                .collect(),
+            unmatched: Vec::new(),
+            total: 0,
+        }
+    }
+
+    // Match the lines of the reader named `name` against the rules
+    pub fn add(&mut self, name: &str, reader: &mut dyn BufRead, config: &Config) -> Result<()> {
+        let mut buf = Vec::new();
+        let mut number = 0;
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            number += 1;
+            self.total += 1;
+            let s = String::from_utf8_lossy(&buf);
+            let s = s.trim_end_matches(&['\r', '\n'][..]);
+            match colorize(s, config)?.1 {
+                Some(i) => {
+                    self.lines[i] += 1;
+                    for (j, token) in config.lines[i].tokens.iter().enumerate() {
+                        if token.pat.is_match(s) {
+                            self.tokens[i][j] += 1;
+                        }
+                    }
+                }
+                None => self
+                    .unmatched
+                    .push((format!("{}:{}", name, number), s.to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    // Whether every rule is used and every line is matched
+    pub fn is_complete(&self) -> bool {
+        self.unmatched.is_empty()
+            && self.lines.iter().all(|x| *x > 0)
+            && self.tokens.iter().flatten().all(|x| *x > 0)
+    }
+
+    pub fn show(&self, config: &Config, writer: &mut dyn Write) -> Result<()> {
+        let mut dead = Vec::new();
+        writeln!(writer, "rules:")?;
+        for (i, line) in config.lines.iter().enumerate() {
+            let rule = match line.description {
+                Some(ref d) => format!("'{}' ({})", line.pat.as_str(), d),
+                None => format!("'{}'", line.pat.as_str()),
+            };
+            writeln!(writer, "  {:>8}  {}", self.lines[i], rule)?;
+            if self.lines[i] == 0 {
+                dead.push(rule.clone());
+            }
+            for (j, token) in line.tokens.iter().enumerate() {
+                writeln!(
+                    writer,
+                    "  {:>8}    token '{}'",
+                    self.tokens[i][j],
+                    token.pat.as_str()
+                )?;
+                if self.tokens[i][j] == 0 {
+                    dead.push(format!("{} token '{}'", rule, token.pat.as_str()));
+                }
+            }
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "rules never matched: {}", dead.len())?;
+        for x in &dead {
+            writeln!(writer, "  {}", x)?;
+        }
+
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "lines matched nothing: {}/{}",
+            self.unmatched.len(),
+            self.total
+        )?;
+        for (location, s) in &self.unmatched {
+            writeln!(writer, "  {}: {}", location, s)?;
+        }
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage() {
+        let config: Config = toml::from_str(
+            r#"
+            [[lines]]
+            pat = "(Error).*"
+            colors = ["Red"]
+            tokens = [{ pat = "[0-9]+", colors = ["Blue"] }, { pat = "x{3}", colors = ["Blue"] }]
+            [[lines]]
+            pat = "(Debug).*"
+            colors = ["Green"]
+            description = "debug"
+            "#,
+        )
+        .unwrap();
+
+        let mut coverage = Coverage::new(&config);
+        let mut input = &b"Error: 404\nInfo: ok\r\nError\n"[..];
+        coverage.add("app.log", &mut input, &config).unwrap();
+        assert_eq!(coverage.lines, vec![2, 0]);
+        assert_eq!(coverage.tokens, vec![vec![1, 0], vec![]]);
+        assert!(!coverage.is_complete());
+
+        let mut out = Vec::new();
+        coverage.show(&config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "rules:
+         2  '(Error).*'
+         1    token '[0-9]+'
+         0    token 'x{3}'
+         0  '(Debug).*' (debug)
+
+rules never matched: 2
+  '(Error).*' token 'x{3}'
+  '(Debug).*' (debug)
+
+lines matched nothing: 1/3
+  app.log:2: Info: ok
+"
+        );
+    }
+}
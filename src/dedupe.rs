@@ -0,0 +1,106 @@
+use regex::Regex;
+use std::collections::VecDeque;
+
+// -------------------------------------------------------------------------------------------------
+// Dedupe
+// -------------------------------------------------------------------------------------------------
+
+// Suppress lines identical to any of the last N lines.
+// The key of a line is compared after removing the text matched by `strip` (e.g. timestamp).
+pub struct Dedupe {
+    window: usize,
+    strip: Option<Regex>,
+    entries: VecDeque<Entry>,
+}
+
+struct Entry {
+    key: String,
+    line: Vec<u8>,
+    repeat: usize,
+}
+
+// A suppressed line and the number of repeats, reported when it leaves the window
+pub type Repeat = (Vec<u8>, usize);
+
+impl Dedupe {
+    pub fn new(window: usize, strip: Option<Regex>) -> Self {
+        Dedupe {
+            window,
+            strip,
+            entries: VecDeque::new(),
+        }
+    }
+
+    // Return whether the line is a duplicate, and the line leaving the window if it was repeated
+    pub fn push(&mut self, line: &[u8]) -> (bool, Option<Repeat>) {
+        let key = self.key(line);
+        if let Some(i) = self.entries.iter().position(|x| x.key == key) {
+            let mut entry = self.entries.remove(i).unwrap();
+            entry.repeat += 1;
+            self.entries.push_back(entry);
+            return (true, None);
+        }
+
+        self.entries.push_back(Entry {
+            key,
+            line: line.to_vec(),
+            repeat: 0,
+        });
+        let evicted = if self.entries.len() > self.window {
+            self.entries.pop_front().filter(|x| x.repeat > 0)
+        } else {
+            None
+        };
+        (false, evicted.map(|x| (x.line, x.repeat)))
+    }
+
+    // Return the repeated lines remaining in the window
+    pub fn finish(&mut self) -> Vec<Repeat> {
+        self.entries
+            .drain(..)
+            .filter(|x| x.repeat > 0)
+            .map(|x| (x.line, x.repeat))
+            .collect()
+    }
+
+    fn key(&self, line: &[u8]) -> String {
+        let s = String::from_utf8_lossy(line);
+        let s = s.trim_end_matches(&['\n', '\r'][..]);
+        match self.strip {
+            Some(ref x) => x.replace_all(s, "").into_owned(),
+            None => s.to_string(),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedupe() {
+        let mut dedupe = Dedupe::new(2, None);
+        assert_eq!(dedupe.push(b"a\n"), (false, None));
+        assert_eq!(dedupe.push(b"b\n"), (false, None));
+        assert_eq!(dedupe.push(b"a\n"), (true, None));
+        assert_eq!(dedupe.push(b"b\n"), (true, None));
+        assert_eq!(dedupe.push(b"a\n"), (true, None));
+        assert_eq!(dedupe.push(b"c\n"), (false, Some((b"b\n".to_vec(), 1))));
+        assert_eq!(dedupe.push(b"b\n"), (false, Some((b"a\n".to_vec(), 2))));
+        assert_eq!(dedupe.finish(), vec![]);
+    }
+
+    #[test]
+    fn test_dedupe_strip() {
+        let strip = Regex::new(r"^\d+:\d+:\d+ ").unwrap();
+        let mut dedupe = Dedupe::new(8, Some(strip));
+        assert_eq!(dedupe.push(b"12:00:00 Error\n"), (false, None));
+        assert_eq!(dedupe.push(b"12:00:01 Info\n"), (false, None));
+        assert_eq!(dedupe.push(b"12:00:02 Error\n"), (true, None));
+        assert_eq!(dedupe.finish(), vec![(b"12:00:00 Error\n".to_vec(), 1)]);
+    }
+}
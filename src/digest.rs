@@ -0,0 +1,109 @@
+use sha2::digest::DynDigest;
+use sha2::{Sha256, Sha512};
+use std::io::{Read, Result};
+use std::sync::{Arc, Mutex};
+
+// -------------------------------------------------------------------------------------------------
+// Summary
+// -------------------------------------------------------------------------------------------------
+
+// The digest and the byte/line counts of an input
+pub struct Summary {
+    pub algorithm: String,
+    hasher: Box<dyn DynDigest + Send>,
+    pub bytes: u64,
+    newlines: u64,
+    last: Option<u8>,
+}
+
+impl Summary {
+    pub fn new(algorithm: &str) -> Self {
+        let hasher: Box<dyn DynDigest + Send> = match algorithm {
+            "sha512" => Box::new(Sha512::default()),
+            _ => Box::new(Sha256::default()),
+        };
+        Summary {
+            algorithm: algorithm.to_string(),
+            hasher,
+            bytes: 0,
+            newlines: 0,
+            last: None,
+        }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.hasher.update(buf);
+        self.bytes += buf.len() as u64;
+        self.newlines += memchr::memchr_iter(b'\n', buf).count() as u64;
+        if let Some(x) = buf.last() {
+            self.last = Some(*x);
+        }
+    }
+
+    // The last line without newline is counted too
+    pub fn lines(&self) -> u64 {
+        match self.last {
+            Some(x) if x != b'\n' => self.newlines + 1,
+            _ => self.newlines,
+        }
+    }
+
+    pub fn hex(&self) -> String {
+        let digest = self.hasher.box_clone().finalize();
+        digest.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// DigestReader
+// -------------------------------------------------------------------------------------------------
+
+// Update the summary by the bytes read through it
+pub struct DigestReader<R: Read> {
+    inner: R,
+    summary: Arc<Mutex<Summary>>,
+}
+
+impl<R: Read> DigestReader<R> {
+    pub fn new(inner: R, summary: Arc<Mutex<Summary>>) -> Self {
+        DigestReader { inner, summary }
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.summary.lock().unwrap().update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let summary = Arc::new(Mutex::new(Summary::new("sha256")));
+        let mut reader = DigestReader::new(&b"abc\ndef"[..], summary.clone());
+        let mut s = String::new();
+        reader.read_to_string(&mut s).unwrap();
+
+        let summary = summary.lock().unwrap();
+        assert_eq!(summary.bytes, 7);
+        assert_eq!(summary.lines(), 2);
+        assert_eq!(
+            summary.hex(),
+            "d53d6b91af7caf8fe3d8021f116270137c0079d579a1e16965da80c2ed138ffb"
+        );
+
+        let mut summary = Summary::new("sha512");
+        summary.update(b"");
+        assert_eq!(summary.lines(), 0);
+        assert_eq!(&summary.hex()[..16], "cf83e1357eefb8bd");
+    }
+}
@@ -0,0 +1,184 @@
+use crate::http;
+use anyhow::{Context, Result};
+use std::env;
+use std::io::{self, BufRead, BufReader, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// -------------------------------------------------------------------------------------------------
+// DockerReader
+// -------------------------------------------------------------------------------------------------
+
+// Demultiplex the log stream of a container without TTY.
+// Each frame has an 8-byte header: the stream type, 3 zero bytes and the big-endian payload size.
+pub struct DockerReader<R: Read> {
+    inner: R,
+    tty: bool,
+    remaining: usize,
+}
+
+impl<R: Read> DockerReader<R> {
+    pub fn new(inner: R, tty: bool) -> Self {
+        DockerReader {
+            inner,
+            tty,
+            remaining: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for DockerReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.tty {
+            return self.inner.read(buf);
+        }
+        while self.remaining == 0 {
+            let mut header = [0; 8];
+            if !read_full(&mut self.inner, &mut header)? {
+                return Ok(0);
+            }
+            self.remaining =
+                u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        }
+        let len = buf.len().min(self.remaining);
+        let len = self.inner.read(&mut buf[..len])?;
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= len;
+        Ok(len)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// The address of the Docker daemon given by DOCKER_HOST, or the default socket
+fn docker_host() -> String {
+    env::var("DOCKER_HOST").unwrap_or_else(|_| String::from("unix:///var/run/docker.sock"))
+}
+
+// Convert --since to UNIX timestamp. Relative time like `10m` is before now.
+pub fn parse_since(s: &str, now: u64) -> Result<u64> {
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return s.parse().context(format!("invalid time '{}'", s)),
+    };
+    let n: u64 = s[..s.len() - 1]
+        .parse()
+        .context(format!("invalid time '{}'", s))?;
+    Ok(now.saturating_sub(n * unit))
+}
+
+pub fn logs(container: &str, follow: bool, since: Option<&str>) -> Result<Box<dyn BufRead + Send>> {
+    logs_from(&docker_host(), container, follow, since)
+}
+
+fn logs_from(
+    host: &str,
+    container: &str,
+    follow: bool,
+    since: Option<&str>,
+) -> Result<Box<dyn BufRead + Send>> {
+    // The log stream is multiplexed unless the container has TTY
+    let inspect = http::get_json(host, &format!("/containers/{}/json", container), container)?;
+    let tty = inspect["Config"]["Tty"].as_bool().unwrap_or(false);
+
+    let mut path = format!(
+        "/containers/{}/logs?stdout=1&stderr=1&follow={}",
+        container, follow as u8
+    );
+    if let Some(x) = since {
+        path.push_str(&format!("&since={}", parse_since(x, now())?));
+    }
+    let body = http::get_ok(host, &path, container)?;
+    Ok(Box::new(BufReader::new(DockerReader::new(body, tty))))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> io::Result<bool> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match reader.read(&mut buf[pos..])? {
+            0 if pos == 0 => return Ok(false),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => pos += n,
+        }
+    }
+    Ok(true)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::{chunked, serve};
+
+    fn frame(stream: u8, s: &str) -> Vec<u8> {
+        let mut ret = vec![stream, 0, 0, 0];
+        ret.extend_from_slice(&(s.len() as u32).to_be_bytes());
+        ret.extend_from_slice(s.as_bytes());
+        ret
+    }
+
+    #[test]
+    fn test_demux() {
+        let mut input = frame(1, "Error: a");
+        input.extend(frame(2, "bc\nWarning\n"));
+        let mut s = String::new();
+        DockerReader::new(&input[..], false)
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s, "Error: abc\nWarning\n");
+
+        let mut s = String::new();
+        DockerReader::new(&b"raw\n"[..], true)
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s, "raw\n");
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("1700000000", 0).unwrap(), 1700000000);
+        assert_eq!(parse_since("10m", 1000).unwrap(), 400);
+        assert_eq!(parse_since("2h", 1000).unwrap(), 0);
+        assert!(parse_since("yesterday", 1000).is_err());
+    }
+
+    #[test]
+    fn test_logs() {
+        let mut logs = frame(1, "Error\n");
+        logs.extend(frame(2, "Warning\n"));
+        let (host, server) = serve(vec![
+            chunked(br#"{"Config":{"Tty":false}}"#),
+            chunked(&logs),
+        ]);
+
+        let mut s = String::new();
+        logs_from(&host, "web", false, Some("1700000000"))
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s, "Error\nWarning\n");
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "GET /containers/web/json HTTP/1.1",
+                "GET /containers/web/logs?stdout=1&stderr=1&follow=0&since=1700000000 HTTP/1.1"
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, Result};
+use encoding_rs::{Decoder, Encoding};
+use std::io::{self, Read};
+
+// The size of input decoded at once
+const CHUNK: usize = 8 * 1024;
+
+// -------------------------------------------------------------------------------------------------
+// DecodeReader
+// -------------------------------------------------------------------------------------------------
+
+// Transcode the input of the encoding to UTF-8.
+// Malformed sequences are replaced by U+FFFD, and a BOM is removed.
+pub struct DecodeReader<R> {
+    inner: R,
+    decoder: Decoder,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> DecodeReader<R> {
+    pub fn new(inner: R, encoding: &'static Encoding) -> Self {
+        DecodeReader {
+            inner,
+            decoder: encoding.new_decoder(),
+            input: vec![0; CHUNK],
+            output: Vec::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.output.len() {
+            if self.eof {
+                return Ok(0);
+            }
+            let n = self.inner.read(&mut self.input)?;
+            let last = n == 0;
+            // The output always has enough space for the whole input
+            let len = self.decoder.max_utf8_buffer_length(n).unwrap_or(n * 3 + 16);
+            self.output.resize(len, 0);
+            let (_, _, written, _) =
+                self.decoder
+                    .decode_to_utf8(&self.input[..n], &mut self.output, last);
+            self.output.truncate(written);
+            self.pos = 0;
+            self.eof = last;
+        }
+        let n = (&self.output[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// Find the encoding by the label like "sjis", "latin1" or "utf-16le"
+pub fn get(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| anyhow!("unknown encoding '{}'", label))
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    fn decode(input: &[u8], label: &str) -> Vec<String> {
+        let reader = DecodeReader::new(input, get(label).unwrap());
+        BufReader::new(reader).lines().map(|x| x.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        // "エラー: ディスク" in Shift_JIS
+        let sjis = b"\x83G\x83\x89\x81[: \x83f\x83B\x83X\x83N\nok\n";
+        assert_eq!(decode(sjis, "sjis"), vec!["エラー: ディスク", "ok"]);
+        assert_eq!(decode(b"caf\xe9\n", "latin1"), vec!["café"]);
+        assert_eq!(
+            decode(b"\xff\xfeo\x00k\x00\n\x00\xa8\x30\n\x00", "utf-16"),
+            vec!["ok", "エ"]
+        );
+        assert!(get("ebcdic").is_err());
+
+        // Multi-byte characters split across chunks
+        let mut long = vec![b'a'; CHUNK - 1];
+        long.extend_from_slice(b"\x83G\n");
+        assert_eq!(decode(&long, "sjis")[0].chars().last(), Some('エ'));
+    }
+}
@@ -0,0 +1,25 @@
+use std::fmt;
+
+// -------------------------------------------------------------------------------------------------
+// Error
+// -------------------------------------------------------------------------------------------------
+
+// Errors of the library API. Variants carry the message only to keep the type stable.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    // The config is not valid TOML or doesn't match the schema
+    Config(String),
+    // A color or a style in the rules can't be resolved
+    Color(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(x) => write!(f, "invalid config: {}", x),
+            Error::Color(x) => write!(f, "invalid color: {}", x),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,230 @@
+use std::fs::{self, File, Metadata};
+use std::io::{Read, Result, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// -------------------------------------------------------------------------------------------------
+// FollowReader
+// -------------------------------------------------------------------------------------------------
+
+// A file reader which reopens the file by name when it is rotated or truncated like `tail -F`.
+pub struct FollowReader {
+    path: PathBuf,
+    file: File,
+    id: Option<u64>,
+    pos: u64,
+}
+
+impl FollowReader {
+    pub fn new(path: PathBuf, mut file: File) -> Result<Self> {
+        let id = file_id(&file.metadata()?);
+        let pos = file.stream_position()?;
+        Ok(FollowReader {
+            path,
+            file,
+            id,
+            pos,
+        })
+    }
+
+    fn is_rotated(&self) -> bool {
+        match fs::metadata(&self.path) {
+            Ok(meta) => file_id(&meta) != self.id || meta.len() < self.pos,
+            // The file may be missing for a moment while being rotated
+            Err(_) => false,
+        }
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        let file = File::open(&self.path)?;
+        self.id = file_id(&file.metadata()?);
+        self.file = file;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut len = self.file.read(buf)?;
+        if len == 0 && self.is_rotated() {
+            self.reopen()?;
+            len = self.file.read(buf)?;
+        }
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FifoReader
+// -------------------------------------------------------------------------------------------------
+
+// A FIFO reader which reopens the FIFO when the writer closes it, and waits for the next writer.
+pub struct FifoReader {
+    path: PathBuf,
+    file: File,
+}
+
+impl FifoReader {
+    pub fn new(path: PathBuf, file: File) -> Self {
+        FifoReader { path, file }
+    }
+}
+
+impl Read for FifoReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let len = self.file.read(buf)?;
+            if len > 0 || buf.is_empty() {
+                return Ok(len);
+            }
+            // Opening blocks until the next writer opens it
+            self.file = File::open(&self.path)?;
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(unix)]
+pub fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(path).is_ok_and(|x| x.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+pub fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+// Seek to the beginning of the last n lines by scanning the file backwards
+pub fn seek_last_lines(file: &mut File, n: usize) -> Result<()> {
+    const CHUNK: u64 = 8192;
+
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut end = len;
+    if end > 0 {
+        let mut last = [0; 1];
+        file.seek(SeekFrom::Start(end - 1))?;
+        file.read_exact(&mut last)?;
+        if last[0] == b'\n' {
+            end -= 1;
+        }
+    }
+
+    let mut start = if n == 0 { len } else { 0 };
+    let mut count = 0;
+    let mut buf = vec![0; CHUNK as usize];
+    'scan: while n > 0 && end > 0 {
+        let size = end.min(CHUNK);
+        let base = end - size;
+        file.seek(SeekFrom::Start(base))?;
+        file.read_exact(&mut buf[..size as usize])?;
+        for (i, &c) in buf[..size as usize].iter().enumerate().rev() {
+            if c == b'\n' {
+                count += 1;
+                if count == n {
+                    start = base + i as u64 + 1;
+                    break 'scan;
+                }
+            }
+        }
+        end = base;
+    }
+
+    file.seek(SeekFrom::Start(start))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.ino())
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata) -> Option<u64> {
+    None
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_truncate() {
+        let path = std::env::temp_dir().join(format!("pipecolor_follow_{}", std::process::id()));
+        fs::write(&path, b"abcdef").unwrap();
+        let mut reader = FollowReader::new(path.clone(), File::open(&path).unwrap()).unwrap();
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"abcdef");
+
+        let mut f = File::create(&path).unwrap();
+        f.write_all(b"xyz").unwrap();
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"xyz");
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo() {
+        use std::ffi::CString;
+
+        let path = std::env::temp_dir().join(format!("pipecolor_fifo_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let cpath = CString::new(path.to_string_lossy().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(cpath.as_ptr(), 0o600) }, 0);
+        assert!(is_fifo(&path));
+        assert!(!is_fifo(Path::new("sample/maillog")));
+
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || {
+            for s in &["first\n", "second\n"] {
+                let mut f = fs::OpenOptions::new()
+                    .write(true)
+                    .open(&writer_path)
+                    .unwrap();
+                f.write_all(s.as_bytes()).unwrap();
+            }
+        });
+
+        let mut reader = FifoReader::new(path.clone(), File::open(&path).unwrap());
+        let mut buf = Vec::new();
+        while buf.len() < 13 {
+            let mut chunk = [0; 16];
+            let len = reader.read(&mut chunk).unwrap();
+            buf.extend_from_slice(&chunk[..len]);
+        }
+        assert_eq!(buf, b"first\nsecond\n");
+        writer.join().unwrap();
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_seek_last_lines() {
+        let mut f = File::open("sample/maillog").unwrap();
+        let mut all = String::new();
+        f.read_to_string(&mut all).unwrap();
+        let lines: Vec<_> = all.lines().collect();
+
+        for n in &[0, 1, 3, lines.len(), lines.len() + 10] {
+            seek_last_lines(&mut f, *n).unwrap();
+            let mut buf = String::new();
+            f.read_to_string(&mut buf).unwrap();
+            let skip = lines.len().saturating_sub(*n);
+            assert_eq!(buf.lines().collect::<Vec<_>>(), lines[skip..].to_vec());
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::{Context, Result};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+// -------------------------------------------------------------------------------------------------
+// ChunkedReader
+// -------------------------------------------------------------------------------------------------
+
+// Decode the body of HTTP chunked transfer encoding
+struct ChunkedReader<R: BufRead> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !self.done {
+            let mut line = String::new();
+            self.inner.read_line(&mut line)?;
+            if line.trim().is_empty() {
+                // CRLF after the previous chunk
+                line.clear();
+                self.inner.read_line(&mut line)?;
+            }
+            let size = line.trim().split(';').next().unwrap_or_default();
+            self.remaining = usize::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+            self.done = self.remaining == 0;
+        }
+        if self.done {
+            return Ok(0);
+        }
+        let len = buf.len().min(self.remaining);
+        let len = self.inner.read(&mut buf[..len])?;
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= len;
+        Ok(len)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+// Connect to `tcp://HOST:PORT`, `http://HOST:PORT` or `unix://PATH`
+fn connect(host: &str) -> Result<Box<dyn Stream>> {
+    let addr = host
+        .strip_prefix("tcp://")
+        .or_else(|| host.strip_prefix("http://"));
+    if let Some(addr) = addr {
+        let addr = addr.trim_end_matches('/');
+        let stream =
+            TcpStream::connect(addr).context(format!("failed to connect to '{}'", host))?;
+        return Ok(Box::new(stream));
+    }
+    connect_unix(host)
+}
+
+#[cfg(unix)]
+fn connect_unix(host: &str) -> Result<Box<dyn Stream>> {
+    let path = host.strip_prefix("unix://").unwrap_or(host);
+    let stream = UnixStream::connect(path).context(format!("failed to connect to '{}'", host))?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(host: &str) -> Result<Box<dyn Stream>> {
+    anyhow::bail!("unsupported host '{}'", host)
+}
+
+// Send a GET request, and return the status code and the body
+pub fn get(host: &str, path: &str) -> Result<(u32, Box<dyn BufRead + Send>)> {
+    let mut stream = connect(host)?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    )?;
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|x| x.parse().ok())
+        .context(format!("invalid response from '{}'", host))?;
+
+    let mut chunked = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let header = line.to_ascii_lowercase();
+        if header.starts_with("transfer-encoding:") && header.contains("chunked") {
+            chunked = true;
+        }
+    }
+
+    if chunked {
+        let reader = ChunkedReader {
+            inner: reader,
+            remaining: 0,
+            done: false,
+        };
+        Ok((status, Box::new(BufReader::new(reader))))
+    } else {
+        Ok((status, Box::new(reader)))
+    }
+}
+
+// Send a GET request, and return the body if succeeded.
+// The error message in the JSON body is used for the error.
+pub fn get_ok(host: &str, path: &str, name: &str) -> Result<Box<dyn BufRead + Send>> {
+    let (status, mut body) = get(host, path)?;
+    if status != 200 {
+        let mut s = String::new();
+        let _ = body.read_to_string(&mut s);
+        let msg = serde_json::from_str::<serde_json::Value>(&s)
+            .ok()
+            .and_then(|x| x["message"].as_str().map(String::from))
+            .unwrap_or(s);
+        anyhow::bail!("failed to read logs of '{}': {}", name, msg.trim());
+    }
+    Ok(body)
+}
+
+pub fn get_json(host: &str, path: &str, name: &str) -> Result<serde_json::Value> {
+    let mut body = get_ok(host, path, name)?;
+    let mut s = String::new();
+    body.read_to_string(&mut s)?;
+    let ret =
+        serde_json::from_str(&s).context(format!("failed to parse response for '{}'", name))?;
+    Ok(ret)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Serve the responses to the connections in order, and return the request lines
+    pub fn serve(responses: Vec<Vec<u8>>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = format!("tcp://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                requests.push(line.trim_end().to_string());
+                while line.trim() != "" {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                }
+                reader.into_inner().write_all(&response).unwrap();
+            }
+            requests
+        });
+        (host, handle)
+    }
+
+    // A chunked response with the body in a chunk
+    pub fn chunked(body: &[u8]) -> Vec<u8> {
+        let mut ret = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+            body.len()
+        )
+        .into_bytes();
+        ret.extend_from_slice(body);
+        ret.extend_from_slice(b"\r\n0\r\n\r\n");
+        ret
+    }
+
+    #[test]
+    fn test_get() {
+        let not_found = r#"{"message":"not found"}"#;
+        let (host, server) = serve(vec![
+            chunked(b"abc"),
+            format!(
+                "HTTP/1.1 404 Not Found\r\nContent-Length: {}\r\n\r\n{}",
+                not_found.len(),
+                not_found
+            )
+            .into_bytes(),
+        ]);
+
+        let mut s = String::new();
+        get_ok(&host, "/a", "a")
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s, "abc");
+        let e = get_ok(&host, "/b", "b").err().unwrap();
+        assert_eq!(e.to_string(), "failed to read logs of 'b': not found");
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["GET /a HTTP/1.1", "GET /b HTTP/1.1"]
+        );
+    }
+}
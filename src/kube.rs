@@ -0,0 +1,158 @@
+use crate::docker;
+use crate::http;
+use anyhow::Result;
+use std::env;
+use std::io::BufRead;
+
+// -------------------------------------------------------------------------------------------------
+// Pod
+// -------------------------------------------------------------------------------------------------
+
+// A container of a pod specified like `NAMESPACE/POD[/CONTAINER]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pod {
+    pub namespace: String,
+    pub name: String,
+    pub container: Option<String>,
+}
+
+impl Pod {
+    pub fn parse(s: &str) -> Result<Self> {
+        let parts: Vec<_> = s.split('/').collect();
+        let (namespace, name, container) = match parts[..] {
+            [name] => ("default", name, None),
+            [namespace, name] => (namespace, name, None),
+            [namespace, name, container] => (namespace, name, Some(container)),
+            _ => anyhow::bail!("invalid pod '{}'", s),
+        };
+        if namespace.is_empty() || name.is_empty() || container == Some("") {
+            anyhow::bail!("invalid pod '{}'", s);
+        }
+        Ok(Pod {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            container: container.map(String::from),
+        })
+    }
+
+    pub fn label(&self) -> String {
+        match self.container {
+            Some(ref x) => format!("{}/{}/{}", self.namespace, self.name, x),
+            None => format!("{}/{}", self.namespace, self.name),
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// The API server given by KUBE_API, or `kubectl proxy` by default.
+// Authentication is left to `kubectl proxy` because TLS is not supported.
+fn kube_api() -> String {
+    env::var("KUBE_API").unwrap_or_else(|_| String::from("http://127.0.0.1:8001"))
+}
+
+// Expand the pod without container to all containers of the pod
+pub fn containers(pod: &Pod) -> Result<Vec<Pod>> {
+    containers_from(&kube_api(), pod)
+}
+
+fn containers_from(host: &str, pod: &Pod) -> Result<Vec<Pod>> {
+    if pod.container.is_some() {
+        return Ok(vec![pod.clone()]);
+    }
+    let path = format!("/api/v1/namespaces/{}/pods/{}", pod.namespace, pod.name);
+    let spec = http::get_json(host, &path, &pod.label())?;
+    let names: Vec<_> = spec["spec"]["containers"]
+        .as_array()
+        .map(|x| x.iter().filter_map(|x| x["name"].as_str()).collect())
+        .unwrap_or_default();
+    if names.len() <= 1 {
+        // The container can be omitted if the pod has only one
+        return Ok(vec![pod.clone()]);
+    }
+    Ok(names
+        .into_iter()
+        .map(|x| Pod {
+            container: Some(x.to_string()),
+            ..pod.clone()
+        })
+        .collect())
+}
+
+pub fn logs(pod: &Pod, follow: bool, since: Option<&str>) -> Result<Box<dyn BufRead + Send>> {
+    logs_from(&kube_api(), pod, follow, since)
+}
+
+fn logs_from(
+    host: &str,
+    pod: &Pod,
+    follow: bool,
+    since: Option<&str>,
+) -> Result<Box<dyn BufRead + Send>> {
+    let mut path = format!(
+        "/api/v1/namespaces/{}/pods/{}/log?follow={}",
+        pod.namespace, pod.name, follow
+    );
+    if let Some(ref x) = pod.container {
+        path.push_str(&format!("&container={}", x));
+    }
+    if let Some(x) = since {
+        let now = docker::now();
+        let secs = now.saturating_sub(docker::parse_since(x, now)?);
+        path.push_str(&format!("&sinceSeconds={}", secs.max(1)));
+    }
+    http::get_ok(host, &path, &pod.label())
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::{chunked, serve};
+    use std::io::Read;
+
+    #[test]
+    fn test_parse() {
+        let pod = Pod::parse("web").unwrap();
+        assert_eq!(pod.label(), "default/web");
+        let pod = Pod::parse("prod/web/nginx").unwrap();
+        assert_eq!(pod.namespace, "prod");
+        assert_eq!(pod.container, Some(String::from("nginx")));
+        assert!(Pod::parse("prod/web/nginx/x").is_err());
+        assert!(Pod::parse("prod/").is_err());
+    }
+
+    #[test]
+    fn test_containers() {
+        let spec = br#"{"spec":{"containers":[{"name":"app"},{"name":"sidecar"}]}}"#;
+        let (host, server) = serve(vec![chunked(spec)]);
+        let pods = containers_from(&host, &Pod::parse("prod/web").unwrap()).unwrap();
+        let labels: Vec<_> = pods.iter().map(|x| x.label()).collect();
+        assert_eq!(labels, vec!["prod/web/app", "prod/web/sidecar"]);
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["GET /api/v1/namespaces/prod/pods/web HTTP/1.1"]
+        );
+    }
+
+    #[test]
+    fn test_logs() {
+        let (host, server) = serve(vec![chunked(b"Error\nInfo\n")]);
+        let pod = Pod::parse("prod/web/app").unwrap();
+        let mut s = String::new();
+        logs_from(&host, &pod, true, None)
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s, "Error\nInfo\n");
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["GET /api/v1/namespaces/prod/pods/web/log?follow=true&container=app HTTP/1.1"]
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! The rule-based colorization engine of pipecolor.
+//!
+//! ```
+//! use pipecolor::{Colorizer, Config};
+//!
+//! let config = Config::from_toml_str(
+//!     r#"
+//!     [[lines]]
+//!     pat = "(Error).*"
+//!     colors = ["Red", "LightRed"]
+//!     "#,
+//! )
+//! .unwrap();
+//! let colorizer = Colorizer::new(config);
+//! let line = colorizer.colorize_line("Error: disk full\n").unwrap();
+//! assert!(line.starts_with("\u{1b}[38;5;9mError\u{1b}[38;5;1m: disk full"));
+//! ```
+
+pub mod colorize;
+mod error;
+pub mod read_timeout;
+pub mod tokenize;
+
+pub use colorize::{ColorDepth, ColoredLine, Colorizer, Config, Spans, Style};
+pub use error::Error;
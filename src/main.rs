@@ -1,22 +1,73 @@
-mod colorize;
-mod read_timeout;
-
-use anyhow::{Context, Result};
-use atty::Stream;
-use colorize::{colorize, Config};
+mod auto;
+mod compress;
+mod context;
+mod coverage;
+mod dedupe;
+mod digest;
+mod docker;
+mod encoding;
+mod follow;
+mod http;
+mod kube;
+mod mmap;
+mod pidof;
+mod pipeline;
+mod recorder;
+mod report;
+#[cfg(unix)]
+mod serial;
+mod signal;
+mod sink;
+mod sortkey;
+mod source;
+mod stats;
+mod syslog;
+mod timestamp;
 #[cfg(all(
     target_os = "linux",
     target_arch = "x86_64",
     any(target_env = "gnu", target_env = "musl")
 ))]
-use proc_reader::ProcReader;
-use read_timeout::read_line_timeout;
-use std::fs::File;
-use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Read, Write};
+mod trace;
+mod tui;
+mod walk;
+
+use anyhow::{Context, Result};
+use atty::Stream;
+use colorize::{
+    colorize_bytes, colorize_with_bg, has_sgr, paint, paint_with_bg, strip_sgr, truncate_sgr,
+    Action, ColorDepth, Config, Line, MatchMode,
+};
+use coverage::Coverage;
+use dedupe::{Dedupe, Repeat};
+use digest::{DigestReader, Summary};
+use encoding::DecodeReader;
+use pipecolor::{colorize, read_timeout};
+use read_timeout::read_record_timeout;
+use recorder::{RecordWriter, Recorder};
+use regex::Regex;
+use signal::SignalForwarder;
+use sink::{Fanout, PipeWriter, Sink, WriterSink};
+use sortkey::SortKey;
+use source::{
+    get_process_sources, get_reader_pipe, get_sources, get_stream_sources, Attach, FileSource,
+    PtraceAttach, Source, SourceKind,
+};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, stderr, stdout, BufRead, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use structopt::{clap, StructOpt};
-use timeout_readwrite::TimeoutReader;
+use timestamp::Since;
+use walk::Walker;
 
 // -------------------------------------------------------------------------------------------------
 // Option
@@ -46,17 +97,492 @@ pub struct Opt {
     #[structopt(short = "c", long = "config", parse(from_os_str))]
     pub config: Option<PathBuf>,
 
-    /// Timeout of stdin by milliseconds
+    /// Use the first config file found or merge all of them
+    #[structopt(
+        long = "config-precedence",
+        default_value = "first",
+        possible_value = "first",
+        possible_value = "merge"
+    )]
+    pub config_precedence: String,
+
+    /// Don't read config files other than --config
+    #[structopt(long = "no-user-config")]
+    pub no_user_config: bool,
+
+    /// Merge every *.toml in the directory after config files.
+    /// The default is pipecolor/rules.d in the config directory
+    #[structopt(
+        long = "rules-dir",
+        value_name = "DIR",
+        number_of_values = 1,
+        parse(from_os_str)
+    )]
+    pub rules_dirs: Vec<PathBuf>,
+
+    /// Add a rule like 'PATTERN:COLOR' prior to rules of config files.
+    /// The default rules are not used with it
+    #[structopt(short = "e", long = "rule", value_name = "RULE", number_of_values = 1)]
+    pub rules: Vec<String>,
+
+    /// Use only the rules of the names in config files
+    #[structopt(long = "only-rules", value_name = "NAME", use_delimiter = true)]
+    pub only_rules: Vec<String>,
+
+    /// Don't use the rules of the names in config files
+    #[structopt(long = "skip-rules", value_name = "NAME", use_delimiter = true)]
+    pub skip_rules: Vec<String>,
+
+    /// Use only the rules with the tags in config files
+    #[structopt(long = "only-tags", value_name = "TAG", use_delimiter = true)]
+    pub only_tags: Vec<String>,
+
+    /// Don't use the rules with the tags in config files
+    #[structopt(long = "skip-tags", value_name = "TAG", use_delimiter = true)]
+    pub skip_tags: Vec<String>,
+
+    /// Match all patterns of rules case-insensitively
+    #[structopt(short = "i", long = "ignore-case")]
+    pub ignore_case: bool,
+
+    /// Which rule is applied if several rules match the line [default: first]
+    #[structopt(
+        long = "match-mode",
+        possible_value = "first",
+        possible_value = "priority",
+        possible_value = "longest",
+        possible_value = "all"
+    )]
+    pub match_mode: Option<String>,
+
+    /// Highlight common tokens like IP addresses, URLs, UUIDs, timestamps and numbers in any line
+    #[structopt(long = "auto")]
+    pub auto: bool,
+
+    /// Highlight all occurrences of the literal word like 'WORD=COLOR' in any line
+    #[structopt(long = "highlight", value_name = "WORD=COLOR", number_of_values = 1)]
+    pub highlights: Vec<String>,
+
+    /// Flush output after stdin is idle for the milliseconds
     #[structopt(short = "t", long = "timeout", default_value = "500")]
     pub timeout: u64,
 
+    /// Emit a partial line of stdin or a wrapped command after it is idle for MS milliseconds
+    #[structopt(long = "flush-partial", value_name = "MS")]
+    pub flush_partial: Option<u64>,
+
+    /// Don't colorize partial lines emitted by --flush-partial and the rest of them
+    #[structopt(long = "raw-partial", requires = "flush-partial")]
+    pub raw_partial: bool,
+
     /// Show verbose message
     #[structopt(short = "v", long = "verbose")]
     pub verbose: bool,
 
-    /// Attach to the specified process
-    #[structopt(short = "p", long = "process", conflicts_with = "FILE")]
-    pub process: Option<i32>,
+    /// Attach to the specified processes
+    #[structopt(
+        short = "p",
+        long = "process",
+        number_of_values = 1,
+        conflicts_with = "FILE"
+    )]
+    pub process: Vec<i32>,
+
+    /// Attach to the newest process whose name or command line matches the pattern
+    #[structopt(
+        long = "process-name",
+        value_name = "PATTERN",
+        conflicts_with = "FILE",
+        conflicts_with = "process"
+    )]
+    pub process_name: Option<String>,
+
+    /// Output stream of the attached processes to read. `tag` reads both with the label of the stream
+    #[structopt(
+        long = "process-stream",
+        default_value = "any",
+        possible_value = "stdout",
+        possible_value = "stderr",
+        possible_value = "any",
+        possible_value = "tag"
+    )]
+    pub process_stream: String,
+
+    /// Wait for the process matched by --process-name to start
+    #[structopt(long = "wait", requires = "process-name")]
+    pub wait: bool,
+
+    /// Attach to child processes of the attached processes too
+    #[structopt(long = "follow-forks")]
+    pub follow_forks: bool,
+
+    /// Read logs of the Docker container
+    #[structopt(
+        long = "docker",
+        value_name = "CONTAINER",
+        number_of_values = 1,
+        conflicts_with = "FILE",
+        conflicts_with = "process",
+        conflicts_with = "process-name"
+    )]
+    pub docker: Vec<String>,
+
+    /// Read logs of the Kubernetes pod specified like NAMESPACE/POD[/CONTAINER].
+    /// The API server is KUBE_API, or `kubectl proxy` at http://127.0.0.1:8001 by default.
+    /// TLS is not supported, so connect through `kubectl proxy`
+    #[structopt(
+        long = "kube",
+        value_name = "POD",
+        number_of_values = 1,
+        conflicts_with = "FILE",
+        conflicts_with = "process",
+        conflicts_with = "process-name",
+        conflicts_with = "docker"
+    )]
+    pub kube: Vec<String>,
+
+    /// Container of the pods by --kube
+    #[structopt(long = "container", value_name = "NAME", requires = "kube")]
+    pub container: Option<String>,
+
+    /// Start from lines since the UNIX timestamp or relative time like 10m.
+    /// Files and stdin require timestamp regex in the config
+    #[structopt(long = "since", value_name = "TIME")]
+    pub since: Option<String>,
+
+    /// Accept lines from TCP connections on the address
+    #[structopt(
+        long = "listen",
+        value_name = "ADDR",
+        number_of_values = 1,
+        conflicts_with = "FILE",
+        conflicts_with = "process",
+        conflicts_with = "process-name",
+        conflicts_with = "docker",
+        conflicts_with = "kube"
+    )]
+    pub listen: Vec<String>,
+
+    /// Accept lines from connections to the unix socket
+    #[structopt(
+        long = "listen-unix",
+        value_name = "PATH",
+        number_of_values = 1,
+        parse(from_os_str),
+        conflicts_with = "FILE",
+        conflicts_with = "process",
+        conflicts_with = "process-name",
+        conflicts_with = "docker",
+        conflicts_with = "kube"
+    )]
+    pub listen_unix: Vec<PathBuf>,
+
+    /// Receive syslog datagrams on the UDP port or address, and colorize them by severity
+    #[structopt(
+        long = "syslog-udp",
+        value_name = "ADDR",
+        conflicts_with = "FILE",
+        conflicts_with = "process",
+        conflicts_with = "process-name",
+        conflicts_with = "docker",
+        conflicts_with = "kube",
+        conflicts_with = "listen",
+        conflicts_with = "listen-unix"
+    )]
+    pub syslog_udp: Option<String>,
+
+    /// Read the serial port like /dev/ttyUSB0, and reopen it when the device is plugged again
+    #[structopt(
+        long = "serial",
+        value_name = "DEVICE",
+        parse(from_os_str),
+        conflicts_with = "FILE",
+        conflicts_with = "process",
+        conflicts_with = "process-name",
+        conflicts_with = "docker",
+        conflicts_with = "kube",
+        conflicts_with = "listen",
+        conflicts_with = "listen-unix",
+        conflicts_with = "syslog-udp"
+    )]
+    pub serial: Option<PathBuf>,
+
+    /// Baud rate of the serial port
+    #[structopt(long = "baud", default_value = "9600")]
+    pub baud: u32,
+
+    /// Command to run and colorize its output
+    #[structopt(
+        name = "COMMAND",
+        last = true,
+        conflicts_with = "FILE",
+        conflicts_with = "process",
+        conflicts_with = "process-name",
+        conflicts_with = "docker",
+        conflicts_with = "kube",
+        conflicts_with = "listen",
+        conflicts_with = "listen-unix",
+        conflicts_with = "syslog-udp",
+        conflicts_with = "serial"
+    )]
+    pub command: Vec<String>,
+
+    /// Show lines in the interactive viewer with scrollback, search and rule toggles
+    #[structopt(long = "tui", conflicts_with = "COMMAND")]
+    pub tui: bool,
+
+    /// Keep reading files as they grow
+    #[structopt(short = "f", long = "follow")]
+    pub follow: bool,
+
+    /// Keep reading files and reopen them when rotated or truncated
+    #[structopt(short = "F", long = "follow-name")]
+    pub follow_name: bool,
+
+    /// Reopen FIFOs when the writer closes them, and wait for the next writer
+    #[structopt(long = "reopen-fifo")]
+    pub reopen_fifo: bool,
+
+    /// Transcode input from the encoding like sjis, latin1 or utf-16le to UTF-8
+    #[structopt(long = "encoding", value_name = "ENCODING")]
+    pub encoding: Option<String>,
+
+    /// Read files by read syscalls instead of memory mapping
+    #[structopt(long = "no-mmap")]
+    pub no_mmap: bool,
+
+    /// Read files under directories recursively
+    #[structopt(short = "r", long = "recursive")]
+    pub recursive: bool,
+
+    /// Follow symbolic links in directories read recursively
+    #[structopt(long = "follow-symlinks", requires = "recursive")]
+    pub follow_symlinks: bool,
+
+    /// Maximum depth of directories read recursively
+    #[structopt(long = "max-depth", requires = "recursive")]
+    pub max_depth: Option<usize>,
+
+    /// Show filename as header before each file or prefix of each line
+    #[structopt(
+        long = "filename",
+        default_value = "auto",
+        possible_value = "auto",
+        possible_value = "header",
+        possible_value = "prefix",
+        possible_value = "none"
+    )]
+    pub filename: String,
+
+    /// Skip files which can't be read because of permission
+    #[structopt(long = "skip-unreadable")]
+    pub skip_unreadable: bool,
+
+    /// Start from the last N lines of each file
+    #[structopt(short = "n", long = "lines")]
+    pub lines: Option<usize>,
+
+    /// Skip the first N lines of each file and stdin
+    #[structopt(long = "skip-lines", value_name = "N", default_value = "0")]
+    pub skip_lines: usize,
+
+    /// Skip the first N bytes of each file and stdin
+    #[structopt(long = "skip-bytes", value_name = "N", conflicts_with = "lines")]
+    pub skip_bytes: Option<u64>,
+
+    /// Alternate background color of successive lines
+    #[structopt(long = "zebra", value_name = "COLOR", require_equals = true)]
+    pub zebra: Option<Option<String>>,
+
+    /// Tint the background of lines by the source when multiple sources are given
+    #[structopt(long = "tint")]
+    pub tint: bool,
+
+    /// Handling of lines already containing color sequences
+    #[structopt(
+        long = "colored-input",
+        default_value = "colorize",
+        possible_value = "colorize",
+        possible_value = "skip",
+        possible_value = "strip"
+    )]
+    pub colored_input: String,
+
+    /// Line terminator of output
+    #[structopt(
+        long = "newline",
+        default_value = "keep",
+        possible_value = "keep",
+        possible_value = "lf",
+        possible_value = "crlf",
+        possible_value = "native"
+    )]
+    pub newline: String,
+
+    /// Don't add a newline to the last line without it
+    #[structopt(long = "no-final-newline")]
+    pub no_final_newline: bool,
+
+    /// Separate records by NUL instead of newline like the output of `find -print0`
+    #[structopt(short = "z", long = "null-data")]
+    pub null_data: bool,
+
+    /// Separate records by the byte like ';', '\t' or 0x1e instead of newline
+    #[structopt(
+        long = "delimiter",
+        value_name = "CHAR",
+        conflicts_with = "null-data",
+        parse(try_from_str = parse_delimiter)
+    )]
+    pub delimiter: Option<u8>,
+
+    /// Don't split lines at carriage returns not followed by a newline like progress bars
+    #[structopt(long = "no-split-cr")]
+    pub no_split_cr: bool,
+
+    /// Flush output per line, or when the buffer is full or input is idle
+    #[structopt(
+        long = "flush",
+        default_value = "auto",
+        possible_value = "auto",
+        possible_value = "line",
+        possible_value = "block"
+    )]
+    pub flush: String,
+
+    /// Colorize lines by N threads while reading and writing concurrently
+    #[structopt(short = "j", long = "jobs", value_name = "N", default_value = "1")]
+    pub jobs: usize,
+
+    /// Show the digest and the byte/line counts of each input at the end of it
+    #[structopt(
+        long = "digest",
+        value_name = "ALGORITHM",
+        possible_value = "sha256",
+        possible_value = "sha512"
+    )]
+    pub digest: Option<String>,
+
+    /// Cut lines longer than N characters with a marker of the hidden bytes
+    #[structopt(long = "truncate", value_name = "N")]
+    pub truncate: Option<usize>,
+
+    /// Append lines cut by --truncate to the file as is
+    #[structopt(
+        long = "truncate-log",
+        value_name = "PATH",
+        parse(from_os_str),
+        requires = "truncate"
+    )]
+    pub truncate_log: Option<PathBuf>,
+
+    /// Wrap matched text by markers in addition to color
+    #[structopt(long = "markers")]
+    pub markers: bool,
+
+    /// Dump lines around a match of trigger rules to an incident file in the directory
+    #[structopt(long = "incident-dir", value_name = "DIR", parse(from_os_str))]
+    pub incident_dir: Option<PathBuf>,
+
+    /// The number of lines kept before the trigger
+    #[structopt(long = "incident-before", value_name = "N", default_value = "100")]
+    pub incident_before: usize,
+
+    /// The number of lines dumped after the trigger
+    #[structopt(long = "incident-after", value_name = "M", default_value = "20")]
+    pub incident_after: usize,
+
+    /// Suppress lines identical to any of the last N lines
+    #[structopt(long = "dedupe-window", value_name = "N")]
+    pub dedupe_window: Option<usize>,
+
+    /// Prefix each line with the sortable key from the named capture and a tab unless output is a terminal
+    #[structopt(long = "emit-sort-key", value_name = "NAME")]
+    pub emit_sort_key: Option<String>,
+
+    /// Copy output to the file or tcp://HOST:PORT, with options like PATH,color=always,format=json
+    #[structopt(long = "tee", value_name = "SPEC", number_of_values = 1)]
+    pub tee: Vec<String>,
+
+    /// Append rule match counts of the run to the file
+    #[structopt(long = "stats-db", value_name = "PATH", parse(from_os_str))]
+    pub stats_db: Option<PathBuf>,
+
+    /// Output only lines matched by rules like grep
+    #[structopt(long = "only-matches")]
+    pub only_matches: bool,
+
+    /// Render lines matched by no rule faint (SGR 2) by 'dim', in the color by 'color:COLOR', or
+    /// hide them by 'hide'
+    #[structopt(long = "others", value_name = "dim|hide|color:COLOR")]
+    pub others: Option<String>,
+
+    /// Output only lines not matched by rules, or by the rule of the name
+    #[structopt(long = "invert", value_name = "NAME", require_equals = true)]
+    pub invert: Option<Option<String>>,
+
+    /// Output N lines after each matched line by --only-matches or rules with filter
+    #[structopt(short = "A", long = "after-context", value_name = "N")]
+    pub after_context: Option<usize>,
+
+    /// Output N lines before each matched line by --only-matches or rules with filter
+    #[structopt(short = "B", long = "before-context", value_name = "N")]
+    pub before_context: Option<usize>,
+
+    /// Output N lines before and after each matched line by --only-matches or rules with filter
+    #[structopt(short = "C", long = "context", value_name = "N")]
+    pub context: Option<usize>,
+
+    /// Print match counts of rules and throughput to stderr at the end of input or by SIGUSR1
+    #[structopt(long = "stats")]
+    pub stats: bool,
+
+    /// Exit with 0 if any line is matched by rules, 1 if not, or 2 by errors like grep
+    #[structopt(long = "match-status")]
+    pub match_status: bool,
+
+    /// Output nothing, and exit with 0 at the first line matched by rules like grep -q
+    #[structopt(short = "q", long = "quiet")]
+    pub quiet: bool,
+
+    /// Stop after N lines matched by rules
+    #[structopt(long = "max-count", value_name = "N")]
+    pub max_count: Option<usize>,
+
+    /// Stop after N lines are output
+    #[structopt(long = "max-lines", value_name = "N")]
+    pub max_lines: Option<usize>,
+
+    #[structopt(subcommand)]
+    pub subcommand: Option<SubCommand>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum SubCommand {
+    /// Show trends of rule match counts stored by --stats-db
+    #[structopt(name = "stats")]
+    Stats {
+        /// File stored by --stats-db
+        #[structopt(long = "stats-db", value_name = "PATH", parse(from_os_str))]
+        stats_db: PathBuf,
+
+        /// The number of the last runs to show
+        #[structopt(long = "last", default_value = "10")]
+        last: usize,
+    },
+
+    /// Check rules against a corpus of sample logs
+    #[structopt(name = "check")]
+    Check {
+        /// Report rules never matched and lines matching no rule in the files or directories
+        #[structopt(
+            long = "coverage",
+            value_name = "PATH",
+            required = true,
+            parse(from_os_str)
+        )]
+        coverage: Vec<PathBuf>,
+    },
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -78,227 +604,3399 @@ pub static DEFAULT_CONFIG: &'static str = r#"
     tokens = []
 "#;
 
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+const WAIT_INTERVAL: Duration = Duration::from_millis(200);
+const OUTPUT_BUFFER: usize = 64 * 1024;
+
+const LABEL_COLORS: [&str; 6] = ["Cyan", "Magenta", "Yellow", "Green", "Blue", "Red"];
+
+// Dark backgrounds distinguishable from each other for --tint
+const TINT_COLORS: [&str; 6] = [
+    "#0f3035", "#351035", "#35300f", "#0f3512", "#10183a", "#3a1010",
+];
+
 // -------------------------------------------------------------------------------------------------
-// Functions
+// Delimiter
 // -------------------------------------------------------------------------------------------------
 
-fn get_reader_file(path: &Path) -> Result<Box<dyn BufRead>> {
-    let f = File::open(path).context(format!("failed to open '{}'", path.to_string_lossy()))?;
-    Ok(Box::new(BufReader::new(f)))
+// The terminator of records by -z, --delimiter and --no-split-cr
+#[derive(Clone, Copy)]
+struct Delimiter {
+    byte: u8,
+    // A carriage return not followed by a newline also ends a record
+    split_cr: bool,
 }
 
-fn get_reader_stdin(timeout_millis: u64) -> Result<Box<dyn BufRead>> {
-    Ok(Box::new(BufReader::new(TimeoutReader::new(
-        stdin(),
-        Duration::from_millis(timeout_millis),
-    ))))
+impl Delimiter {
+    // Whether the record has the terminator
+    fn is_end(&self, buf: &[u8]) -> bool {
+        match buf.last() {
+            Some(&x) => x == self.byte || (self.split_cr && x == b'\r'),
+            None => false,
+        }
+    }
 }
 
-#[cfg(all(
-    target_os = "linux",
-    target_arch = "x86_64",
-    any(target_env = "gnu", target_env = "musl")
-))]
-fn get_reader_proc(pid: i32) -> Result<Box<dyn BufRead>> {
-    Ok(Box::new(BufReader::new(ProcReader::from_stdany(
-        pid as u32,
-    ))))
-}
+// -------------------------------------------------------------------------------------------------
+// Stats
+// -------------------------------------------------------------------------------------------------
 
-#[cfg(not(all(
-    target_os = "linux",
-    target_arch = "x86_64",
-    any(target_env = "gnu", target_env = "musl")
-)))]
-fn get_reader_proc(_pid: i32) -> Result<Box<dyn BufRead>> {
-    anyhow::bail!("--process option is supported on linux only")
+// The numbers of output lines for --max-count, --max-lines and --stats.
+// They are shared by the workers of --jobs.
+#[derive(Debug, Default)]
+pub struct Stats {
+    lines: AtomicUsize,
+    matches: AtomicUsize,
+    bytes: AtomicUsize,
 }
 
-fn get_config_path(opt: &Opt) -> Option<PathBuf> {
-    if let Some(ref p) = opt.config {
-        return Some(p.clone());
-    } else if let Some(mut p) = dirs::home_dir() {
-        p.push(".pipecolor.toml");
-        if p.exists() {
-            return Some(p);
+impl Stats {
+    // Count the output line
+    fn count_line(&self, matched: bool, bytes: usize) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        if matched {
+            self.matches.fetch_add(1, Ordering::Relaxed);
         }
     }
-    None
-}
 
-fn output(
-    reader: &mut dyn BufRead,
-    writer: &mut dyn Write,
-    use_color: bool,
-    config: &Config,
-    opt: &Opt,
-) -> Result<()> {
-    let mut buf = Vec::new();
-    loop {
-        match read_line_timeout(reader, &mut buf)? {
-            (0, false) => {
-                if opt.process.is_some() {
-                    continue;
-                } else {
-                    break;
-                }
-            }
-            (0, true) => continue,
-            (_, _) => {
-                let s = std::str::from_utf8(&buf);
-                match s {
-                    Ok(s) => {
-                        if use_color {
-                            let (s, i) = colorize(s.to_string(), config)?;
-                            if opt.verbose {
-                                if let Some(i) = i {
-                                    eprintln!(
-                                        "pipecolor: line matched to '{:?}'",
-                                        config.lines[i].pat
-                                    );
-                                }
-                            }
-                            let _ = writer.write(s.as_bytes());
-                        } else {
-                            let _ = writer.write(s.as_bytes());
-                        }
-                    }
-                    Err(_) => {
-                        let _ = writer.write(&buf);
-                    }
-                }
-                let _ = writer.flush();
-                buf.clear();
-            }
-        }
+    fn matches(&self) -> usize {
+        self.matches.load(Ordering::Relaxed)
+    }
+
+    // Whether the output reached --max-count or --max-lines
+    fn is_limit_reached(&self, opt: &Opt) -> bool {
+        let lines = self.lines.load(Ordering::Relaxed);
+        let matches = self.matches();
+        opt.max_lines.is_some_and(|x| lines >= x)
+            || opt.max_count.is_some_and(|x| matches >= x)
+            || (opt.quiet && matches > 0)
     }
-    Ok(())
 }
 
 // -------------------------------------------------------------------------------------------------
-// Main
+// Functions
 // -------------------------------------------------------------------------------------------------
 
-fn main() -> Result<()> {
-    let opt = Opt::from_args();
-    run_opt(&opt)
+#[cfg(windows)]
+fn enable_ansi_escape() {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+        STD_OUTPUT_HANDLE,
+    };
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) != 0 {
+            SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING);
+        }
+    }
 }
 
-fn run_opt(opt: &Opt) -> Result<()> {
-    let config = get_config_path(opt);
+#[cfg(not(windows))]
+fn enable_ansi_escape() {}
 
-    let config: Config = match config {
-        Some(c) => {
-            if opt.verbose {
-                eprintln!("pipecolor: Read config from '{}'", c.to_string_lossy());
-            }
-            let mut f =
-                File::open(&c).context(format!("failed to open '{}'", c.to_string_lossy()))?;
-            let mut s = String::new();
-            let _ = f.read_to_string(&mut s);
-            toml::from_str(&s).context(format!("failed to parse toml '{}'", c.to_string_lossy()))?
+// The PIDs specified by --process, or the newest one matched by --process-name
+fn get_pids(opt: &Opt) -> Result<Vec<i32>> {
+    let pattern = match opt.process_name {
+        Some(ref x) => x,
+        None => return Ok(opt.process.clone()),
+    };
+    let pid = loop {
+        match pidof::find_process(pattern)?.last() {
+            Some(x) => break *x,
+            None if opt.wait => thread::sleep(WAIT_INTERVAL),
+            None => anyhow::bail!("failed to find process matching '{}'", pattern),
         }
-        None => toml::from_str(DEFAULT_CONFIG).unwrap(),
     };
+    if opt.verbose {
+        eprintln!("pipecolor: attach to process {}", pid);
+    }
+    Ok(vec![pid])
+}
 
-    let use_color = match opt.mode.as_ref() {
-        "auto" => atty::is(Stream::Stdout),
-        "always" => true,
-        "disable" => false,
-        _ => true,
-    };
+// Open the source, or return None if it is skipped by --skip-unreadable
+fn open_source(
+    source: &dyn Source,
+    opt: &Opt,
+    skipped: &mut Vec<String>,
+) -> Result<Option<Box<dyn BufRead + Send>>> {
+    match source.open(opt) {
+        Ok(reader) => Ok(Some(reader)),
+        Err(e) if opt.skip_unreadable && is_permission_denied(&e) => {
+            eprint_notice(&format!("pipecolor: skipped '{}'", source.name()))?;
+            skipped.push(source.name());
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// Print the message dimly if stderr is a terminal
+fn eprint_notice(msg: &str) -> Result<()> {
+    if atty::is(Stream::Stderr) {
+        eprintln!("{}", paint(msg, "LightBlack", ColorDepth::Ansi16)?);
+    } else {
+        eprintln!("{}", msg);
+    }
+    Ok(())
+}
+
+fn is_permission_denied(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+}
 
-    let mut writer = BufWriter::new(stdout());
+// Config files found in the order of priority: project-local, XDG and home directory
+fn get_config_paths(opt: &Opt) -> Vec<PathBuf> {
+    if let Some(ref p) = opt.config {
+        return vec![p.clone()];
+    } else if opt.no_user_config {
+        return vec![];
+    }
+
+    let mut candidates = vec![PathBuf::from(".pipecolor.toml")];
+    if let Some(mut p) = dirs::config_dir() {
+        p.push("pipecolor");
+        p.push("pipecolor.toml");
+        candidates.push(p);
+    }
+    if let Some(mut p) = dirs::home_dir() {
+        p.push(".pipecolor.toml");
+        candidates.push(p);
+    }
+    candidates.into_iter().filter(|x| x.exists()).collect()
+}
 
-    if let Some(pid) = opt.process {
-        let mut reader = get_reader_proc(pid)?;
-        let _ = output(&mut *reader, writer.get_mut(), use_color, &config, &opt)?;
-    } else if opt.files.is_empty() {
-        let mut reader = get_reader_stdin(opt.timeout)?;
-        let _ = output(&mut *reader, writer.get_mut(), use_color, &config, &opt)?;
+// Config files in rules directories sorted by the file name in each directory.
+// The default directory is skipped if it doesn't exist, but --rules-dir must exist.
+fn get_rules_dir_paths(opt: &Opt) -> Result<Vec<PathBuf>> {
+    let dirs = if !opt.rules_dirs.is_empty() {
+        opt.rules_dirs.clone()
+    } else if opt.config.is_some() || opt.no_user_config {
+        return Ok(vec![]);
     } else {
-        for f in &opt.files {
-            let mut reader = get_reader_file(&f)?;
-            let _ = output(&mut *reader, writer.get_mut(), use_color, &config, &opt)?;
+        match dirs::config_dir() {
+            Some(p) if p.join("pipecolor/rules.d").is_dir() => vec![p.join("pipecolor/rules.d")],
+            _ => return Ok(vec![]),
         }
     };
 
-    Ok(())
+    let mut ret = Vec::new();
+    for dir in dirs {
+        let entries = std::fs::read_dir(&dir).context(format!(
+            "failed to read rules directory '{}'",
+            dir.to_string_lossy()
+        ))?;
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|x| x == "toml") && path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        ret.extend(paths);
+    }
+    Ok(ret)
 }
 
-// -------------------------------------------------------------------------------------------------
-// Test
-// -------------------------------------------------------------------------------------------------
+fn read_config(path: &Path) -> Result<Config> {
+    read_config_included(path, &mut Vec::new())
+}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// Read the config file and merge the included files into it.
+// The stack holds the files including it to detect circular includes.
+fn read_config_included(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Config> {
+    let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&key) {
+        anyhow::bail!("circular include of config '{}'", path.to_string_lossy());
+    }
 
-    #[test]
-    fn test_run() {
-        let args = vec![
-            "pipecolor",
-            "-c",
-            "sample/pipecolor.toml",
-            "sample/access_log",
-            "sample/maillog",
-        ];
-        let opt = Opt::from_iter(args.iter());
-        let ret = run_opt(&opt);
-        assert!(ret.is_ok());
+    let mut f = File::open(path).context(format!("failed to open '{}'", path.to_string_lossy()))?;
+    let mut s = String::new();
+    let _ = f.read_to_string(&mut s);
+    let mut config: Config =
+        toml::from_str(&s).context(format!("failed to parse toml '{}'", path.to_string_lossy()))?;
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let includes: Vec<_> = config
+        .include
+        .drain(..)
+        .chain(config.extends.take())
+        .collect();
+    stack.push(key);
+    for p in includes {
+        let c = read_config_included(&dir.join(p), stack).context(format!(
+            "failed to include from '{}'",
+            path.to_string_lossy()
+        ))?;
+        config.merge(c);
     }
+    stack.pop();
+    Ok(config)
+}
 
-    #[test]
-    fn test_verbose() {
-        let args = vec![
-            "pipecolor",
-            "-v",
-            "-c",
-            "sample/pipecolor.toml",
-            "sample/access_log",
-        ];
-        let opt = Opt::from_iter(args.iter());
-        let ret = run_opt(&opt);
-        assert!(ret.is_ok());
+// Load config files according to --config-precedence, and return the config and the used paths
+fn load_config(opt: &Opt) -> Result<(Config, Vec<PathBuf>)> {
+    let paths = get_config_paths(opt);
+    let (used, ignored) = if opt.config_precedence == "merge" {
+        (&paths[..], &paths[paths.len()..])
+    } else {
+        paths.split_at(paths.len().min(1))
+    };
+    let mut used = used.to_vec();
+    used.extend(get_rules_dir_paths(opt)?);
+
+    let mut config: Option<Config> = None;
+    for p in &used {
+        if opt.verbose {
+            eprintln!("pipecolor: Read config from '{}'", p.to_string_lossy());
+        }
+        let c = read_config(p)?;
+        match config {
+            Some(ref mut x) => x.merge(c),
+            None => config = Some(c),
+        }
+    }
+    if opt.verbose {
+        for p in ignored {
+            eprintln!("pipecolor: Ignored config '{}'", p.to_string_lossy());
+        }
     }
 
-    #[test]
-    fn test_mode() {
-        let args = vec![
-            "pipecolor",
+    let mut config = match config {
+        Some(x) => x,
+        None if !opt.rules.is_empty() || !opt.highlights.is_empty() => {
+            toml::from_str("lines = []").unwrap()
+        }
+        None => toml::from_str(DEFAULT_CONFIG).unwrap(),
+    };
+    select_rules(&mut config, opt)?;
+    let rules = opt
+        .rules
+        .iter()
+        .map(|x| Line::parse(x))
+        .collect::<Result<Vec<_>>>()?;
+    config.lines.splice(0..0, rules);
+    if opt.auto {
+        config.highlights.extend(auto::highlights(&config.styles)?);
+    }
+    for x in &opt.highlights {
+        config.highlights.push(colorize::parse_highlight(x)?);
+    }
+    config.ignore_case = opt.ignore_case;
+    config.others = match opt.others.as_deref() {
+        Some("dim") => Some(String::from("+Dim")),
+        Some("hide") | None => None,
+        Some(x) => match x.strip_prefix("color:") {
+            Some(color) if !color.is_empty() => Some(color.to_string()),
+            _ => anyhow::bail!("--others must be 'dim', 'hide' or 'color:COLOR': '{}'", x),
+        },
+    };
+    config.match_mode = match opt.match_mode.as_deref() {
+        Some("first") => Some(MatchMode::First),
+        Some("priority") => Some(MatchMode::Priority),
+        Some("longest") => Some(MatchMode::Longest),
+        Some("all") => Some(MatchMode::All),
+        _ => config.match_mode,
+    };
+    Ok((config, used))
+}
+
+// Load the config, and resolve it to colorize lines like `check --coverage` does
+fn prepare_config(opt: &Opt) -> Result<(Config, Vec<PathBuf>)> {
+    let (mut config, config_paths) = load_config(opt)?;
+    check_since(&config, opt)?;
+    check_invert(&config, opt)?;
+    if opt.syslog_udp.is_some() {
+        // The severity takes precedence over the other rules
+        let mut lines = syslog::rules(&config.styles)?;
+        lines.append(&mut config.lines);
+        config.lines = lines;
+    }
+    config.resolve_styles()?;
+    config.depth = colorize::detect_color_depth();
+    Ok((config, config_paths))
+}
+
+// Select the rules of config files by --only-rules, --skip-rules, --only-tags and --skip-tags
+fn select_rules(config: &mut Config, opt: &Opt) -> Result<()> {
+    let has_name = |name: &String, x: &Line| x.name.as_ref() == Some(name);
+    let has_tag = |tag: &String, x: &Line| x.tags.contains(tag);
+
+    let lines: Vec<_> = config
+        .lines
+        .iter()
+        .chain(config.stderr_lines.iter().flatten())
+        .collect();
+    for name in opt.only_rules.iter().chain(&opt.skip_rules) {
+        if !lines.iter().any(|x| has_name(name, x)) {
+            anyhow::bail!("no rule named '{}'", name);
+        }
+    }
+    for tag in opt.only_tags.iter().chain(&opt.skip_tags) {
+        if !lines.iter().any(|x| has_tag(tag, x)) {
+            anyhow::bail!("no rule tagged '{}'", tag);
+        }
+    }
+
+    let only = !opt.only_rules.is_empty() || !opt.only_tags.is_empty();
+    let selected = |x: &Line| {
+        let included = !only
+            || opt.only_rules.iter().any(|y| has_name(y, x))
+            || opt.only_tags.iter().any(|y| has_tag(y, x));
+        let excluded = opt.skip_rules.iter().any(|y| has_name(y, x))
+            || opt.skip_tags.iter().any(|y| has_tag(y, x));
+        included && !excluded
+    };
+    config.lines.retain(selected);
+    if let Some(ref mut x) = config.stderr_lines {
+        x.retain(selected);
+    }
+    Ok(())
+}
+
+fn get_use_color(opt: &Opt) -> bool {
+    get_use_color_stream(opt, Stream::Stdout)
+}
+
+fn get_use_color_stream(opt: &Opt, stream: Stream) -> bool {
+    resolve_use_color(
+        &opt.mode,
+        env::var_os("NO_COLOR"),
+        env::var_os("CLICOLOR_FORCE"),
+        atty::is(stream),
+    )
+}
+
+// Precedence: --mode always/disable > NO_COLOR > CLICOLOR_FORCE > tty detection
+fn resolve_use_color(
+    mode: &str,
+    no_color: Option<OsString>,
+    clicolor_force: Option<OsString>,
+    is_tty: bool,
+) -> bool {
+    match mode {
+        "always" => true,
+        "disable" => false,
+        _ => {
+            if no_color.is_some_and(|x| !x.is_empty()) {
+                false
+            } else if clicolor_force.is_some_and(|x| !x.is_empty() && x != "0") {
+                true
+            } else {
+                is_tty
+            }
+        }
+    }
+}
+
+// Read a record terminated by delim into buf, and return false at the end of the stream.
+// If follow is true, wait for the next line instead of the end of the stream.
+// The writer is flushed before waiting not to hold the output in the buffer.
+fn read_line(
+    reader: &mut dyn BufRead,
+    delim: Delimiter,
+    buf: &mut Vec<u8>,
+    follow: bool,
+    writer: &mut dyn Write,
+) -> Result<bool> {
+    loop {
+        // Input is not read anymore if the output is discarded
+        if sink::is_broken_pipe() {
+            return Ok(false);
+        }
+        match read_record_timeout(reader, delim.byte, delim.split_cr, buf)? {
+            (0, false) => {
+                if follow {
+                    let _ = writer.flush();
+                    thread::sleep(FOLLOW_INTERVAL);
+                    continue;
+                } else {
+                    return Ok(false);
+                }
+            }
+            (0, true) => {
+                let _ = writer.flush();
+                continue;
+            }
+            (_, false) if follow && !delim.is_end(buf) => {
+                // Wait for the rest of the line being written
+                let _ = writer.flush();
+                thread::sleep(FOLLOW_INTERVAL);
+                continue;
+            }
+            (_, _) => return Ok(true),
+        }
+    }
+}
+
+// Read a record of lines from a line matching record_start to the next one into buf.
+// The line read ahead is kept in next. The record ends when input is idle not to hold the
+// last record of live input.
+fn read_record(
+    reader: &mut dyn BufRead,
+    record_start: Option<&Regex>,
+    delim: Delimiter,
+    buf: &mut Vec<u8>,
+    next: &mut Vec<u8>,
+    follow: bool,
+    writer: &mut dyn Write,
+) -> Result<bool> {
+    let record_start = match record_start {
+        Some(x) => x,
+        None => return read_line(reader, delim, buf, follow, writer),
+    };
+    if !delim.is_end(next) && !read_line(reader, delim, next, follow, writer)? && next.is_empty() {
+        return Ok(false);
+    }
+    buf.append(next);
+    loop {
+        let (len, timeout) = read_record_timeout(reader, delim.byte, delim.split_cr, next)?;
+        // A partial line of live input is completed by the next call.
+        // Otherwise it is the last line of the stream.
+        let partial = !delim.is_end(next) && (follow || timeout);
+        if len == 0 || partial || record_start.is_match(&String::from_utf8_lossy(next)) {
+            return Ok(true);
+        }
+        buf.append(next);
+    }
+}
+
+// Parse --delimiter from a character, an escape like '\0' or '\t', or a hex byte like 0x1e
+fn parse_delimiter(s: &str) -> Result<u8> {
+    let ret = match s {
+        "\\0" => Some(0),
+        "\\t" => Some(b'\t'),
+        "\\n" => Some(b'\n'),
+        "\\r" => Some(b'\r'),
+        _ if s.len() == 1 => Some(s.as_bytes()[0]),
+        _ => s
+            .strip_prefix("0x")
+            .and_then(|x| u8::from_str_radix(x, 16).ok()),
+    };
+    ret.ok_or_else(|| {
+        anyhow::anyhow!(
+            "delimiter must be a single byte like ';', '\\0' or 0x1e: '{}'",
+            s
+        )
+    })
+}
+
+// The record delimiter by -z or --delimiter. Carriage returns split lines only.
+fn get_delimiter(opt: &Opt) -> Delimiter {
+    let byte = if opt.null_data {
+        0
+    } else {
+        opt.delimiter.unwrap_or(b'\n')
+    };
+    Delimiter {
+        byte,
+        split_cr: byte == b'\n' && !opt.no_split_cr,
+    }
+}
+
+fn get_newline(opt: &Opt) -> &'static [u8] {
+    match opt.newline.as_ref() {
+        "crlf" => b"\r\n",
+        "native" if cfg!(windows) => b"\r\n",
+        _ => b"\n",
+    }
+}
+
+// Replace the line terminator according to --newline
+fn convert_newline<'a>(buf: &'a [u8], opt: &Opt) -> Cow<'a, [u8]> {
+    if opt.newline == "keep" || !buf.ends_with(b"\n") {
+        return Cow::Borrowed(buf);
+    }
+    let body = buf.strip_suffix(b"\n").unwrap();
+    let body = body.strip_suffix(b"\r").unwrap_or(body);
+    let mut ret = body.to_vec();
+    ret.extend_from_slice(get_newline(opt));
+    Cow::Owned(ret)
+}
+
+// Add a newline, or the delimiter of records, at the end of input if the last line doesn't have it
+fn finish_newline(last: &[u8], writer: &mut dyn Write, opt: &Opt) {
+    // The line updated by carriage returns is left as is
+    let delim = get_delimiter(opt).byte;
+    if !opt.no_final_newline && !last.is_empty() && last.last() != Some(&delim) {
+        if delim == b'\n' {
+            let _ = writer.write(get_newline(opt));
+        } else {
+            let _ = writer.write(&[delim]);
+        }
+        flush_line(writer, opt);
+    }
+}
+
+// Resolve --flush auto to line on a terminal or for live input, otherwise block
+fn resolve_flush(opt: &mut Opt) {
+    if opt.flush != "auto" {
+        return;
+    }
+    let live = opt.follow
+        || opt.follow_name
+        || opt.reopen_fifo
+        || !opt.process.is_empty()
+        || opt.process_name.is_some()
+        || !opt.listen.is_empty()
+        || !opt.listen_unix.is_empty()
+        || opt.syslog_udp.is_some()
+        || opt.serial.is_some();
+    opt.flush = if live || atty::is(Stream::Stdout) {
+        String::from("line")
+    } else {
+        String::from("block")
+    };
+}
+
+// Flush the line unless --flush block.
+// The block mode leaves it to the buffered writer, and flushes when input is idle.
+fn flush_line(writer: &mut dyn Write, opt: &Opt) {
+    if opt.flush != "block" {
+        let _ = writer.flush();
+    }
+}
+
+fn output_line(
+    buf: &[u8],
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    count: usize,
+    line_output: LineOutput,
+) -> Result<()> {
+    let delim = get_delimiter(opt);
+    let bg = get_zebra_bg(opt, count).or(line_output.tint);
+    let converted;
+    let (buf, end) = match buf.split_last() {
+        // The delimiter, or the carriage return of an updated line, is written after the
+        // colorized record as is
+        Some((x, body)) if *x != b'\n' && delim.is_end(buf) => (body, &buf[body.len()..]),
+        _ if delim.byte == b'\n' => {
+            converted = convert_newline(buf, opt);
+            (&*converted, &[][..])
+        }
+        _ => (buf, &[][..]),
+    };
+    let mut rule = None;
+    let s = std::str::from_utf8(buf);
+    match s {
+        Ok(s) => {
+            let colored = has_sgr(s);
+            let s = if colored && opt.colored_input == "strip" {
+                Cow::Owned(strip_sgr(s))
+            } else {
+                Cow::Borrowed(s)
+            };
+            if use_color && !(colored && opt.colored_input == "skip") {
+                let (ret, i) = colorize_with_bg(&s, config, bg)?;
+                notify_match(i, &s, config, opt)?;
+                rule = i;
+                let ret = truncate_line(ret, buf, use_color, config, opt)?;
+                let _ = writer.write(ret.as_bytes());
+            } else {
+                let s = truncate_line(s, buf, false, config, opt)?;
+                let _ = writer.write(s.as_bytes());
+            }
+        }
+        Err(_) if use_color => {
+            // Invalid bytes are passed through as is with colors around them
+            let (s, i) = colorize_bytes(buf, config, bg)?;
+            notify_match(i, &String::from_utf8_lossy(buf), config, opt)?;
+            rule = i;
+            let _ = writer.write(&s);
+        }
+        Err(_) => {
+            let _ = writer.write(buf);
+        }
+    }
+    let _ = writer.write(end);
+    flush_line(writer, opt);
+    line_output
+        .stats
+        .count_line(rule.is_some(), buf.len() + end.len());
+    Ok(())
+}
+
+// How the line is handled by filter mode
+#[derive(Debug, PartialEq)]
+enum Select {
+    Output,
+    // Not matched by --only-matches or rules with filter, but can be output as the context
+    Filtered,
+    // Matched by rules dropping lines
+    Dropped,
+}
+
+fn select_line(buf: &[u8], config: &Config, opt: &Opt) -> Select {
+    let filter = config.lines.iter().any(|x| x.filter);
+    let drop = config.lines.iter().any(|x| x.action == Action::Drop);
+    let only_matches = opt.only_matches || opt.others.as_deref() == Some("hide");
+    if !only_matches && !filter && !drop && opt.invert.is_none() {
+        return Select::Output;
+    }
+    let rule =
+        colorize::match_rule(&String::from_utf8_lossy(buf), config).map(|i| &config.lines[i]);
+    if rule.is_some_and(|x| x.action == Action::Drop) {
+        return Select::Dropped;
+    }
+    let matched = match opt.invert {
+        // Only the named rule is inverted
+        Some(Some(ref name)) => rule.is_some_and(|x| x.name.as_ref() == Some(name)),
+        _ if filter => rule.is_some_and(|x| x.filter),
+        _ => rule.is_some(),
+    };
+    let filtering = only_matches || filter || opt.invert.is_some();
+    if filtering && matched == opt.invert.is_some() {
+        Select::Filtered
+    } else {
+        Select::Output
+    }
+}
+
+fn check_invert(config: &Config, opt: &Opt) -> Result<()> {
+    if let Some(Some(ref name)) = opt.invert {
+        if !config.lines.iter().any(|x| x.name.as_ref() == Some(name)) {
+            anyhow::bail!("--invert: no rule named '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+fn get_context(opt: &Opt) -> Option<context::Context> {
+    let before = opt.before_context.or(opt.context).unwrap_or(0);
+    let after = opt.after_context.or(opt.context).unwrap_or(0);
+    if before == 0 && after == 0 {
+        None
+    } else {
+        Some(context::Context::new(before, after))
+    }
+}
+
+// Return whether the line is output by filter mode.
+// The lines before the matched line are output here, and groups of lines are separated by "--".
+fn check_filter(
+    buf: &[u8],
+    context: &mut Option<context::Context>,
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    line_output: LineOutput,
+) -> Result<bool> {
+    let context = match (select_line(buf, config, opt), context) {
+        (Select::Output, Some(x)) => x,
+        (Select::Output, None) => return Ok(true),
+        (Select::Filtered, Some(x)) => return Ok(x.push(buf)),
+        _ => return Ok(false),
+    };
+    let (separator, lines) = context.matched();
+    if separator {
+        let mark = if use_color {
+            paint("--", "Cyan", config.depth)?
+        } else {
+            String::from("--")
+        };
+        let _ = writer.write(mark.as_bytes());
+        let _ = writer.write(&[get_delimiter(opt).byte]);
+    }
+    for line in lines {
+        let _ = writer.write(line_output.prefix.as_bytes());
+        output_line(&line, writer, use_color, config, opt, 0, line_output)?;
+    }
+    Ok(true)
+}
+
+// Print the report by --stats
+fn write_report(config: &Config, stats: &Stats) -> Result<()> {
+    let lines = stats.lines.load(Ordering::Relaxed);
+    let bytes = stats.bytes.load(Ordering::Relaxed);
+    report::write(config, lines, bytes, report::elapsed(), &mut stderr())?;
+    Ok(())
+}
+
+// Print the report if it is requested by SIGUSR1
+fn check_report(config: &Config, opt: &Opt, stats: &Stats) -> Result<()> {
+    if opt.stats && report::is_requested() {
+        write_report(config, stats)?;
+    }
+    Ok(())
+}
+
+// Whether the line is partial or the rest of a partial line, which isn't colorized by --raw-partial
+fn is_raw_partial(buf: &[u8], partial: &mut bool, opt: &Opt) -> bool {
+    let rest = *partial;
+    *partial = !get_delimiter(opt).is_end(buf);
+    opt.raw_partial && (rest || *partial)
+}
+
+// The background color of odd lines by --zebra
+fn get_zebra_bg(opt: &Opt, count: usize) -> Option<&str> {
+    match opt.zebra {
+        Some(ref x) if count % 2 == 1 => Some(x.as_deref().unwrap_or("LightBlack")),
+        _ => None,
+    }
+}
+
+// Notify the matched rule if it is disabled by max_matches_total, or --verbose is specified
+fn notify_match(i: Option<usize>, s: &str, config: &Config, opt: &Opt) -> Result<()> {
+    let line = match i {
+        Some(i) => &config.lines[i],
+        None => return Ok(()),
+    };
+    let name = line.name.as_deref().unwrap_or_else(|| line.pat.as_str());
+    if line.is_exhausted() {
+        eprint_notice(&format!(
+            "pipecolor: rule '{}' reached max_matches_total and is disabled",
+            name
+        ))?;
+    }
+    if opt.verbose {
+        let mut msg = format!("pipecolor: matched rule '{}'", name);
+        if let Some(ref d) = line.description {
+            msg.push_str(&format!(" ({})", d));
+        }
+        let tokens: Vec<_> = line
+            .tokens
+            .iter()
+            .filter(|x| x.pat.is_match(s))
+            .map(|x| format!("'{}'", x.name.as_deref().unwrap_or_else(|| x.pat.as_str())))
+            .collect();
+        if !tokens.is_empty() {
+            msg.push_str(&format!(" with token {}", tokens.join(", ")));
+        }
+        eprintln!("{}", msg);
+    }
+    Ok(())
+}
+
+// Cut the line longer than --truncate, and append the marker with the number of hidden bytes.
+// The whole line is appended to --truncate-log.
+fn truncate_line<'a>(
+    s: Cow<'a, str>,
+    buf: &[u8],
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+) -> Result<Cow<'a, str>> {
+    let width = match opt.truncate {
+        Some(x) => x,
+        None => return Ok(s),
+    };
+    let len = s.trim_end_matches(&['\r', '\n'][..]).len();
+    let (mut body, hidden) = match truncate_sgr(&s[..len], width) {
+        Some(x) => x,
+        None => return Ok(s),
+    };
+
+    if let Some(ref path) = opt.truncate_log {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("failed to open '{}'", path.to_string_lossy()))?;
+        file.write_all(buf)?;
+        if !buf.ends_with(b"\n") {
+            file.write_all(b"\n")?;
+        }
+    }
+
+    let marker = format!("… (+{} bytes)", group_digits(hidden));
+    if use_color {
+        if has_sgr(&body) {
+            body.push_str("\u{1b}[m");
+        }
+        body.push_str(&paint(&marker, "LightBlack", config.depth)?);
+    } else {
+        body.push_str(&marker);
+    }
+    body.push_str(&s[len..]);
+    Ok(Cow::Owned(body))
+}
+
+// Format the number with thousands separators like 12,345
+fn group_digits(n: usize) -> String {
+    let s = n.to_string();
+    let mut ret = String::new();
+    for (i, c) in s.chars().enumerate() {
+        if i > 0 && (s.len() - i).is_multiple_of(3) {
+            ret.push(',');
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+// The background color of lines of the source by --tint
+fn get_tint(idx: usize, use_color: bool, opt: &Opt) -> Option<&'static str> {
+    if use_color && opt.tint {
+        Some(TINT_COLORS[idx % TINT_COLORS.len()])
+    } else {
+        None
+    }
+}
+
+// How lines of a source are output: the filename prefix, the background by --tint, and the stats
+// counting them
+#[derive(Clone, Copy)]
+struct LineOutput<'a> {
+    prefix: &'a str,
+    tint: Option<&'a str>,
+    stats: &'a Stats,
+}
+
+impl<'a> LineOutput<'a> {
+    fn new(stats: &'a Stats) -> Self {
+        LineOutput {
+            prefix: "",
+            tint: None,
+            stats,
+        }
+    }
+}
+
+fn output(
+    reader: &mut (dyn BufRead + Send),
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    follow: bool,
+    line_output: LineOutput,
+) -> Result<()> {
+    if opt.jobs > 1 && !follow && pipeline::is_supported(config, opt) {
+        return pipeline::output(reader, writer, use_color, config, opt, line_output);
+    }
+
+    // The buffers are reused for all lines
+    let mut buf = Vec::new();
+    let mut last = Vec::new();
+    let mut count = 0;
+    let mut dedupe = get_dedupe(config, opt);
+    let mut sort_key = get_sort_key(opt);
+    let mut recorder = get_recorder(opt);
+    let mut next = Vec::new();
+    let mut partial = false;
+    let mut since = get_since(config, opt);
+    let mut context = get_context(opt);
+    let record_start = config.record_start.as_ref();
+    let delim = get_delimiter(opt);
+    while !line_output.stats.is_limit_reached(opt)
+        && read_record(
+            reader,
+            record_start,
+            delim,
+            &mut buf,
+            &mut next,
+            follow,
+            writer,
+        )?
+    {
+        let skip = since.as_mut().is_some_and(|x| x.is_before(&buf))
+            || !check_filter(
+                &buf,
+                &mut context,
+                writer,
+                use_color,
+                config,
+                opt,
+                line_output,
+            )?;
+        if !skip
+            && !check_dedupe(
+                &buf,
+                &mut dedupe,
+                writer,
+                use_color,
+                config,
+                opt,
+                line_output,
+            )?
+        {
+            let use_color = use_color && !is_raw_partial(&buf, &mut partial, opt);
+            write_sort_key(&buf, &mut sort_key, writer, config);
+            let mut line_writer = RecordWriter::new(writer, recorder.as_mut());
+            let _ = line_writer.write(line_output.prefix.as_bytes());
+            output_line(
+                &buf,
+                &mut line_writer,
+                use_color,
+                config,
+                opt,
+                count,
+                line_output,
+            )?;
+            finish_record(line_writer, &buf, config)?;
+            // A partial line is returned because input is idle
+            if partial {
+                let _ = writer.flush();
+            }
+            count += 1;
+            std::mem::swap(&mut buf, &mut last);
+        }
+        buf.clear();
+        check_report(config, opt, line_output.stats)?;
+    }
+    finish_newline(&last, writer, opt);
+    finish_dedupe(&mut dedupe, writer, use_color, config, opt, line_output)
+}
+
+fn get_recorder(opt: &Opt) -> Option<Recorder> {
+    opt.incident_dir
+        .as_ref()
+        .map(|x| Recorder::new(x.clone(), opt.incident_before, opt.incident_after))
+}
+
+// Record the line written through the incident recorder, and notify if an incident is recorded
+fn finish_record(writer: RecordWriter, buf: &[u8], config: &Config) -> Result<()> {
+    if let Some(path) = writer.finish(|| is_trigger(buf, config))? {
+        eprint_notice(&format!(
+            "pipecolor: incident recorded to '{}'",
+            path.to_string_lossy()
+        ))?;
+    }
+    Ok(())
+}
+
+// Whether the line matches any trigger rule of the incident recorder
+fn is_trigger(buf: &[u8], config: &Config) -> bool {
+    let s = String::from_utf8_lossy(buf);
+    config.lines.iter().any(|x| x.trigger && x.pat.is_match(&s))
+}
+
+// The sort key is stripped on a terminal because it is for `sort` only
+fn get_sort_key(opt: &Opt) -> Option<SortKey> {
+    opt.emit_sort_key
+        .as_ref()
+        .filter(|_| !atty::is(Stream::Stdout))
+        .map(|x| SortKey::new(x))
+}
+
+fn write_sort_key(
+    buf: &[u8],
+    sort_key: &mut Option<SortKey>,
+    writer: &mut dyn Write,
+    config: &Config,
+) {
+    if let Some(x) = sort_key {
+        let _ = writer.write(format!("{}\t", x.get(buf, config)).as_bytes());
+    }
+}
+
+fn get_dedupe(config: &Config, opt: &Opt) -> Option<Dedupe> {
+    opt.dedupe_window
+        .map(|n| Dedupe::new(n, config.dedupe_strip.clone()))
+}
+
+// Logs of containers are filtered by --since of the daemon instead of the timestamp regex
+fn get_since(config: &Config, opt: &Opt) -> Option<Since> {
+    if !opt.docker.is_empty() || !opt.kube.is_empty() {
+        return None;
+    }
+    let now = docker::now();
+    let time = docker::parse_since(opt.since.as_ref()?, now).ok()?;
+    Some(Since::new(config.timestamp.clone()?, time, now))
+}
+
+fn check_since(config: &Config, opt: &Opt) -> Result<()> {
+    if let Some(ref x) = opt.since {
+        if opt.docker.is_empty() && opt.kube.is_empty() {
+            if config.timestamp.is_none() {
+                anyhow::bail!("--since requires --docker, --kube or timestamp in the config");
+            }
+            docker::parse_since(x, docker::now())?;
+        }
+    }
+    Ok(())
+}
+
+// Return whether the line is a duplicate in the dedupe window.
+// The repeat count of the line leaving the window is output.
+fn check_dedupe(
+    buf: &[u8],
+    dedupe: &mut Option<Dedupe>,
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    line_output: LineOutput,
+) -> Result<bool> {
+    let (duplicate, repeat) = match dedupe {
+        Some(x) => x.push(buf),
+        None => return Ok(false),
+    };
+    if let Some(repeat) = repeat {
+        output_repeat(repeat, writer, use_color, config, opt, line_output)?;
+    }
+    Ok(duplicate)
+}
+
+// Output the repeat counts of the lines remaining in the dedupe window
+fn finish_dedupe(
+    dedupe: &mut Option<Dedupe>,
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    line_output: LineOutput,
+) -> Result<()> {
+    if let Some(x) = dedupe {
+        for repeat in x.finish() {
+            output_repeat(repeat, writer, use_color, config, opt, line_output)?;
+        }
+    }
+    Ok(())
+}
+
+fn output_repeat(
+    repeat: Repeat,
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    line_output: LineOutput,
+) -> Result<()> {
+    let (line, n) = repeat;
+    let mark = format!("[{} more] ", n);
+    let mark = if use_color {
+        paint_with_bg(&mark, "Yellow", line_output.tint, config.depth)?
+    } else {
+        mark
+    };
+    let _ = writer.write(line_output.prefix.as_bytes());
+    let _ = writer.write(mark.as_bytes());
+    output_line(&line, writer, use_color, config, opt, 0, line_output)
+}
+
+fn get_label(source: &dyn Source, idx: usize, use_color: bool, config: &Config) -> Result<String> {
+    let label = format!("[{}]", source.name());
+    if use_color {
+        let color = LABEL_COLORS[idx % LABEL_COLORS.len()];
+        Ok(format!("{} ", paint(&label, color, config.depth)?))
+    } else {
+        Ok(format!("{} ", label))
+    }
+}
+
+fn get_banner(config_paths: &[PathBuf], config: &Config) -> String {
+    let path = if config_paths.is_empty() {
+        String::from("default")
+    } else {
+        let paths: Vec<_> = config_paths.iter().map(|x| x.to_string_lossy()).collect();
+        paths.join("', '")
+    };
+    let tokens: usize = config.lines.iter().map(|x| x.tokens.len()).sum();
+    format!(
+        "pipecolor: config '{}', {} line rules, {} token rules",
+        path,
+        config.lines.len(),
+        tokens
+    )
+}
+
+fn get_header(source: &dyn Source, use_color: bool, config: &Config) -> Result<String> {
+    let header = format!("==> {} <==", source.name());
+    if use_color {
+        Ok(format!(
+            "{}\n",
+            paint(&header, "LightWhite+Bold", config.depth)?
+        ))
+    } else {
+        Ok(format!("{}\n", header))
+    }
+}
+
+fn get_prefix(source: &dyn Source, use_color: bool, config: &Config) -> Result<String> {
+    if use_color {
+        Ok(format!(
+            "{}:",
+            paint(&source.name(), "Magenta", config.depth)?
+        ))
+    } else {
+        Ok(format!("{}:", source.name()))
+    }
+}
+
+// The digest and the byte/line counts of the source read through the reader
+fn get_footer(
+    source: &dyn Source,
+    summary: &Summary,
+    use_color: bool,
+    config: &Config,
+    tint: Option<&str>,
+) -> Result<String> {
+    let footer = format!(
+        "{}:{}  {} ({} bytes, {} lines)",
+        summary.algorithm,
+        summary.hex(),
+        source.name(),
+        group_digits(summary.bytes as usize),
+        group_digits(summary.lines() as usize)
+    );
+    if use_color {
+        Ok(format!(
+            "{}\n",
+            paint_with_bg(&footer, "Cyan", tint, config.depth)?
+        ))
+    } else {
+        Ok(format!("{}\n", footer))
+    }
+}
+
+// Wrap the reader to compute the digest of the input by --digest
+fn with_digest(
+    reader: Box<dyn BufRead + Send>,
+    opt: &Opt,
+) -> (Box<dyn BufRead + Send>, Option<Arc<Mutex<Summary>>>) {
+    match opt.digest {
+        Some(ref x) => {
+            let summary = Arc::new(Mutex::new(Summary::new(x)));
+            let reader = DigestReader::new(reader, summary.clone());
+            (Box::new(BufReader::new(reader)), Some(summary))
+        }
+        None => (reader, None),
+    }
+}
+
+// Transcode the input by --encoding after the digest is computed from the raw input
+fn decode(reader: Box<dyn BufRead + Send>, opt: &Opt) -> Box<dyn BufRead + Send> {
+    match opt.encoding.as_deref().map(encoding::get) {
+        Some(Ok(x)) => Box::new(BufReader::new(DecodeReader::new(reader, x))),
+        _ => reader,
+    }
+}
+
+type LineSender = mpsc::Sender<(usize, Result<Option<Vec<u8>>>)>;
+
+// Read lines in a thread, and send them with the index of the reader.
+// None is sent at the end of the stream.
+fn spawn_reader(
+    i: usize,
+    mut reader: Box<dyn BufRead + Send>,
+    record_start: Option<Regex>,
+    delim: Delimiter,
+    follow: bool,
+    tx: LineSender,
+) {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let mut next = Vec::new();
+        let record_start = record_start.as_ref();
+        loop {
+            // The receiver flushes its output by itself
+            let sink = &mut io::sink();
+            match read_record(
+                &mut *reader,
+                record_start,
+                delim,
+                &mut buf,
+                &mut next,
+                follow,
+                sink,
+            ) {
+                Ok(true) => {
+                    if tx.send((i, Ok(Some(std::mem::take(&mut buf))))).is_err() {
+                        break;
+                    }
+                }
+                Ok(false) => {
+                    let _ = tx.send((i, Ok(None)));
+                    break;
+                }
+                Err(e) => {
+                    let _ = tx.send((i, Err(e)));
+                    break;
+                }
+            }
+        }
+    });
+}
+
+// Run the command, and colorize its stdout and stderr to each stream.
+// The exit code of the command is returned.
+fn output_command(
+    command: &[String],
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    stats: &Stats,
+) -> Result<i32> {
+    let mut child = signal::spawn_group(Command::new(&command[0]).args(&command[1..]))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(format!("failed to execute '{}'", command[0]))?;
+    let forwarder = SignalForwarder::new(child.id())?;
+
+    let (tx, rx) = mpsc::channel();
+    let child_stdout = child.stdout.take().unwrap();
+    let child_stderr = child.stderr.take().unwrap();
+    let child_stdout = decode(get_reader_pipe(child_stdout, opt.flush_partial), opt);
+    let child_stderr = decode(get_reader_pipe(child_stderr, opt.flush_partial), opt);
+    let record_start = config.record_start.clone();
+    spawn_reader(
+        0,
+        child_stdout,
+        record_start.clone(),
+        get_delimiter(opt),
+        false,
+        tx.clone(),
+    );
+    spawn_reader(1, child_stderr, record_start, get_delimiter(opt), false, tx);
+
+    let use_color_err = get_use_color_stream(opt, Stream::Stderr);
+    let config_err = config.stderr_config();
+    let mut writer_err = stderr();
+    let mut count = [0, 0];
+    let mut partial = [false, false];
+    let mut dedupe = [get_dedupe(config, opt), get_dedupe(&config_err, opt)];
+    let mut since = [get_since(config, opt), get_since(config, opt)];
+    let mut context = [get_context(opt), get_context(opt)];
+    let mut recorder = get_recorder(opt);
+    let line_output = LineOutput::new(stats);
+    while !stats.is_limit_reached(opt) {
+        check_report(config, opt, stats)?;
+        let (i, buf) = match rx.recv_timeout(FOLLOW_INTERVAL) {
+            Ok(x) => x,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let _ = writer.flush();
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let (writer, use_color, config): (&mut dyn Write, bool, &Config) = if i == 0 {
+            (writer, use_color, config)
+        } else {
+            (&mut writer_err, use_color_err, &config_err)
+        };
+        match buf? {
+            Some(buf) if since[i].as_mut().is_some_and(|x| x.is_before(&buf)) => (),
+            Some(buf)
+                if !check_filter(
+                    &buf,
+                    &mut context[i],
+                    writer,
+                    use_color,
+                    config,
+                    opt,
+                    line_output,
+                )? => {}
+            Some(buf) => {
+                if !check_dedupe(
+                    &buf,
+                    &mut dedupe[i],
+                    writer,
+                    use_color,
+                    config,
+                    opt,
+                    line_output,
+                )? {
+                    let use_color = use_color && !is_raw_partial(&buf, &mut partial[i], opt);
+                    let mut line_writer = RecordWriter::new(writer, recorder.as_mut());
+                    output_line(
+                        &buf,
+                        &mut line_writer,
+                        use_color,
+                        config,
+                        opt,
+                        count[i],
+                        line_output,
+                    )?;
+                    finish_record(line_writer, &buf, config)?;
+                    count[i] += 1;
+                }
+            }
+            None => finish_dedupe(&mut dedupe[i], writer, use_color, config, opt, line_output)?,
+        }
+    }
+
+    if stats.is_limit_reached(opt) {
+        // The rest of the output is not needed
+        let _ = child.kill();
+        let _ = child.wait();
+        return Ok(0);
+    }
+
+    let status = child
+        .wait()
+        .context(format!("failed to wait '{}'", command[0]))?;
+    drop(forwarder);
+    Ok(signal::exit_code(status))
+}
+
+// Attach to the processes, and output lines with the PID label if multiple processes are attached
+fn output_processes(
+    pids: Vec<i32>,
+    attacher: Arc<dyn Attach>,
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    stats: &Stats,
+) -> Result<()> {
+    if opt.process_stream == "tag" {
+        if opt.follow_forks {
+            anyhow::bail!("--process-stream tag can't be used with --follow-forks");
+        }
+        let sources = get_stream_sources(pids, attacher)?;
+        output_multi(
+            sources,
+            writer,
+            use_color,
+            config,
+            opt,
+            stats,
+            &mut Vec::new(),
+        )
+    } else if pids.len() == 1 && !opt.follow_forks {
+        let sources = get_process_sources(pids, attacher);
+        let mut reader = decode(sources[0].open(opt)?, opt);
+        output(
+            &mut *reader,
+            writer,
+            use_color,
+            config,
+            opt,
+            false,
+            LineOutput::new(stats),
+        )?;
+        eprint_notice(&format!("pipecolor: {} exited", sources[0].name()))
+    } else {
+        let sources = get_process_sources(pids, attacher);
+        output_multi(
+            sources,
+            writer,
+            use_color,
+            config,
+            opt,
+            stats,
+            &mut Vec::new(),
+        )
+    }
+}
+
+// A source read concurrently by output_multi with its state, which is removed at the end of it
+struct MultiSource {
+    source: Box<dyn Source>,
+    label: String,
+    tint: Option<&'static str>,
+    dedupe: Option<Dedupe>,
+    since: Option<Since>,
+    context: Option<context::Context>,
+    sort_key: Option<SortKey>,
+    digest: Option<Arc<Mutex<Summary>>>,
+}
+
+// Start reading the source in a thread, and return the state of it
+fn start_source(
+    i: usize,
+    source: Box<dyn Source>,
+    reader: Box<dyn BufRead + Send>,
+    tx: &LineSender,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+) -> Result<MultiSource> {
+    let label = get_label(&*source, i, use_color, config)?;
+    let (reader, digest) = with_digest(reader, opt);
+    spawn_reader(
+        i,
+        decode(reader, opt),
+        config.record_start.clone(),
+        get_delimiter(opt),
+        source.can_follow(),
+        tx.clone(),
+    );
+    Ok(MultiSource {
+        source,
+        label,
+        tint: get_tint(i, use_color, opt),
+        dedupe: get_dedupe(config, opt),
+        since: get_since(config, opt),
+        context: get_context(opt),
+        sort_key: get_sort_key(opt),
+        digest,
+    })
+}
+
+// Follow all files concurrently, and output lines prefixed by the file label as they arrive
+fn output_multi(
+    sources: Vec<Box<dyn Source>>,
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    stats: &Stats,
+    skipped: &mut Vec<String>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    // Keep the sender to add child processes or connections later
+    let listen = sources.iter().any(|x| x.kind() == SourceKind::Listener);
+    let mut next = sources.len();
+    // The sources being read by the index
+    let mut states = BTreeMap::new();
+    for (i, source) in sources.into_iter().enumerate() {
+        if let Some(reader) = open_source(&*source, opt, skipped)? {
+            let state = start_source(i, source, reader, &tx, use_color, config, opt)?;
+            states.insert(i, state);
+        }
+    }
+
+    let tx = if opt.follow_forks || listen {
+        Some(tx)
+    } else {
+        drop(tx);
+        None
+    };
+
+    let mut count = 0;
+    let mut recorder = get_recorder(opt);
+    let mut last_poll = Instant::now();
+    while !stats.is_limit_reached(opt) {
+        check_report(config, opt, stats)?;
+        let received = rx.recv_timeout(FOLLOW_INTERVAL);
+        if let Some(ref tx) = tx {
+            // Poll periodically even if lines are received continuously
+            if last_poll.elapsed() >= FOLLOW_INTERVAL {
+                last_poll = Instant::now();
+                let children: Vec<_> = states.values().flat_map(|x| x.source.children()).collect();
+                for child in children {
+                    if states.values().any(|x| x.source.key() == child.key()) {
+                        continue;
+                    }
+                    // The child may exit before attaching
+                    if let Ok(reader) = child.open(opt) {
+                        if opt.verbose {
+                            eprintln!("pipecolor: start reading {}", child.name());
+                        }
+                        let state = start_source(next, child, reader, tx, use_color, config, opt)?;
+                        states.insert(next, state);
+                        next += 1;
+                    }
+                }
+            }
+        }
+        let (i, buf) = match received {
+            Ok(x) => x,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let _ = writer.flush();
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let buf = match buf? {
+            Some(x) => x,
+            None => {
+                if let Some(state) = states.remove(&i) {
+                    finish_source(state, writer, use_color, config, opt, stats)?;
+                }
+                if states.is_empty() {
+                    break;
+                }
+                continue;
+            }
+        };
+        let state = match states.get_mut(&i) {
+            Some(x) => x,
+            None => continue,
+        };
+        let line_output = LineOutput {
+            prefix: &state.label,
+            tint: state.tint,
+            stats,
+        };
+        if state.since.as_mut().is_some_and(|x| x.is_before(&buf))
+            || !check_filter(
+                &buf,
+                &mut state.context,
+                writer,
+                use_color,
+                config,
+                opt,
+                line_output,
+            )?
+            || check_dedupe(
+                &buf,
+                &mut state.dedupe,
+                writer,
+                use_color,
+                config,
+                opt,
+                line_output,
+            )?
+        {
+            continue;
+        }
+        write_sort_key(&buf, &mut state.sort_key, writer, config);
+        let mut line_writer = RecordWriter::new(writer, recorder.as_mut());
+        let _ = line_writer.write(line_output.prefix.as_bytes());
+        output_line(
+            &buf,
+            &mut line_writer,
+            use_color,
+            config,
+            opt,
+            count,
+            line_output,
+        )?;
+        // Lines of other files follow the line
+        finish_newline(&buf, &mut line_writer, opt);
+        finish_record(line_writer, &buf, config)?;
+        count += 1;
+    }
+    Ok(())
+}
+
+// Output the end of the source read by output_multi, and run the on_eof hook
+fn finish_source(
+    mut state: MultiSource,
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    stats: &Stats,
+) -> Result<()> {
+    let line_output = LineOutput {
+        prefix: &state.label,
+        tint: state.tint,
+        stats,
+    };
+    finish_dedupe(
+        &mut state.dedupe,
+        writer,
+        use_color,
+        config,
+        opt,
+        line_output,
+    )?;
+    let source = &*state.source;
+    if source.kind() == SourceKind::Process {
+        eprint_notice(&format!("pipecolor: {} exited", source.name()))?;
+    } else if source.kind() == SourceKind::Container {
+        eprint_notice(&format!("pipecolor: {} stopped", source.name()))?;
+    } else if source.kind() == SourceKind::Connection && opt.verbose {
+        eprintln!("pipecolor: {} disconnected", source.name());
+    }
+    if let Some(ref x) = state.digest {
+        let summary = x.lock().unwrap();
+        let footer = get_footer(source, &summary, use_color, config, state.tint)?;
+        let _ = writer.write(footer.as_bytes());
+    }
+    run_on_eof(source, writer, use_color, config, opt, line_output)
+}
+
+// Run the on_eof hook of the config when the source finishes
+fn run_on_eof(
+    source: &dyn Source,
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    line_output: LineOutput,
+) -> Result<()> {
+    let on_eof = match config.on_eof {
+        Some(ref x) => x,
+        None => return Ok(()),
+    };
+    if let Some(ref print) = on_eof.print {
+        output_line(
+            format!("{}\n", print).as_bytes(),
+            writer,
+            use_color,
+            config,
+            opt,
+            0,
+            line_output,
+        )?;
+    }
+    if let Some(ref exec) = on_eof.exec {
+        let (shell, flag) = if cfg!(windows) {
+            ("cmd", "/C")
+        } else {
+            ("sh", "-c")
+        };
+        let status = Command::new(shell)
+            .arg(flag)
+            .arg(exec)
+            .env("PIPECOLOR_SOURCE", source.name())
+            .status()
+            .context(format!("failed to execute '{}'", exec))?;
+        if !status.success() && opt.verbose {
+            eprintln!("pipecolor: '{}' exited with {}", exec, status);
+        }
+    }
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+// Main
+// -------------------------------------------------------------------------------------------------
+
+fn main() -> Result<()> {
+    let mut opt = Opt::from_args();
+    resolve_flush(&mut opt);
+    let code = match run_opt(&opt) {
+        // The reader of the output exited like `head`
+        Err(e) if is_broken_pipe(&e) => 0,
+        // The wrapped command is killed by SIGPIPE after pipecolor stops
+        _ if sink::is_broken_pipe() => 0,
+        Err(e) if opt.match_status || opt.quiet => {
+            eprintln!("Error: {:?}", e);
+            2
+        }
+        x => x?,
+    };
+    if code != 0 {
+        process::exit(code);
+    }
+    Ok(())
+}
+
+// Whether the error is caused by writing to the closed pipe
+fn is_broken_pipe(e: &anyhow::Error) -> bool {
+    sink::is_broken_pipe()
+        || e.chain().any(|x| {
+            x.downcast_ref::<io::Error>()
+                .is_some_and(|x| x.kind() == io::ErrorKind::BrokenPipe)
+        })
+}
+
+// Return the exit code
+fn run_opt(opt: &Opt) -> Result<i32> {
+    if let Some(ref x) = opt.encoding {
+        encoding::get(x)?;
+    }
+
+    if let Some(SubCommand::Stats { stats_db, last }) = &opt.subcommand {
+        let records = stats::load(stats_db)?;
+        stats::show(&records, *last, &mut stdout())?;
+        return Ok(0);
+    }
+
+    if let Some(SubCommand::Check { coverage }) = &opt.subcommand {
+        let (config, _) = prepare_config(opt)?;
+        return check_coverage(coverage, &config, opt);
+    }
+
+    let (mut config, config_paths) = prepare_config(opt)?;
+
+    if config.banner && atty::is(Stream::Stderr) {
+        let banner = get_banner(&config_paths, &config);
+        eprintln!("{}", paint(&banner, "Cyan", config.depth)?);
+    }
+
+    let mut use_color = get_use_color(opt);
+    if use_color {
+        enable_ansi_escape();
+    }
+
+    let mut sinks = Vec::new();
+    for x in &opt.tee {
+        sinks.push(sink::open_sink(x)?);
+    }
+    let stdout_color = use_color;
+    if sinks.iter().any(|x| x.color()) {
+        // Colorize once, and sinks without color strip it
+        use_color = true;
+    }
+    if !opt.quiet {
+        sinks.insert(
+            0,
+            Box::new(WriterSink::new(
+                BufWriter::with_capacity(OUTPUT_BUFFER, PipeWriter::new(stdout())),
+                stdout_color,
+                sink::Format::Text,
+            )) as Box<dyn Sink>,
+        );
+    }
+
+    config.show_markers = opt.markers;
+    let count = opt.stats
+        || opt.stats_db.is_some()
+        || opt.max_count.is_some()
+        || opt.match_status
+        || opt.quiet;
+    if (opt.markers || count) && !use_color {
+        // Rules are applied to output markers or count matches only
+        config.depth = ColorDepth::Dumb;
+        use_color = true;
+    }
+
+    if opt.stats {
+        report::init()?;
+    }
+
+    let mut writer = Fanout::new(sinks);
+    let stats = Stats::default();
+    let mut skipped = Vec::new();
+    let mut code = 0;
+    let pids = get_pids(opt)?;
+
+    if !opt.command.is_empty() {
+        code = output_command(&opt.command, &mut writer, use_color, &config, opt, &stats)?;
+    } else if !pids.is_empty() {
+        output_processes(
+            pids,
+            Arc::new(PtraceAttach),
+            &mut writer,
+            use_color,
+            &config,
+            opt,
+            &stats,
+        )?;
+    } else {
+        let sources = get_sources(opt)?;
+        if opt.tui {
+            return tui::run(sources, &config, opt).map(|_| 0);
+        }
+        // Logs of containers are merged with labels because they are output concurrently
+        let merge = opt.follow
+            || opt.follow_name
+            || sources.iter().any(|x| x.kind() == SourceKind::Container);
+        let listen = sources.iter().any(|x| x.kind() == SourceKind::Listener);
+        if listen || (merge && sources.len() > 1) {
+            output_multi(
+                sources,
+                &mut writer,
+                use_color,
+                &config,
+                opt,
+                &stats,
+                &mut skipped,
+            )?;
+        } else {
+            let filename = match opt.filename.as_ref() {
+                "auto" if sources.len() > 1 => "header",
+                "auto" => "none",
+                x => x,
+            };
+            for (i, source) in sources.iter().enumerate() {
+                if stats.is_limit_reached(opt) {
+                    break;
+                }
+                let follow = (opt.follow || opt.follow_name) && source.can_follow();
+                let reader = match open_source(&**source, opt, &mut skipped)? {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let (reader, digest) = with_digest(reader, opt);
+                let mut reader = decode(reader, opt);
+                let tint = if sources.len() > 1 {
+                    get_tint(i, use_color, opt)
+                } else {
+                    None
+                };
+                if filename == "header" {
+                    let header = get_header(&**source, use_color, &config)?;
+                    let _ = writer.write(header.as_bytes());
+                }
+                let prefix = if filename == "prefix" {
+                    get_prefix(&**source, use_color, &config)?
+                } else {
+                    String::new()
+                };
+                let line_output = LineOutput {
+                    prefix: &prefix,
+                    tint,
+                    stats: &stats,
+                };
+                let _ = output(
+                    &mut *reader,
+                    &mut writer,
+                    use_color,
+                    &config,
+                    opt,
+                    follow,
+                    line_output,
+                )?;
+                if let Some(x) = digest {
+                    let summary = x.lock().unwrap();
+                    let footer = get_footer(&**source, &summary, use_color, &config, tint)?;
+                    let _ = writer.write(footer.as_bytes());
+                }
+                run_on_eof(&**source, &mut writer, use_color, &config, opt, line_output)?;
+            }
+        }
+    };
+
+    if !skipped.is_empty() {
+        eprintln!(
+            "pipecolor: skipped {} unreadable file(s): {}",
+            skipped.len(),
+            skipped.join(", ")
+        );
+    }
+
+    if let Some(ref path) = opt.stats_db {
+        stats::append(path, &get_stats_record(&config))?;
+    }
+    if opt.stats {
+        let _ = writer.flush();
+        write_report(&config, &stats)?;
+    }
+    if opt.match_status || opt.quiet {
+        code = if stats.matches() > 0 { 0 } else { 1 };
+    }
+
+    Ok(code)
+}
+
+// Return 1 if any rule is never matched or any line matches nothing
+fn check_coverage(paths: &[PathBuf], config: &Config, opt: &Opt) -> Result<i32> {
+    let mut walker = Walker::new(opt.follow_symlinks, opt.max_depth);
+    let mut coverage = Coverage::new(config);
+    for x in paths {
+        let files = if x.is_dir() {
+            walker.walk(x)?
+        } else {
+            vec![x.clone()]
+        };
+        for path in files {
+            let source = FileSource { path };
+            let mut reader = decode(source.open(opt)?, opt);
+            coverage.add(&source.name(), &mut *reader, config)?;
+        }
+    }
+    coverage.show(config, &mut stdout())?;
+    Ok(if coverage.is_complete() { 0 } else { 1 })
+}
+
+fn get_stats_record(config: &Config) -> stats::Record {
+    let matches = config
+        .lines
+        .iter()
+        .map(|x| stats::RuleCount {
+            rule: x.label(),
+            count: x.matches.get(),
+        })
+        .collect();
+    stats::Record::new(matches)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use source::FileSource;
+
+    #[test]
+    fn test_run() {
+        let args = vec![
+            "pipecolor",
+            "-c",
+            "sample/pipecolor.toml",
+            "sample/access_log",
+            "sample/maillog",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        let ret = run_opt(&opt);
+        assert!(ret.is_ok());
+    }
+
+    #[test]
+    fn test_verbose() {
+        let args = vec![
+            "pipecolor",
+            "-v",
+            "-c",
+            "sample/pipecolor.toml",
+            "sample/access_log",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        let ret = run_opt(&opt);
+        assert!(ret.is_ok());
+    }
+
+    #[test]
+    fn test_mode() {
+        let args = vec![
+            "pipecolor",
+            "-m",
+            "always",
+            "-c",
+            "sample/pipecolor.toml",
+            "sample/access_log",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        let ret = run_opt(&opt);
+        assert!(ret.is_ok());
+
+        let args = vec![
+            "pipecolor",
+            "-m",
+            "auto",
+            "-c",
+            "sample/pipecolor.toml",
+            "sample/access_log",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        let ret = run_opt(&opt);
+        assert!(ret.is_ok());
+
+        let args = vec![
+            "pipecolor",
+            "-m",
+            "disable",
+            "-c",
+            "sample/pipecolor.toml",
+            "sample/access_log",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        let ret = run_opt(&opt);
+        assert!(ret.is_ok());
+    }
+
+    #[test]
+    fn test_resolve_use_color() {
+        let some = |x: &str| Some(OsString::from(x));
+        assert!(resolve_use_color("auto", None, None, true));
+        assert!(!resolve_use_color("auto", None, None, false));
+        assert!(!resolve_use_color("auto", some("1"), None, true));
+        assert!(resolve_use_color("auto", some(""), None, true));
+        assert!(resolve_use_color("auto", None, some("1"), false));
+        assert!(!resolve_use_color("auto", None, some("0"), false));
+        assert!(!resolve_use_color("auto", some("1"), some("1"), true));
+        assert!(resolve_use_color("always", some("1"), None, false));
+        assert!(!resolve_use_color("disable", None, some("1"), true));
+    }
+
+    #[test]
+    fn test_zebra() {
+        let args = vec![
+            "pipecolor",
             "-m",
             "always",
+            "--zebra",
             "-c",
             "sample/pipecolor.toml",
             "sample/access_log",
         ];
         let opt = Opt::from_iter(args.iter());
+        assert_eq!(opt.zebra, Some(None));
         let ret = run_opt(&opt);
         assert!(ret.is_ok());
 
+        let args = vec!["pipecolor", "--zebra=Blue", "sample/access_log"];
+        let opt = Opt::from_iter(args.iter());
+        assert_eq!(opt.zebra, Some(Some(String::from("Blue"))));
+    }
+
+    #[test]
+    fn test_tint() {
+        let args = ["pipecolor", "--tint", "a.log", "b.log"];
+        let opt = Opt::from_iter(args.iter());
+        assert_eq!(get_tint(0, false, &opt), None);
+        assert_eq!(get_tint(1, true, &opt), Some(TINT_COLORS[1]));
+        assert_eq!(get_tint(6, true, &opt), Some(TINT_COLORS[0]));
+        let opt = Opt::from_iter(["pipecolor", "a.log", "b.log"].iter());
+        assert_eq!(get_tint(0, true, &opt), None);
+
+        let config: Config = toml::from_str("lines = []").unwrap();
+        let opt = Opt::from_iter(["pipecolor", "--tint", "--zebra"].iter());
+        let mut reader = BufReader::new(&b"a\nb\n"[..]);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            true,
+            &config,
+            &opt,
+            false,
+            LineOutput {
+                tint: Some("Blue"),
+                ..LineOutput::new(&Stats::default())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "\u{1b}[48;5;4ma\u{1b}[49m\n\u{1b}[48;5;8mb\u{1b}[49m\n"
+        );
+
+        // Context lines and repeat counts of --dedupe-window are tinted too
+        let config: Config = toml::from_str("[[lines]]\npat = 'b'\ncolors = ['Red']").unwrap();
+        let args = [
+            "pipecolor",
+            "--only-matches",
+            "-B",
+            "1",
+            "--dedupe-window",
+            "1",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        let mut reader = BufReader::new(&b"a\nb\nb\n"[..]);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            true,
+            &config,
+            &opt,
+            false,
+            LineOutput {
+                tint: Some("Blue"),
+                ..LineOutput::new(&Stats::default())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "\u{1b}[48;5;4ma\u{1b}[49m\n\
+             \u{1b}[48;5;4m\u{1b}[38;5;1mb\u{1b}[39m\u{1b}[49m\n\
+             \u{1b}[48;5;4m\u{1b}[38;5;3m[1 more] \u{1b}[49m\u{1b}[39m\
+             \u{1b}[48;5;4m\u{1b}[38;5;1mb\u{1b}[39m\u{1b}[49m\n"
+        );
+    }
+
+    #[test]
+    fn test_lines() {
         let args = vec![
             "pipecolor",
-            "-m",
-            "auto",
+            "-n",
+            "3",
             "-c",
             "sample/pipecolor.toml",
             "sample/access_log",
+            "sample/maillog",
         ];
         let opt = Opt::from_iter(args.iter());
         let ret = run_opt(&opt);
         assert!(ret.is_ok());
+    }
+
+    #[test]
+    fn test_is_permission_denied() {
+        let e = io::Error::from(io::ErrorKind::PermissionDenied);
+        let e = anyhow::Error::new(e).context("failed to open 'test'");
+        assert!(is_permission_denied(&e));
+
+        let e = io::Error::from(io::ErrorKind::NotFound);
+        let e = anyhow::Error::new(e).context("failed to open 'test'");
+        assert!(!is_permission_denied(&e));
+    }
+
+    #[test]
+    fn test_pids() {
+        let args = vec!["pipecolor", "-p", "123", "-p", "456"];
+        let opt = Opt::from_iter(args.iter());
+        assert_eq!(get_pids(&opt).unwrap(), vec![123, 456]);
+
+        let args = vec!["pipecolor", "--process-name", "^pipecolor_not_found$"];
+        let opt = Opt::from_iter(args.iter());
+        assert!(get_pids(&opt).is_err());
+
+        let args = vec!["pipecolor", "--wait", "sample/access_log"];
+        assert!(Opt::from_iter_safe(args.iter()).is_err());
+    }
+
+    #[test]
+    fn test_recursive() {
+        let args = vec!["pipecolor", "-r", "--max-depth", "0", "sample"];
+        let opt = Opt::from_iter(args.iter());
+        let sources = get_sources(&opt).unwrap();
+        let names: Vec<_> = sources.iter().map(|x| PathBuf::from(x.name())).collect();
+        assert!(names.contains(&PathBuf::from("sample/access_log")));
+        assert!(names.contains(&PathBuf::from("sample/maillog")));
+    }
+
+    #[test]
+    fn test_on_eof() {
+        let config = r#"
+            on_eof = { exec = "test -n \"$PIPECOLOR_SOURCE\"", print = "==== done ====" }
+            [[lines]]
+                pat   = "(done)"
+                colors = ["Default", "Green"]
+        "#;
+        let config: Config = toml::from_str(config).unwrap();
+        let opt = Opt::from_iter(["pipecolor"].iter());
+        let source = FileSource {
+            path: PathBuf::from("sample/maillog"),
+        };
+        let mut out = Vec::new();
+        let stats = Stats::default();
+        let ret = run_on_eof(
+            &source,
+            &mut out,
+            true,
+            &config,
+            &opt,
+            LineOutput::new(&stats),
+        );
+        assert!(ret.is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "==== \u{1b}[38;5;2mdone\u{1b}[39m ====\n"
+        );
+    }
+
+    #[test]
+    fn test_glob() {
+        let args = vec!["pipecolor", "--filename", "prefix", "sample/*log"];
+        let opt = Opt::from_iter(args.iter());
+        let sources = get_sources(&opt).unwrap();
+        let names: Vec<_> = sources.iter().map(|x| PathBuf::from(x.name())).collect();
+        assert_eq!(
+            names,
+            vec![
+                PathBuf::from("sample/access_log"),
+                PathBuf::from("sample/maillog")
+            ]
+        );
+        let ret = run_opt(&opt);
+        assert!(ret.is_ok());
+    }
+
+    #[test]
+    fn test_filename() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let source = FileSource {
+            path: PathBuf::from("a.log"),
+        };
+        let ret = get_header(&source, false, &config).unwrap();
+        assert_eq!(ret, "==> a.log <==\n");
+        let ret = get_prefix(&source, false, &config).unwrap();
+        assert_eq!(ret, "a.log:");
+        let ret = get_prefix(&source, true, &config).unwrap();
+        assert_eq!(ret, "\u{1b}[38;5;5ma.log\u{1b}[39m:");
+    }
+
+    #[test]
+    fn test_banner() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        assert_eq!(
+            get_banner(&[], &config),
+            "pipecolor: config 'default', 3 line rules, 0 token rules"
+        );
+    }
+
+    #[test]
+    fn test_config_precedence() {
+        let args = vec!["pipecolor", "--no-user-config", "sample/access_log"];
+        let opt = Opt::from_iter(args.iter());
+        let (config, paths) = load_config(&opt).unwrap();
+        assert!(paths.is_empty());
+        assert_eq!(config.lines.len(), 3);
+
+        let mut config = read_config(Path::new("sample/pipecolor.toml")).unwrap();
+        config.merge(toml::from_str(DEFAULT_CONFIG).unwrap());
+        assert_eq!(config.lines.len(), 5);
+        assert_eq!(
+            config.lines[0].description.as_deref(),
+            Some("Apache access log")
+        );
+    }
+
+    #[test]
+    fn test_rules_dir() {
+        let dir = env::temp_dir().join(format!("pipecolor_test_rules_dir_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rule = |pat: &str| format!("[[lines]]\npat = \"{}\"\ncolors = [\"Red\"]\n", pat);
+        std::fs::write(dir.join("20-nginx.toml"), rule("nginx")).unwrap();
+        std::fs::write(dir.join("10-syslog.toml"), rule("syslog")).unwrap();
+        std::fs::write(dir.join("README"), "not a config").unwrap();
+
+        let dir_arg = dir.to_string_lossy().into_owned();
+        let args = ["pipecolor", "--no-user-config", "--rules-dir", &dir_arg];
+        let (config, paths) = load_config(&Opt::from_iter(args.iter())).unwrap();
+        assert_eq!(paths.len(), 2);
+        let pats: Vec<_> = config.lines.iter().map(|x| x.pat.as_str()).collect();
+        assert_eq!(pats, ["syslog", "nginx"]);
+
+        // Rules of config files precede rules of the directory
+        let args = [
+            "pipecolor",
+            "-c",
+            "sample/pipecolor.toml",
+            "--rules-dir",
+            &dir_arg,
+        ];
+        let (config, paths) = load_config(&Opt::from_iter(args.iter())).unwrap();
+        assert_eq!(paths[0], Path::new("sample/pipecolor.toml"));
+        assert_eq!(config.lines.len(), 4);
+        assert_eq!(config.lines[3].pat.as_str(), "nginx");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let args = ["pipecolor", "--rules-dir", &dir_arg];
+        assert!(load_config(&Opt::from_iter(args.iter())).is_err());
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = env::temp_dir().join(format!("pipecolor_test_include_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        std::fs::write(
+            dir.join("shared/base.toml"),
+            r#"
+            match_mode = "longest"
+            markers    = ["[", "]"]
+            [styles]
+            error = { fg = "Red" }
+            warn  = { fg = "Yellow" }
+            [[lines]]
+            pat    = "base"
+            colors = ["@error"]
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("shared/nginx.toml"),
+            "markers = [\"<\", \">\"]\n[[lines]]\npat = \"nginx\"\ncolors = [\"@warn\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("personal.toml"),
+            r#"
+            include = ["shared/nginx.toml"]
+            extends = "shared/base.toml"
+            [styles]
+            error = { fg = "Magenta" }
+            [[lines]]
+            pat    = "mine"
+            colors = ["@error"]
+            "#,
+        )
+        .unwrap();
+
+        // The including file precedes included files, and `extends` follows `include`
+        let config = read_config(&dir.join("personal.toml")).unwrap();
+        let pats: Vec<_> = config.lines.iter().map(|x| x.pat.as_str()).collect();
+        assert_eq!(pats, ["mine", "nginx", "base"]);
+        assert_eq!(config.styles["error"].fg.as_deref(), Some("Magenta"));
+        assert_eq!(config.styles["warn"].fg.as_deref(), Some("Yellow"));
+        assert_eq!(config.match_mode, Some(MatchMode::Longest));
+        let markers = (String::from("<"), String::from(">"));
+        assert_eq!(config.markers, Some(markers));
+
+        std::fs::write(
+            dir.join("shared/base.toml"),
+            "extends = \"../personal.toml\"\n",
+        )
+        .unwrap();
+        let err = read_config(&dir.join("personal.toml")).err().unwrap();
+        assert!(format!("{:#}", err).contains("circular include"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_inline_rules() {
+        let args = [
+            "pipecolor",
+            "--no-user-config",
+            "-e",
+            "ERROR:Red+bold",
+            "--rule",
+            r"(\d+):(\d+):Yellow,Cyan",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        let (mut config, _) = load_config(&opt).unwrap();
+        assert_eq!(config.lines.len(), 2);
+        assert_eq!(config.lines[1].pat.as_str(), r"(\d+):(\d+)");
+        assert_eq!(config.lines[1].colors, vec!["Yellow", "Cyan"]);
+        config.resolve_styles().unwrap();
+        let (ret, _) = colorize_with_bg("ERROR", &config, None).unwrap();
+        assert_eq!(
+            ret,
+            "\u{1b}[m\u{1b}[1m\u{1b}[38;5;1mERROR\u{1b}[m\u{1b}[39m"
+        );
+
+        // Inline rules precede rules of config files
+        let args = ["pipecolor", "-c", "sample/pipecolor.toml", "-e", "x:Red"];
+        let opt = Opt::from_iter(args.iter());
+        let (config, _) = load_config(&opt).unwrap();
+        assert_eq!(config.lines.len(), 3);
+        assert_eq!(config.lines[0].pat.as_str(), "x");
+
+        let args = ["pipecolor", "-e", "ERROR"];
+        assert!(load_config(&Opt::from_iter(args.iter())).is_err());
+        let args = ["pipecolor", "-e", "(:Red"];
+        assert!(load_config(&Opt::from_iter(args.iter())).is_err());
+    }
+
+    #[test]
+    fn test_select_rules() {
+        let config = r#"
+        [[lines]]
+            pat  = "a"
+            colors = ["Red"]
+            name = "nginx-access"
+            tags = ["http"]
+        [[lines]]
+            pat  = "b"
+            colors = ["Red"]
+            name = "nginx-error"
+            tags = ["http", "error"]
+        [[lines]]
+            pat  = "c"
+            colors = ["Red"]
+            tags = ["error"]
+        "#;
+        let check = |args: &[&str], expected: &[&str]| {
+            let mut config: Config = toml::from_str(config).unwrap();
+            select_rules(&mut config, &Opt::from_iter(args.iter())).unwrap();
+            let pats: Vec<_> = config.lines.iter().map(|x| x.pat.as_str()).collect();
+            assert_eq!(pats, expected);
+        };
+        check(&["pipecolor"], &["a", "b", "c"]);
+        check(&["pipecolor", "--only-tags", "http"], &["a", "b"]);
+        check(
+            &[
+                "pipecolor",
+                "--only-tags",
+                "http",
+                "--skip-rules",
+                "nginx-access",
+            ],
+            &["b"],
+        );
+        check(&["pipecolor", "--skip-tags", "http,error"], &[]);
+        check(
+            &[
+                "pipecolor",
+                "--only-rules",
+                "nginx-access",
+                "--only-tags",
+                "error",
+            ],
+            &["a", "b", "c"],
+        );
+
+        let mut config: Config = toml::from_str(config).unwrap();
+        let opt = Opt::from_iter(["pipecolor", "--skip-rules", "x"].iter());
+        assert!(select_rules(&mut config, &opt).is_err());
+        let opt = Opt::from_iter(["pipecolor", "--only-tags", "x"].iter());
+        assert!(select_rules(&mut config, &opt).is_err());
+    }
+
+    #[test]
+    fn test_dedupe_window() {
+        let config: Config = toml::from_str(
+            r#"
+            dedupe_strip = '^\d+ '
+
+            [[lines]]
+            pat = "Error"
+            colors = ["Red"]
+            "#,
+        )
+        .unwrap();
+        let args = vec!["pipecolor", "--dedupe-window", "2"];
+        let opt = Opt::from_iter(args.iter());
+        let input = b"1 Error\n2 Info\n3 Error\n4 Warn\n5 Other\n6 Warn\n";
+        let mut reader = BufReader::new(&input[..]);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            false,
+            &config,
+            &opt,
+            false,
+            LineOutput::new(&Stats::default()),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "1 Error\n2 Info\n4 Warn\n[1 more] 1 Error\n5 Other\n[1 more] 4 Warn\n"
+        );
+    }
+
+    #[test]
+    fn test_max_matches() {
+        let config: Config = toml::from_str(
+            r#"
+            [[lines]]
+            pat = "Error"
+            colors = ["Red"]
+            max_matches_per_line = 1
+            max_matches_total = 1
+            [[lines.tokens]]
+            pat = "a"
+            colors = ["Blue"]
+            [[lines.tokens]]
+            pat = "b"
+            colors = ["Green"]
+            "#,
+        )
+        .unwrap();
+        let args = vec!["pipecolor"];
+        let opt = Opt::from_iter(args.iter());
+        let input = b"Error ab\nError ab\n";
+        let mut reader = BufReader::new(&input[..]);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            true,
+            &config,
+            &opt,
+            false,
+            LineOutput::new(&Stats::default()),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "\u{1b}[38;5;1mError\u{1b}[39m \u{1b}[38;5;4ma\u{1b}[39mb\nError ab\n"
+        );
+        assert!(config.lines[0].is_exhausted());
+    }
+
+    #[test]
+    fn test_newline() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let input = b"a\r\nb\nc";
+        let check = |args: &[&str], expected: &str| {
+            let opt = Opt::from_iter(args.iter());
+            let mut reader = BufReader::new(&input[..]);
+            let mut writer = Vec::new();
+            output(
+                &mut reader,
+                &mut writer,
+                false,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            assert_eq!(String::from_utf8(writer).unwrap(), expected);
+        };
+        check(&["pipecolor"], "a\r\nb\nc\n");
+        check(&["pipecolor", "--newline", "lf"], "a\nb\nc\n");
+        check(&["pipecolor", "--newline", "crlf"], "a\r\nb\r\nc\r\n");
+        check(&["pipecolor", "--no-final-newline"], "a\r\nb\nc");
+    }
+
+    #[test]
+    fn test_flush_partial() {
+        // Input idle after the prompt
+        struct Prompt(Vec<io::Result<&'static [u8]>>);
+        impl Read for Prompt {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.0.pop() {
+                    Some(x) => x.and_then(|x| (&x[..]).read(buf)),
+                    None => Ok(0),
+                }
+            }
+        }
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let check = |args: &[&str], expected: &str| {
+            let opt = Opt::from_iter(args.iter());
+            let mut reader = BufReader::new(Prompt(vec![
+                Ok(b"Error: x\n"),
+                Ok(b"Error\n"),
+                Err(io::ErrorKind::TimedOut.into()),
+                Ok(b"Error: retry? "),
+            ]));
+            let mut writer = Vec::new();
+            output(
+                &mut reader,
+                &mut writer,
+                true,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            assert_eq!(String::from_utf8(writer).unwrap(), expected);
+        };
+        check(
+            &["pipecolor", "--flush-partial", "100"],
+            "\u{1b}[38;5;9mError\u{1b}[38;5;1m: retry? \u{1b}[39m\
+             \u{1b}[38;5;9mError\u{1b}[39m\n\
+             \u{1b}[38;5;9mError\u{1b}[38;5;1m: x\u{1b}[39m\n",
+        );
+        check(
+            &["pipecolor", "--flush-partial", "100", "--raw-partial"],
+            "Error: retry? Error\n\
+             \u{1b}[38;5;9mError\u{1b}[38;5;1m: x\u{1b}[39m\n",
+        );
+    }
+
+    #[test]
+    fn test_max_count() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let input = b"Error: a\nb\nError: c\nd\nError: e\n";
+        let check = |args: &[&str], expected: usize| {
+            let opt = Opt::from_iter(args.iter());
+            let mut reader = BufReader::new(&input[..]);
+            let mut writer = Vec::new();
+            output(
+                &mut reader,
+                &mut writer,
+                true,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            assert_eq!(writer.split(|x| *x == b'\n').count() - 1, expected);
+        };
+        check(&["pipecolor", "--max-count", "2"], 3);
+        check(&["pipecolor", "--max-lines", "2"], 2);
+        check(&["pipecolor", "--max-lines", "4", "--max-count", "1"], 1);
+        check(&["pipecolor", "--max-count", "0"], 0);
+    }
+
+    #[test]
+    fn test_since() {
+        let mut config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let input = b"1970-01-01Z a\n  b\n1970-01-02Z c\n  d\n1970-01-01Z e\n";
+        let opt = Opt::from_iter(["pipecolor", "--since", "86400"].iter());
+        assert!(check_since(&config, &opt).is_err());
+
+        config.timestamp =
+            Some(Regex::new(r"^(?P<year>\d+)-(?P<month>\d+)-(?P<day>\d+)(?P<offset>Z)").unwrap());
+        assert!(check_since(&config, &opt).is_ok());
+        let mut reader = BufReader::new(&input[..]);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            false,
+            &config,
+            &opt,
+            false,
+            LineOutput::new(&Stats::default()),
+        )
+        .unwrap();
+        assert_eq!(writer, b"1970-01-02Z c\n  d\n1970-01-01Z e\n");
+
+        let opt = Opt::from_iter(["pipecolor", "--since", "x"].iter());
+        assert!(check_since(&config, &opt).is_err());
+    }
+
+    #[test]
+    fn test_others() {
+        let check = |args: &[&str]| {
+            let opt = Opt::from_iter(args.iter());
+            load_config(&opt).map(|(x, _)| x.others)
+        };
+        let args = ["pipecolor", "--no-user-config", "--others"];
+        assert_eq!(
+            check(&[&args[..], &["dim"]].concat()).unwrap(),
+            Some(String::from("+Dim"))
+        );
+        assert_eq!(
+            check(&[&args[..], &["color:Blue"]].concat()).unwrap(),
+            Some(String::from("Blue"))
+        );
+        assert_eq!(check(&[&args[..], &["hide"]].concat()).unwrap(), None);
+        assert!(check(&[&args[..], &["grey"]].concat()).is_err());
+        assert!(check(&[&args[..], &["color:"]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_only_matches() {
+        let input = b"Error: a\nb\nWarning: c\nd\n";
+        let check = |config: &str, args: &[&str], expected: &[u8]| {
+            let config: Config = toml::from_str(config).unwrap();
+            let opt = Opt::from_iter(args.iter());
+            let mut reader = BufReader::new(&input[..]);
+            let mut writer = Vec::new();
+            output(
+                &mut reader,
+                &mut writer,
+                false,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            assert_eq!(writer, expected);
+        };
+        check(DEFAULT_CONFIG, &["pipecolor"], input);
+        check(
+            DEFAULT_CONFIG,
+            &["pipecolor", "--only-matches"],
+            b"Error: a\nWarning: c\n",
+        );
+        check(
+            DEFAULT_CONFIG,
+            &["pipecolor", "--others", "hide"],
+            b"Error: a\nWarning: c\n",
+        );
+        let config = r#"
+        [[lines]]
+            pat    = "Error"
+            colors = ["Red"]
+            filter = true
+        [[lines]]
+            pat    = "Warning"
+            colors = ["Yellow"]
+        "#;
+        check(config, &["pipecolor"], b"Error: a\n");
+        check(config, &["pipecolor", "--only-matches"], b"Error: a\n");
+
+        // Lines matched by drop rules are discarded even with --only-matches
+        let config = r#"
+        [[lines]]
+            pat    = "Warning"
+            action = "drop"
+        [[lines]]
+            pat    = "Error"
+            colors = ["Red"]
+        "#;
+        check(config, &["pipecolor"], b"Error: a\nb\nd\n");
+        check(config, &["pipecolor", "--only-matches"], b"Error: a\n");
+    }
+
+    #[test]
+    fn test_invert() {
+        let config = r#"
+        [[lines]]
+            pat    = "Error"
+            colors = ["Red"]
+            name   = "error"
+        [[lines]]
+            pat    = "Warning"
+            colors = ["Yellow"]
+        "#;
+        let config: Config = toml::from_str(config).unwrap();
+        let input = b"Error: a\nb\nWarning: c\nd\n";
+        let check = |args: &[&str], expected: &[u8]| {
+            let opt = Opt::from_iter(args.iter());
+            let mut reader = BufReader::new(&input[..]);
+            let mut writer = Vec::new();
+            output(
+                &mut reader,
+                &mut writer,
+                false,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&writer),
+                String::from_utf8_lossy(expected)
+            );
+        };
+        check(&["pipecolor", "--invert"], b"b\nd\n");
+        check(&["pipecolor", "--invert=error"], b"b\nWarning: c\nd\n");
+        check(
+            &["pipecolor", "--invert", "-B", "1"],
+            b"Error: a\nb\nWarning: c\nd\n",
+        );
+
+        let opt = Opt::from_iter(["pipecolor", "--invert=x"].iter());
+        assert!(check_invert(&config, &opt).is_err());
+    }
 
+    #[test]
+    fn test_context() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let input = b"a\nError: b\nc\nd\ne\nf\nError: g\nh\n";
+        let check = |args: &[&str], expected: &[u8]| {
+            let opt = Opt::from_iter(args.iter());
+            let mut reader = BufReader::new(&input[..]);
+            let mut writer = Vec::new();
+            output(
+                &mut reader,
+                &mut writer,
+                false,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&writer),
+                String::from_utf8_lossy(expected)
+            );
+        };
+        check(
+            &["pipecolor", "--only-matches", "-C", "1"],
+            b"a\nError: b\nc\n--\nf\nError: g\nh\n",
+        );
+        check(
+            &["pipecolor", "--only-matches", "-A", "3"],
+            b"Error: b\nc\nd\ne\n--\nError: g\nh\n",
+        );
+        check(
+            &["pipecolor", "--only-matches", "-B", "4"],
+            b"a\nError: b\nc\nd\ne\nf\nError: g\n",
+        );
+        // The context is ignored without filter mode
+        check(&["pipecolor", "-C", "1"], input);
+    }
+
+    #[test]
+    fn test_split_cr() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let check = |args: &[&str], expected: &str| {
+            let opt = Opt::from_iter(args.iter());
+            let mut reader = BufReader::new(&b"Error: 1%\rError: 2%\r"[..]);
+            let mut writer = Vec::new();
+            output(
+                &mut reader,
+                &mut writer,
+                true,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            assert_eq!(String::from_utf8(writer).unwrap(), expected);
+        };
+        // The progress is colorized per update, and the line is finished at the end
+        check(
+            &["pipecolor"],
+            "\u{1b}[38;5;9mError\u{1b}[38;5;1m: 1%\u{1b}[39m\r\
+             \u{1b}[38;5;9mError\u{1b}[38;5;1m: 2%\u{1b}[39m\r\n",
+        );
+        check(
+            &["pipecolor", "--no-split-cr"],
+            "\u{1b}[38;5;9mError\u{1b}[38;5;1m: 1%\rError: 2%\r\u{1b}[39m\n",
+        );
+    }
+
+    #[test]
+    fn test_read_record() {
+        let record_start = Regex::new(r"^\S").unwrap();
+        let mut reader = BufReader::new(&b"  x\nError\n  at A\n  at B\nInfo\n  y"[..]);
+        let mut records = Vec::new();
+        let mut buf = Vec::new();
+        let mut next = Vec::new();
+        let sink = &mut io::sink();
+        let delim = Delimiter {
+            byte: b'\n',
+            split_cr: true,
+        };
+        while read_record(
+            &mut reader,
+            Some(&record_start),
+            delim,
+            &mut buf,
+            &mut next,
+            false,
+            sink,
+        )
+        .unwrap()
+        {
+            records.push(String::from_utf8(std::mem::take(&mut buf)).unwrap());
+        }
+        assert_eq!(
+            records,
+            vec!["  x\n", "Error\n  at A\n  at B\n", "Info\n  y"]
+        );
+    }
+
+    #[test]
+    fn test_delimiter() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let check = |args: &[&str], input: &[u8], use_color: bool, expected: &[u8]| {
+            let opt = Opt::from_iter(args.iter());
+            let mut reader = BufReader::new(input);
+            let mut writer = Vec::new();
+            output(
+                &mut reader,
+                &mut writer,
+                use_color,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&writer),
+                String::from_utf8_lossy(expected)
+            );
+        };
+        check(&["pipecolor", "-z"], b"a\nb\0c", false, b"a\nb\0c\0");
+        check(&["pipecolor", "--delimiter", ";"], b"a;b", false, b"a;b;");
+        check(
+            &["pipecolor", "--delimiter", "0x1e"],
+            b"a\x1e",
+            false,
+            b"a\x1e",
+        );
+        // The delimiter is written after the reset sequence
+        check(
+            &["pipecolor", "-z"],
+            b"Error\0",
+            true,
+            b"\x1b[38;5;9mError\x1b[39m\0",
+        );
+
+        assert_eq!(parse_delimiter("\\0").unwrap(), 0);
+        assert_eq!(parse_delimiter("\\t").unwrap(), b'\t');
+        assert!(parse_delimiter(";;").is_err());
+        assert!(Opt::from_iter_safe(&["pipecolor", "-z", "--delimiter", ";"]).is_err());
+    }
+
+    #[test]
+    fn test_flush() {
+        struct Counter(usize);
+        impl Write for Counter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let check = |args: &[&str], expected: usize| {
+            let opt = Opt::from_iter(args.iter());
+            let mut reader = BufReader::new(&b"a\nb\nc"[..]);
+            let mut writer = Counter(0);
+            output(
+                &mut reader,
+                &mut writer,
+                false,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            assert_eq!(writer.0, expected);
+        };
+        check(&["pipecolor", "--flush", "line"], 4);
+        check(&["pipecolor", "--flush", "block"], 0);
+
+        let resolve = |args: &[&str]| {
+            let mut opt = Opt::from_iter(args.iter());
+            resolve_flush(&mut opt);
+            opt.flush
+        };
+        assert_eq!(resolve(&["pipecolor", "-f", "a.log"]), "line");
+        assert_eq!(
+            resolve(&["pipecolor", "--flush", "block", "-f", "a.log"]),
+            "block"
+        );
+    }
+
+    #[test]
+    fn test_markers() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[lines]]
+            pat = "Error"
+            colors = ["Red"]
+            [[lines]]
+            pat = "Warn"
+            colors = ["Yellow"]
+            marker = ["[W:", "]"]
+            "#,
+        )
+        .unwrap();
+        config.show_markers = true;
+        config.depth = ColorDepth::Dumb;
+        let args = vec!["pipecolor"];
+        let opt = Opt::from_iter(args.iter());
+        let input = b"an Error occurred\nWarn\nInfo\n";
+        let mut reader = BufReader::new(&input[..]);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            true,
+            &config,
+            &opt,
+            false,
+            LineOutput::new(&Stats::default()),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "an »Error« occurred\n[W:Warn]\nInfo\n"
+        );
+    }
+
+    #[test]
+    fn test_incident() {
+        let config: Config = toml::from_str(
+            r#"
+            [[lines]]
+            pat = "panic"
+            colors = ["Red"]
+            trigger = true
+            "#,
+        )
+        .unwrap();
+        let dir = env::temp_dir().join(format!("pipecolor_test_incident_{}", std::process::id()));
+        let dir_arg = dir.to_string_lossy().into_owned();
+        let args = vec![
+            "pipecolor",
+            "--incident-dir",
+            &dir_arg,
+            "--incident-before",
+            "1",
+            "--incident-after",
+            "1",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        let input = b"a\nb\npanic\nc\nd\n";
+        let mut reader = BufReader::new(&input[..]);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            false,
+            &config,
+            &opt,
+            false,
+            LineOutput::new(&Stats::default()),
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "a\nb\npanic\nc\nd\n");
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let path = files[0].as_ref().unwrap().path();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "b\npanic\nc\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Lines of a command are recorded in the same way
+        let args = [&args[..], &["--", "printf", "a\\nb\\npanic\\nc\\nd\\n"]].concat();
+        let opt = Opt::from_iter(args.iter());
+        let mut writer = Vec::new();
+        let stats = Stats::default();
+        output_command(&opt.command, &mut writer, false, &config, &opt, &stats).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "a\nb\npanic\nc\nd\n");
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let path = files[0].as_ref().unwrap().path();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "b\npanic\nc\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stats_db() {
+        let path = env::temp_dir().join(format!("pipecolor_test_stats_db_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path_arg = path.to_string_lossy().into_owned();
         let args = vec![
             "pipecolor",
-            "-m",
-            "disable",
             "-c",
             "sample/pipecolor.toml",
-            "sample/access_log",
+            "--stats-db",
+            &path_arg,
+            "sample/maillog",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        assert!(run_opt(&opt).is_ok());
+        let records = stats::load(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].matches.iter().any(|x| x.count > 0));
+
+        let args = vec!["pipecolor", "stats", "--stats-db", &path_arg];
+        let opt = Opt::from_iter(args.iter());
+        assert!(matches!(opt.subcommand, Some(SubCommand::Stats { .. })));
+        assert!(run_opt(&opt).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_check_coverage() {
+        let args = vec![
+            "pipecolor",
+            "-c",
+            "sample/pipecolor.toml",
+            "check",
+            "--coverage",
+            "sample/maillog",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        assert!(matches!(opt.subcommand, Some(SubCommand::Check { .. })));
+        // The rule of access_log is never matched
+        assert_eq!(run_opt(&opt).unwrap(), 1);
+
+        // The config is resolved like colorizing lines
+        let dir = env::temp_dir().join(format!("pipecolor_test_coverage_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("pipecolor.toml");
+        std::fs::write(
+            &config,
+            r#"
+            [styles]
+            error = { fg = "Red", bold = true }
+
+            [[lines]]
+            pat = "error"
+            colors = ["@error"]
+            "#,
+        )
+        .unwrap();
+        let corpus = dir.join("corpus.log");
+        std::fs::write(&corpus, "Error: a\nERROR: b\n").unwrap();
+        let config_arg = config.to_string_lossy().into_owned();
+        let corpus_arg = corpus.to_string_lossy().into_owned();
+        let args = ["-c", &config_arg, "check", "--coverage", &corpus_arg];
+        let opt = Opt::from_iter([&["pipecolor", "-i"][..], &args].concat().iter());
+        assert_eq!(run_opt(&opt).unwrap(), 0);
+        let opt = Opt::from_iter([&["pipecolor"][..], &args].concat().iter());
+        assert_eq!(run_opt(&opt).unwrap(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tee() {
+        let path = env::temp_dir().join(format!("pipecolor_test_tee_{}", std::process::id()));
+        let spec = format!("{},color=always", path.to_string_lossy());
+        let args = vec![
+            "pipecolor",
+            "--mode",
+            "disable",
+            "--tee",
+            &spec,
+            "sample/maillog",
+        ];
+        let opt = Opt::from_iter(args.iter());
+        assert!(run_opt(&opt).is_ok());
+
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let mut reader = BufReader::new(File::open("sample/maillog").unwrap());
+        let mut expected = Vec::new();
+        output(
+            &mut reader,
+            &mut expected,
+            true,
+            &config,
+            &opt,
+            false,
+            LineOutput::new(&Stats::default()),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        std::fs::remove_file(&path).unwrap();
+
+        let args = vec!["pipecolor", "--tee", "a.log,format=xml", "sample/maillog"];
+        let opt = Opt::from_iter(args.iter());
+        assert!(run_opt(&opt).is_err());
+    }
+
+    #[test]
+    fn test_truncate() {
+        let path = env::temp_dir().join(format!("pipecolor_test_truncate_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path_arg = path.to_string_lossy().into_owned();
+        let args = vec!["pipecolor", "--truncate", "5", "--truncate-log", &path_arg];
+        let opt = Opt::from_iter(args.iter());
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+
+        let mut reader = BufReader::new(&b"Error: disk full\nInfo\n"[..]);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            false,
+            &config,
+            &opt,
+            false,
+            LineOutput::new(&Stats::default()),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "Error… (+11 bytes)\nInfo\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "Error: disk full\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let mut reader = BufReader::new(&b"Error: disk full\n"[..]);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            true,
+            &config,
+            &opt,
+            false,
+            LineOutput::new(&Stats::default()),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "\u{1b}[38;5;9mError\u{1b}[38;5;1m\u{1b}[m\u{1b}[38;5;8m… (+11 bytes)\u{1b}[39m\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(group_digits(12345), "12,345");
+        assert_eq!(group_digits(123), "123");
+        assert_eq!(group_digits(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn test_digest() {
+        let args = vec!["pipecolor", "--digest", "sha256", "sample/access_log"];
+        let opt = Opt::from_iter(args.iter());
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let source = FileSource {
+            path: PathBuf::from("sample/access_log"),
+        };
+        let reader = source.open(&opt).unwrap();
+        let (mut reader, digest) = with_digest(reader, &opt);
+        let mut writer = Vec::new();
+        output(
+            &mut reader,
+            &mut writer,
+            false,
+            &config,
+            &opt,
+            false,
+            LineOutput::new(&Stats::default()),
+        )
+        .unwrap();
+        let digest = digest.unwrap();
+        let footer = get_footer(&source, &digest.lock().unwrap(), false, &config, None).unwrap();
+
+        let data = std::fs::read("sample/access_log").unwrap();
+        let mut summary = Summary::new("sha256");
+        summary.update(&data);
+        assert_eq!(
+            footer,
+            format!(
+                "sha256:{}  sample/access_log ({} bytes, 10 lines)\n",
+                summary.hex(),
+                group_digits(data.len())
+            )
+        );
+        assert!(run_opt(&opt).is_ok());
+    }
+
+    // Attach to child processes spawned by the test through their stdout and stderr
+    struct ChildAttach {
+        children: std::sync::Mutex<Vec<std::process::Child>>,
+    }
+
+    impl ChildAttach {
+        fn new(children: Vec<std::process::Child>) -> Arc<Self> {
+            Arc::new(ChildAttach {
+                children: std::sync::Mutex::new(children),
+            })
+        }
+
+        // Reap the child processes
+        fn wait(&self) {
+            for child in self.children.lock().unwrap().iter_mut() {
+                child.wait().unwrap();
+            }
+        }
+    }
+
+    impl Attach for ChildAttach {
+        fn attach(&self, pid: i32, _stream: &str) -> Result<Box<dyn BufRead + Send>> {
+            let [stdout, _] = self.attach_streams(pid)?;
+            Ok(stdout)
+        }
+
+        fn attach_streams(&self, pid: i32) -> Result<[Box<dyn BufRead + Send>; 2]> {
+            let mut children = self.children.lock().unwrap();
+            let child = children
+                .iter_mut()
+                .find(|x| x.id() as i32 == pid)
+                .ok_or_else(|| anyhow::anyhow!("failed to attach to {}", pid))?;
+            Ok([
+                Box::new(BufReader::new(child.stdout.take().unwrap())),
+                Box::new(BufReader::new(child.stderr.take().unwrap())),
+            ])
+        }
+    }
+
+    fn spawn_writer(script: &str) -> std::process::Child {
+        Command::new("sh")
+            .args(["-c", script])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let opt = Opt::from_iter(["pipecolor"].iter());
+
+        let child = spawn_writer("echo Error; sleep 0.1; echo Warning");
+        let pid = child.id() as i32;
+        let attacher = ChildAttach::new(vec![child]);
+        let mut writer = Vec::new();
+        output_processes(
+            vec![pid],
+            attacher.clone(),
+            &mut writer,
+            true,
+            &config,
+            &opt,
+            &Stats::default(),
+        )
+        .unwrap();
+        attacher.wait();
+        let expected = {
+            let mut reader = BufReader::new(&b"Error\nWarning\n"[..]);
+            let mut expected = Vec::new();
+            output(
+                &mut reader,
+                &mut expected,
+                true,
+                &config,
+                &opt,
+                false,
+                LineOutput::new(&Stats::default()),
+            )
+            .unwrap();
+            expected
+        };
+        assert_eq!(writer, expected);
+        assert!(attacher.attach(0, "any").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process_multi() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let opt = Opt::from_iter(["pipecolor"].iter());
+
+        let child0 = spawn_writer("echo a");
+        let child1 = spawn_writer("echo b");
+        let pids = vec![child0.id() as i32, child1.id() as i32];
+        let attacher = ChildAttach::new(vec![child0, child1]);
+        let mut writer = Vec::new();
+        output_processes(
+            pids.clone(),
+            attacher.clone(),
+            &mut writer,
+            false,
+            &config,
+            &opt,
+            &Stats::default(),
+        )
+        .unwrap();
+        attacher.wait();
+        // The order of lines between the processes is not determined
+        let output = String::from_utf8(writer).unwrap();
+        let mut lines: Vec<_> = output.lines().map(String::from).collect();
+        lines.sort_unstable();
+        let mut expected = vec![
+            format!("[pid {}] a", pids[0]),
+            format!("[pid {}] b", pids[1]),
+        ];
+        expected.sort_unstable();
+        assert_eq!(lines, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_listen_reconnect() {
+        use std::os::unix::net::UnixStream;
+
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let opt = Opt::from_iter(["pipecolor", "--max-lines", "2"].iter());
+        let path = env::temp_dir().join(format!("pipecolor_test_listen_{}", std::process::id()));
+        let source = source::ListenSource::unix(&path).unwrap();
+
+        // Each connection is read as a new source even after the previous one is closed
+        for line in [&b"a\n"[..], &b"b\n"[..]] {
+            let mut client = UnixStream::connect(&path).unwrap();
+            client.write_all(line).unwrap();
+        }
+        let mut writer = Vec::new();
+        output_multi(
+            vec![Box::new(source)],
+            &mut writer,
+            false,
+            &config,
+            &opt,
+            &Stats::default(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        let output = String::from_utf8(writer).unwrap();
+        let mut lines: Vec<_> = output.lines().collect();
+        lines.sort_unstable();
+        assert_eq!(lines, vec!["[unix#1] a", "[unix#2] b"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process_stream_tag() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let opt = Opt::from_iter(["pipecolor", "--process-stream", "tag"].iter());
+
+        let child = spawn_writer("echo a; echo b >&2");
+        let pid = child.id() as i32;
+        let attacher = ChildAttach::new(vec![child]);
+        let mut writer = Vec::new();
+        output_processes(
+            vec![pid],
+            attacher.clone(),
+            &mut writer,
+            false,
+            &config,
+            &opt,
+            &Stats::default(),
+        )
+        .unwrap();
+        attacher.wait();
+        // The order of lines between the streams is not determined
+        let output = String::from_utf8(writer).unwrap();
+        let mut lines: Vec<_> = output.lines().collect();
+        lines.sort_unstable();
+        assert_eq!(
+            lines,
+            vec![
+                format!("[pid {} stderr] b", pid),
+                format!("[pid {} stdout] a", pid)
+            ]
+        );
+
+        let opt = Opt::from_iter(["pipecolor", "--process-stream", "tag", "--follow-forks"].iter());
+        let attacher = ChildAttach::new(Vec::new());
+        assert!(output_processes(
+            vec![pid],
+            attacher,
+            &mut Vec::new(),
+            false,
+            &config,
+            &opt,
+            &Stats::default()
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command() {
+        let args = vec![
+            "pipecolor",
+            "--",
+            "sh",
+            "-c",
+            "echo Error; echo Warning >&2; exit 3",
         ];
         let opt = Opt::from_iter(args.iter());
         let ret = run_opt(&opt);
-        assert!(ret.is_ok());
+        assert_eq!(ret.unwrap(), 3);
+
+        let args = vec!["pipecolor", "--", "pipecolor_not_found"];
+        let opt = Opt::from_iter(args.iter());
+        let ret = run_opt(&opt);
+        assert!(ret.is_err());
     }
 
     #[test]
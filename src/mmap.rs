@@ -0,0 +1,83 @@
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek};
+
+// Files smaller than this are read normally because mapping costs more than reading
+const MIN_SIZE: u64 = 1024 * 1024;
+
+// -------------------------------------------------------------------------------------------------
+// MmapReader
+// -------------------------------------------------------------------------------------------------
+
+// Read the memory-mapped file without read syscalls.
+// Lines are split by memchr in `BufRead::read_until` directly over the mapping.
+pub struct MmapReader {
+    map: Mmap,
+    pos: usize,
+}
+
+impl MmapReader {
+    // Map the file from the current position.
+    // None is returned for small files, or if the file can't be mapped like special files.
+    pub fn new(file: &mut File) -> Option<Self> {
+        let metadata = file.metadata().ok()?;
+        if !metadata.is_file() || metadata.len() < MIN_SIZE {
+            return None;
+        }
+        let pos = file.stream_position().ok()? as usize;
+        // Reading the mapping crashes if the file is truncated by others like copytruncate of
+        // logrotate. --no-mmap avoids it.
+        let map = unsafe { Mmap::map(&*file) }.ok()?;
+        Some(MmapReader { map, pos })
+    }
+}
+
+impl Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for MmapReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(&self.map[self.pos.min(self.map.len())..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{SeekFrom, Write};
+
+    #[test]
+    fn test_mmap_reader() {
+        let path = std::env::temp_dir().join(format!("pipecolor_mmap_{}", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        for i in 0..100_000 {
+            writeln!(file, "line {}", i).unwrap();
+        }
+        drop(file);
+
+        let mut file = File::open(&path).unwrap();
+        file.seek(SeekFrom::Start(7)).unwrap();
+        let mut reader = MmapReader::new(&mut file).unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "line 1\n");
+        assert_eq!(reader.lines().count(), 99_998);
+
+        let mut small = File::open("Cargo.toml").unwrap();
+        assert!(MmapReader::new(&mut small).is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
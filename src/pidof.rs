@@ -0,0 +1,250 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// -------------------------------------------------------------------------------------------------
+// ProcessReader
+// -------------------------------------------------------------------------------------------------
+
+// A reader of an attached process, which waits for output while the process is alive.
+// EOF is returned after the process exits.
+pub struct ProcessReader<R> {
+    inner: R,
+    pid: i32,
+    proc_dir: PathBuf,
+    interval: Duration,
+}
+
+impl<R: Read> ProcessReader<R> {
+    pub fn new(inner: R, pid: i32, interval: Duration) -> Self {
+        ProcessReader {
+            inner,
+            pid,
+            proc_dir: PathBuf::from("/proc"),
+            interval,
+        }
+    }
+}
+
+impl<R: Read> Read for ProcessReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let len = self.inner.read(buf)?;
+            if len > 0 || !is_alive_in(&self.proc_dir, self.pid) {
+                return Ok(len);
+            }
+            thread::sleep(self.interval);
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// Find PIDs whose name or command line matches the pattern by scanning /proc.
+// The result is sorted by the start time, so the newest process is the last.
+pub fn find_process(pattern: &str) -> Result<Vec<i32>> {
+    find_process_in(Path::new("/proc"), pattern, std::process::id() as i32)
+}
+
+fn find_process_in(proc_dir: &Path, pattern: &str, own: i32) -> Result<Vec<i32>> {
+    let re = Regex::new(pattern).context(format!("failed to parse pattern '{}'", pattern))?;
+    let entries = fs::read_dir(proc_dir)
+        .context(format!("failed to read '{}'", proc_dir.to_string_lossy()))?;
+
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        let pid: i32 = match entry.file_name().to_string_lossy().parse() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if pid == own {
+            continue;
+        }
+        // The process may exit while scanning
+        let path = entry.path();
+        let comm = fs::read_to_string(path.join("comm")).unwrap_or_default();
+        let cmdline = fs::read(path.join("cmdline")).unwrap_or_default();
+        let cmdline = String::from_utf8_lossy(&cmdline).replace('\0', " ");
+        if re.is_match(comm.trim_end()) || re.is_match(cmdline.trim_end()) {
+            let stat = fs::read_to_string(path.join("stat")).unwrap_or_default();
+            found.push((start_time(&stat), pid));
+        }
+    }
+
+    found.sort();
+    Ok(found.into_iter().map(|(_, pid)| pid).collect())
+}
+
+// A zombie process is not alive because it has already exited
+fn is_alive_in(proc_dir: &Path, pid: i32) -> bool {
+    match fs::read_to_string(proc_dir.join(pid.to_string()).join("stat")) {
+        Ok(stat) => process_state(&stat) != Some("Z"),
+        Err(_) => false,
+    }
+}
+
+// The 3rd field of /proc/PID/stat
+fn process_state(stat: &str) -> Option<&str> {
+    stat.rfind(')')
+        .and_then(|x| stat[x + 1..].split_whitespace().next())
+}
+
+// Find descendant PIDs of the processes by scanning /proc.
+// Zombies are skipped because they have already exited.
+pub fn find_descendants(pids: &[i32]) -> Vec<i32> {
+    find_descendants_in(Path::new("/proc"), pids)
+}
+
+fn find_descendants_in(proc_dir: &Path, pids: &[i32]) -> Vec<i32> {
+    let mut parents = Vec::new();
+    if let Ok(entries) = fs::read_dir(proc_dir) {
+        for entry in entries.flatten() {
+            let pid: i32 = match entry.file_name().to_string_lossy().parse() {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let stat = fs::read_to_string(entry.path().join("stat")).unwrap_or_default();
+            if process_state(&stat) == Some("Z") {
+                continue;
+            }
+            if let Some(ppid) = parent_pid(&stat) {
+                parents.push((pid, ppid));
+            }
+        }
+    }
+
+    let mut ret = Vec::new();
+    let mut targets = pids.to_vec();
+    while let Some(target) = targets.pop() {
+        for (pid, _) in parents.iter().filter(|(_, ppid)| *ppid == target) {
+            if !ret.contains(pid) && !pids.contains(pid) {
+                ret.push(*pid);
+                targets.push(*pid);
+            }
+        }
+    }
+    ret.sort_unstable();
+    ret
+}
+
+// The 4th field of /proc/PID/stat
+fn parent_pid(stat: &str) -> Option<i32> {
+    stat.rfind(')')
+        .and_then(|x| stat[x + 1..].split_whitespace().nth(1))
+        .and_then(|x| x.parse().ok())
+}
+
+// The 22nd field of /proc/PID/stat. The fields are counted after the command name in parentheses
+// because it may contain spaces.
+fn start_time(stat: &str) -> u64 {
+    stat.rfind(')')
+        .and_then(|x| stat[x + 1..].split_whitespace().nth(19))
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(0)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_proc(dir: &Path, pid: i32, comm: &str, start: u64) {
+        create_child(dir, pid, 1, comm, start);
+    }
+
+    fn create_child(dir: &Path, pid: i32, ppid: i32, comm: &str, start: u64) {
+        let path = dir.join(pid.to_string());
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("comm"), format!("{}\n", comm)).unwrap();
+        fs::write(path.join("cmdline"), format!("/usr/sbin/{}\0-g\0", comm)).unwrap();
+        let stat = format!(
+            "{} ({}) S {} 1 1 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 {} 0 0",
+            pid, comm, ppid, start
+        );
+        fs::write(path.join("stat"), stat).unwrap();
+    }
+
+    #[test]
+    fn test_find_process() {
+        let dir = std::env::temp_dir().join(format!("pipecolor_test_proc_{}", std::process::id()));
+        create_proc(&dir, 100, "nginx", 300);
+        create_proc(&dir, 200, "nginx", 100);
+        create_proc(&dir, 300, "sshd", 200);
+        create_proc(&dir, 400, "nginx", 400);
+        fs::create_dir_all(dir.join("self")).unwrap();
+
+        assert_eq!(
+            find_process_in(&dir, "^nginx$", 400).unwrap(),
+            vec![200, 100]
+        );
+        assert_eq!(find_process_in(&dir, "sbin/sshd", 0).unwrap(), vec![300]);
+        assert!(find_process_in(&dir, "httpd", 0).unwrap().is_empty());
+        assert!(find_process_in(&dir, "(", 0).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_descendants() {
+        let dir = std::env::temp_dir().join(format!("pipecolor_test_fork_{}", std::process::id()));
+        create_proc(&dir, 100, "sh", 0);
+        create_child(&dir, 200, 100, "worker", 0);
+        create_child(&dir, 300, 200, "worker", 0);
+        create_child(&dir, 400, 1, "sshd", 0);
+        create_child(&dir, 500, 100, "worker", 0);
+        create_child(&dir, 600, 100, "worker", 0);
+        fs::write(
+            dir.join("600").join("stat"),
+            "600 (worker) Z 100 1 1 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 0 0 0",
+        )
+        .unwrap();
+
+        assert_eq!(find_descendants_in(&dir, &[100]), vec![200, 300, 500]);
+        assert_eq!(find_descendants_in(&dir, &[200, 400]), vec![300]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_process_reader() {
+        let dir = std::env::temp_dir().join(format!("pipecolor_test_alive_{}", std::process::id()));
+        create_proc(&dir, 100, "sh", 0);
+        assert!(is_alive_in(&dir, 100));
+        assert!(!is_alive_in(&dir, 200));
+
+        // EOF after the process exits
+        let mut reader = ProcessReader {
+            inner: &b"output"[..],
+            pid: 200,
+            proc_dir: dir.clone(),
+            interval: Duration::from_millis(1),
+        };
+        let mut s = String::new();
+        reader.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "output");
+
+        fs::write(
+            dir.join("100").join("stat"),
+            "100 (sh) Z 1 1 1 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 0 0 0",
+        )
+        .unwrap();
+        assert!(!is_alive_in(&dir, 100));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_start_time() {
+        let stat = "1 (a b) S 1 1 1 0 -1 0 0 0 0 0 0 0 0 0 20 0 1 0 12345 0";
+        assert_eq!(start_time(stat), 12345);
+        assert_eq!(start_time(""), 0);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::colorize::{Action, Config};
+use crate::read_timeout::read_record_timeout;
+use crate::sink;
+use crate::{finish_newline, get_delimiter, output_line, Delimiter, LineOutput, Opt};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// The number of lines colorized at once by a worker
+const BATCH_LINES: usize = 1024;
+
+// -------------------------------------------------------------------------------------------------
+// Batch
+// -------------------------------------------------------------------------------------------------
+
+// Consecutive lines of input. `count` is the index of the first line for zebra striping.
+// `ends` are the end positions of the lines.
+struct Batch {
+    seq: usize,
+    count: usize,
+    lines: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// Whether lines can be colorized independently of each other.
+// Features keeping state across lines are processed in order by a single thread.
+pub fn is_supported(config: &Config, opt: &Opt) -> bool {
+    opt.dedupe_window.is_none()
+        && opt.max_count.is_none()
+        && opt.max_lines.is_none()
+        && !opt.quiet
+        && opt.since.is_none()
+        && !opt.only_matches
+        && opt.others.as_deref() != Some("hide")
+        && opt.invert.is_none()
+        && opt.emit_sort_key.is_none()
+        && opt.incident_dir.is_none()
+        && opt.stats_db.is_none()
+        && !opt.stats
+        && opt.truncate_log.is_none()
+        && !opt.verbose
+        && config.record_start.is_none()
+        && config
+            .lines
+            .iter()
+            .all(|x| !x.filter && x.action != Action::Drop)
+        && config.lines.iter().all(|x| x.max_matches_total.is_none())
+}
+
+// Read, colorize and write lines concurrently.
+// Batches of lines are colorized by `opt.jobs` workers, and written in the order of input.
+pub fn output(
+    reader: &mut (dyn BufRead + Send),
+    writer: &mut dyn Write,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    line_output: LineOutput,
+) -> Result<()> {
+    let (work_tx, work_rx) = mpsc::sync_channel::<Batch>(opt.jobs * 2);
+    let work_rx = Arc::new(Mutex::new(work_rx));
+    let (done_tx, done_rx) = mpsc::sync_channel::<(usize, Result<Vec<u8>>)>(opt.jobs * 2);
+
+    thread::scope(|s| {
+        let read = s.spawn(move || read_batches(reader, get_delimiter(opt), work_tx));
+        for _ in 0..opt.jobs {
+            // Each worker has its own config because match counters of rules are not shared
+            let config = config.clone();
+            let work_rx = work_rx.clone();
+            let done_tx = done_tx.clone();
+            s.spawn(move || {
+                colorize_batches(&work_rx, &done_tx, use_color, &config, opt, line_output)
+            });
+        }
+        drop(done_tx);
+
+        let ret = write_batches(done_rx, writer, opt);
+        // The reader error is prior because it may cause the other errors
+        read.join().unwrap().and(ret)
+    })
+}
+
+// Send batches of lines. A partial batch is sent when input is idle not to delay the output.
+fn read_batches(
+    reader: &mut (dyn BufRead + Send),
+    delim: Delimiter,
+    tx: SyncSender<Batch>,
+) -> Result<()> {
+    let mut seq = 0;
+    let mut count = 0;
+    let mut lines = Vec::new();
+    let mut ends = Vec::new();
+    let mut n = 0;
+    while !sink::is_broken_pipe() {
+        let (len, timeout) = read_record_timeout(reader, delim.byte, delim.split_cr, &mut lines)?;
+        if len > 0 {
+            n += 1;
+            ends.push(lines.len());
+        }
+        let eof = len == 0 && !timeout;
+        if n > 0 && (n >= BATCH_LINES || timeout || eof) {
+            let batch = Batch {
+                seq,
+                count,
+                lines: std::mem::take(&mut lines),
+                ends: std::mem::take(&mut ends),
+            };
+            if tx.send(batch).is_err() {
+                // The writer stopped by an error
+                return Ok(());
+            }
+            seq += 1;
+            count += n;
+            n = 0;
+        }
+        if eof {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+fn colorize_batches(
+    rx: &Mutex<Receiver<Batch>>,
+    tx: &SyncSender<(usize, Result<Vec<u8>>)>,
+    use_color: bool,
+    config: &Config,
+    opt: &Opt,
+    line_output: LineOutput,
+) {
+    loop {
+        let batch = match rx.lock().unwrap().recv() {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let mut out = Vec::with_capacity(batch.lines.len() * 2);
+        let mut ret = Ok(());
+        let mut start = 0;
+        for (i, end) in batch.ends.iter().enumerate() {
+            let line = &batch.lines[start..*end];
+            start = *end;
+            out.extend_from_slice(line_output.prefix.as_bytes());
+            ret = output_line(
+                line,
+                &mut out,
+                use_color,
+                config,
+                opt,
+                batch.count + i,
+                line_output,
+            );
+            if ret.is_err() {
+                break;
+            }
+        }
+        if tx.send((batch.seq, ret.map(|_| out))).is_err() {
+            return;
+        }
+    }
+}
+
+// Write batches in order. The output is flushed per batch, or only when the workers are idle
+// if --flush block.
+fn write_batches(
+    rx: Receiver<(usize, Result<Vec<u8>>)>,
+    writer: &mut dyn Write,
+    opt: &Opt,
+) -> Result<()> {
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    let mut last = Vec::new();
+    loop {
+        let (seq, out) = match rx.try_recv() {
+            Ok(x) => x,
+            Err(TryRecvError::Empty) => {
+                let _ = writer.flush();
+                match rx.recv() {
+                    Ok(x) => x,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        pending.insert(seq, out?);
+        while let Some(out) = pending.remove(&next) {
+            let _ = writer.write(&out);
+            if opt.flush != "block" {
+                let _ = writer.flush();
+            }
+            if !out.is_empty() {
+                last = out;
+            }
+            next += 1;
+        }
+    }
+    finish_newline(&last[last.len().saturating_sub(1)..], writer, opt);
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Stats, DEFAULT_CONFIG};
+    use std::io::BufReader;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_output() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        let mut input = Vec::new();
+        for i in 0..5000 {
+            input.extend_from_slice(format!("Error {} Debug 0x{:x}\n", i, i).as_bytes());
+        }
+        input.extend_from_slice(b"Info");
+
+        let args = ["pipecolor", "--zebra", "--newline", "crlf"];
+        let opt = Opt::from_iter(args.iter());
+        let mut expected = Vec::new();
+        let mut reader = BufReader::new(&input[..]);
+        let stats = Stats::default();
+        let line_output = LineOutput::new(&stats);
+        crate::output(
+            &mut reader,
+            &mut expected,
+            true,
+            &config,
+            &opt,
+            false,
+            line_output,
+        )
+        .unwrap();
+
+        let args = ["pipecolor", "--jobs", "4", "--zebra", "--newline", "crlf"];
+        let opt = Opt::from_iter(args.iter());
+        assert!(is_supported(&config, &opt));
+
+        let mut writer = Vec::new();
+        let mut reader = BufReader::new(&input[..]);
+        let line_output = LineOutput {
+            prefix: "> ",
+            ..LineOutput::new(&stats)
+        };
+        output(&mut reader, &mut writer, true, &config, &opt, line_output).unwrap();
+        let expected: Vec<_> = expected
+            .split_inclusive(|x| *x == b'\n')
+            .flat_map(|x| [&b"> "[..], x].concat())
+            .collect();
+        assert_eq!(String::from_utf8(writer), String::from_utf8(expected));
+    }
+}
@@ -1,5 +1,63 @@
 use memchr;
 use std::io::{BufRead, ErrorKind, Result};
+#[cfg(unix)]
+use std::io::{Error, Read};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::time::Duration;
+
+// -------------------------------------------------------------------------------------------------
+// PollReader
+// -------------------------------------------------------------------------------------------------
+
+// Wait for input by poll(2), and return ErrorKind::TimedOut if input is idle for the timeout.
+// The timeout is reported once after each input, and the reader sleeps without timeout after it,
+// so idle input doesn't wake up the process.
+#[cfg(unix)]
+pub struct PollReader<R> {
+    inner: R,
+    timeout: Duration,
+    armed: bool,
+}
+
+#[cfg(unix)]
+impl<R: Read + AsRawFd> PollReader<R> {
+    pub fn new(inner: R, timeout: Duration) -> Self {
+        PollReader {
+            inner,
+            timeout,
+            armed: false,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl<R: Read + AsRawFd> Read for PollReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let timeout = if self.armed {
+            self.timeout.as_millis().min(i32::MAX as u128) as i32
+        } else {
+            -1
+        };
+        let mut fd = libc::pollfd {
+            fd: self.inner.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            x if x < 0 => return Err(Error::last_os_error()),
+            0 => {
+                self.armed = false;
+                return Err(ErrorKind::TimedOut.into());
+            }
+            _ => (),
+        }
+        let n = self.inner.read(buf)?;
+        self.armed = n > 0;
+        Ok(n)
+    }
+}
 
 // -------------------------------------------------------------------------------------------------
 // Functions
@@ -46,6 +104,116 @@ pub fn read_line_timeout<R: BufRead + ?Sized>(
     read_until_timeout(r, b'\n', buf)
 }
 
+// Read until the delimiter, or a carriage return not followed by a newline if split_cr is true.
+// The carriage return ends the record if the next byte is not available yet like progress bars.
+pub fn read_record_timeout<R: BufRead + ?Sized>(
+    r: &mut R,
+    delim: u8,
+    split_cr: bool,
+    buf: &mut Vec<u8>,
+) -> Result<(usize, bool)> {
+    if !split_cr {
+        return read_until_timeout(r, delim, buf);
+    }
+    let mut read = 0;
+    loop {
+        let (done, used) = {
+            let available = match r.fill_buf() {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => return Ok((read, true)),
+                Err(e) => return Err(e),
+            };
+            match memchr::memchr2(delim, b'\r', available) {
+                Some(i) => {
+                    buf.extend_from_slice(&available[..i + 1]);
+                    (available[i] == delim, i + 1)
+                }
+                None => {
+                    buf.extend_from_slice(available);
+                    (false, available.len())
+                }
+            }
+        };
+        r.consume(used);
+        read += used;
+        if done || used == 0 {
+            return Ok((read, false));
+        }
+        if buf.ends_with(b"\r") {
+            let next = loop {
+                match r.fill_buf() {
+                    Ok(n) => break n.first().copied(),
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(ref e) if e.kind() == ErrorKind::TimedOut => return Ok((read, true)),
+                    Err(e) => return Err(e),
+                }
+            };
+            if next != Some(delim) {
+                return Ok((read, false));
+            }
+        }
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // Test
 // -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_poll_reader() {
+        use std::fs::File;
+        use std::io::Write;
+        use std::os::unix::io::FromRawFd;
+        use std::thread;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let rx = unsafe { File::from_raw_fd(fds[0]) };
+        let mut tx = unsafe { File::from_raw_fd(fds[1]) };
+        let mut reader = PollReader::new(rx, Duration::from_millis(10));
+        let mut buf = [0; 16];
+
+        tx.write_all(b"ab").unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        // The idle reader waits for the next input without timeout
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            tx.write_all(b"c").unwrap();
+        });
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        writer.join().unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_read_record_timeout() {
+        let mut reader = &b"10%\r20%\r\rdone\r\nnext"[..];
+        let mut records = Vec::new();
+        loop {
+            let mut buf = Vec::new();
+            if read_record_timeout(&mut reader, b'\n', true, &mut buf)
+                .unwrap()
+                .0
+                == 0
+            {
+                break;
+            }
+            records.push(String::from_utf8(buf).unwrap());
+        }
+        assert_eq!(records, vec!["10%\r", "20%\r", "\r", "done\r\n", "next"]);
+
+        let mut reader = &b"10%\r20%\n"[..];
+        let mut buf = Vec::new();
+        read_record_timeout(&mut reader, b'\n', false, &mut buf).unwrap();
+        assert_eq!(buf, b"10%\r20%\n");
+    }
+}
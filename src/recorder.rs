@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// -------------------------------------------------------------------------------------------------
+// Recorder
+// -------------------------------------------------------------------------------------------------
+
+// Keep the last lines in memory like a flight recorder, and dump them with the following lines
+// to an incident file when a trigger rule matches.
+pub struct Recorder {
+    dir: PathBuf,
+    before: usize,
+    after: usize,
+    ring: VecDeque<Vec<u8>>,
+    file: Option<File>,
+    remaining: usize,
+    count: usize,
+    // The output line written through RecordWriter
+    line: Vec<u8>,
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf, before: usize, after: usize) -> Self {
+        Recorder {
+            dir,
+            before,
+            after,
+            ring: VecDeque::new(),
+            file: None,
+            remaining: 0,
+            count: 0,
+            line: Vec::new(),
+        }
+    }
+
+    // Record the output line, and return the path of the incident file if it is created
+    pub fn record(&mut self, line: &[u8], trigger: bool) -> Result<Option<PathBuf>> {
+        let mut created = None;
+        if trigger {
+            if self.file.is_none() {
+                let path = self.incident_path();
+                let mut file = File::create(&path)
+                    .context(format!("failed to create '{}'", path.to_string_lossy()))?;
+                for x in self.ring.drain(..) {
+                    file.write_all(&x)?;
+                }
+                self.file = Some(file);
+                created = Some(path);
+            }
+            // A trigger during the following lines extends the incident
+            self.remaining = self.after + 1;
+        }
+
+        if let Some(ref mut file) = self.file {
+            file.write_all(line)?;
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.file = None;
+            }
+        } else if self.before > 0 {
+            if self.ring.len() == self.before {
+                self.ring.pop_front();
+            }
+            self.ring.push_back(line.to_vec());
+        }
+        Ok(created)
+    }
+
+    fn incident_path(&mut self) -> PathBuf {
+        let _ = fs::create_dir_all(&self.dir);
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+        self.count += 1;
+        self.dir.join(format!(
+            "incident-{}-{}-{}.log",
+            secs,
+            std::process::id(),
+            self.count
+        ))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// RecordWriter
+// -------------------------------------------------------------------------------------------------
+
+// Write an output line through to the writer, and keep it to record by `finish` if the recorder
+// is enabled.
+pub struct RecordWriter<'a> {
+    writer: &'a mut dyn Write,
+    recorder: Option<&'a mut Recorder>,
+}
+
+impl<'a> RecordWriter<'a> {
+    pub fn new(writer: &'a mut dyn Write, recorder: Option<&'a mut Recorder>) -> Self {
+        RecordWriter { writer, recorder }
+    }
+
+    // Record the written line, and return the path of the incident file if it is created.
+    // The trigger is checked only if the recorder is enabled.
+    pub fn finish(self, trigger: impl FnOnce() -> bool) -> Result<Option<PathBuf>> {
+        match self.recorder {
+            Some(recorder) => {
+                let line = std::mem::take(&mut recorder.line);
+                let ret = recorder.record(&line, trigger());
+                recorder.line = line;
+                recorder.line.clear();
+                ret
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Write for RecordWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.line.extend_from_slice(&buf[..len]);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorder() {
+        let dir = std::env::temp_dir().join(format!("pipecolor_test_rec_{}", std::process::id()));
+        let mut rec = Recorder::new(dir.clone(), 2, 1);
+        assert!(rec.record(b"a\n", false).unwrap().is_none());
+        assert!(rec.record(b"b\n", false).unwrap().is_none());
+        assert!(rec.record(b"c\n", false).unwrap().is_none());
+        let path = rec.record(b"crash\n", true).unwrap().unwrap();
+        assert!(rec.record(b"crash\n", true).unwrap().is_none());
+        assert!(rec.record(b"d\n", false).unwrap().is_none());
+        assert!(rec.record(b"e\n", false).unwrap().is_none());
+
+        let s = fs::read_to_string(&path).unwrap();
+        assert_eq!(s, "b\nc\ncrash\ncrash\nd\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_writer() {
+        let dir = std::env::temp_dir().join(format!("pipecolor_test_recw_{}", std::process::id()));
+        let mut rec = Recorder::new(dir.clone(), 1, 0);
+        let mut out = Vec::new();
+        let mut paths = Vec::new();
+        for (line, trigger) in [("a", false), ("b", false), ("crash", true)] {
+            let mut writer = RecordWriter::new(&mut out, Some(&mut rec));
+            let _ = writer.write(b"> ");
+            let _ = writer.write(line.as_bytes());
+            let _ = writer.write(b"\n");
+            paths.extend(writer.finish(|| trigger).unwrap());
+        }
+        assert_eq!(out, b"> a\n> b\n> crash\n");
+        assert_eq!(paths.len(), 1);
+        let s = fs::read_to_string(&paths[0]).unwrap();
+        assert_eq!(s, "> b\n> crash\n");
+
+        let writer = RecordWriter::new(&mut out, None);
+        assert!(writer.finish(|| unreachable!()).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
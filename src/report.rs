@@ -0,0 +1,132 @@
+use crate::colorize::Config;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+// The time when reading input started, and the flag set by SIGUSR1
+static START: OnceLock<Instant> = OnceLock::new();
+static REQUESTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// Start measuring the throughput, and accept requests of the report by SIGUSR1
+pub fn init() -> io::Result<()> {
+    START.get_or_init(Instant::now);
+    let requested = REQUESTED.get_or_init(|| Arc::new(AtomicBool::new(false)));
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGUSR1, requested.clone())?;
+    #[cfg(not(unix))]
+    let _ = requested;
+    Ok(())
+}
+
+// Whether the report is requested by SIGUSR1 since the last call
+pub fn is_requested() -> bool {
+    REQUESTED
+        .get()
+        .is_some_and(|x| x.swap(false, Ordering::Relaxed))
+}
+
+pub fn elapsed() -> Duration {
+    START.get().map_or(Duration::ZERO, |x| x.elapsed())
+}
+
+// Write the match counts and descriptions of each rule, the number of lines and the throughput
+pub fn write(
+    config: &Config,
+    lines: usize,
+    bytes: usize,
+    elapsed: Duration,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    let labels: Vec<_> = config.lines.iter().map(|x| x.label()).collect();
+    let width = labels.iter().map(|x| x.len()).max().unwrap_or(0).max(4);
+    // The description is the label of the rule without the name, so it is not repeated
+    let descriptions: Vec<_> = labels
+        .iter()
+        .zip(&config.lines)
+        .map(|(label, line)| line.description.as_deref().filter(|x| x != label))
+        .collect();
+    if descriptions.iter().any(Option::is_some) {
+        writeln!(writer, "{:width$}  {:>10}  description", "rule", "matches")?;
+    } else {
+        writeln!(writer, "{:width$}  {:>10}", "rule", "matches")?;
+    }
+    for ((label, line), description) in labels.iter().zip(&config.lines).zip(descriptions) {
+        write!(writer, "{:width$}  {:>10}", label, line.matches.get())?;
+        match description {
+            Some(x) => writeln!(writer, "  {}", x)?,
+            None => writeln!(writer)?,
+        }
+    }
+
+    let secs = elapsed.as_secs_f64();
+    let (lines_per_sec, mib_per_sec) = if secs > 0.0 {
+        (lines as f64 / secs, bytes as f64 / secs / 1048576.0)
+    } else {
+        (0.0, 0.0)
+    };
+    writeln!(
+        writer,
+        "{} lines, {} bytes in {:.2}s ({:.0} lines/s, {:.1} MiB/s)",
+        lines, bytes, secs, lines_per_sec, mib_per_sec
+    )
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let config = r#"
+        [[lines]]
+            pat    = "Error"
+            colors = ["Red"]
+            name   = "error"
+        [[lines]]
+            pat    = "Warn"
+            colors = ["Yellow"]
+        "#;
+        let config: Config = toml::from_str(config).unwrap();
+        config.lines[0].matches.set(3);
+        let mut writer = Vec::new();
+        write(&config, 10, 2097152, Duration::from_secs(2), &mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "rule      matches\n\
+             error           3\n\
+             Warn            0\n\
+             10 lines, 2097152 bytes in 2.00s (5 lines/s, 1.0 MiB/s)\n"
+        );
+
+        let config = r#"
+        [[lines]]
+            pat         = "Error"
+            colors      = ["Red"]
+            name        = "error"
+            description = "Fatal errors"
+        [[lines]]
+            pat         = "Warn"
+            colors      = ["Yellow"]
+            description = "Warnings"
+        "#;
+        let config: Config = toml::from_str(config).unwrap();
+        let mut writer = Vec::new();
+        write(&config, 0, 0, Duration::ZERO, &mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "rule         matches  description\n\
+             error              0  Fatal errors\n\
+             Warnings           0\n\
+             0 lines, 0 bytes in 0.00s (0 lines/s, 0.0 MiB/s)\n"
+        );
+    }
+}
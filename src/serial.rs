@@ -0,0 +1,164 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// The interval to retry opening the unplugged device
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+// -------------------------------------------------------------------------------------------------
+// SerialReader
+// -------------------------------------------------------------------------------------------------
+
+// Read the serial port, and reopen it when the device is unplugged and plugged again
+pub struct SerialReader {
+    path: PathBuf,
+    baud: u32,
+    file: Option<File>,
+}
+
+impl SerialReader {
+    pub fn new(path: &Path, baud: u32) -> Result<Self> {
+        let file = open(path, baud)?;
+        Ok(SerialReader {
+            path: path.to_path_buf(),
+            baud,
+            file: Some(file),
+        })
+    }
+}
+
+impl Read for SerialReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let file = match self.file {
+                Some(ref mut x) => x,
+                None => {
+                    thread::sleep(RECONNECT_INTERVAL);
+                    if let Ok(x) = open(&self.path, self.baud) {
+                        eprintln!("pipecolor: {} reconnected", self.path.to_string_lossy());
+                        self.file = Some(x);
+                    }
+                    continue;
+                }
+            };
+            match file.read(buf) {
+                Ok(0) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // EIO is returned when the device is unplugged
+                Err(ref e) if e.raw_os_error() == Some(libc::EIO) => (),
+                x => return x,
+            }
+            eprintln!(
+                "pipecolor: {} disconnected, waiting for reconnect",
+                self.path.to_string_lossy()
+            );
+            self.file = None;
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+fn speed(baud: u32) -> Option<libc::speed_t> {
+    let ret = match baud {
+        1200 => libc::B1200,
+        2400 => libc::B2400,
+        4800 => libc::B4800,
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        #[cfg(target_os = "linux")]
+        460800 => libc::B460800,
+        #[cfg(target_os = "linux")]
+        921600 => libc::B921600,
+        _ => return None,
+    };
+    Some(ret)
+}
+
+// Open the device in raw mode with the baud rate
+pub fn open(path: &Path, baud: u32) -> Result<File> {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    let speed = speed(baud).context(format!("unsupported baud rate '{}'", baud))?;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)
+        .context(format!("failed to open '{}'", path.to_string_lossy()))?;
+
+    let fd = file.as_raw_fd();
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error())
+                .context(format!("'{}' is not a serial port", path.to_string_lossy()));
+        }
+        libc::cfmakeraw(&mut termios);
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        libc::cfsetispeed(&mut termios, speed);
+        libc::cfsetospeed(&mut termios, speed);
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error())
+                .context(format!("failed to configure '{}'", path.to_string_lossy()));
+        }
+    }
+    Ok(file)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_serial() {
+        assert_eq!(speed(115200), Some(libc::B115200));
+        assert_eq!(speed(12345), None);
+        assert!(open(Path::new("Cargo.toml"), 9600).is_err());
+
+        // A pseudo terminal stands in for the device
+        let (mut master, mut slave) = (0, 0);
+        let path = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            );
+            CStr::from_ptr(libc::ptsname(master))
+                .to_string_lossy()
+                .into_owned()
+        };
+        let mut reader = BufReader::new(SerialReader::new(Path::new(&path), 115200).unwrap());
+        unsafe {
+            libc::write(master, b"boot: ok\n".as_ptr() as *const libc::c_void, 9);
+        }
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "boot: ok\n");
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+}
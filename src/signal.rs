@@ -0,0 +1,171 @@
+use std::io::Result;
+use std::process::{Command, ExitStatus};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(unix)]
+use std::sync::{Arc, Mutex};
+
+// -------------------------------------------------------------------------------------------------
+// SignalForwarder
+// -------------------------------------------------------------------------------------------------
+
+// Forward SIGINT/SIGTERM/SIGHUP to the process group of the child while alive.
+// The child should be spawned by `spawn_group` to have its own process group.
+// After drop, the signals take the default action again.
+#[cfg(unix)]
+pub struct SignalForwarder {
+    ids: Vec<signal_hook::SigId>,
+    default: Arc<AtomicBool>,
+}
+
+#[cfg(unix)]
+const FORWARDED_SIGNALS: [i32; 3] = [
+    signal_hook::consts::SIGINT,
+    signal_hook::consts::SIGTERM,
+    signal_hook::consts::SIGHUP,
+];
+
+#[cfg(unix)]
+impl SignalForwarder {
+    pub fn new(pid: u32) -> Result<Self> {
+        let default = default_action()?;
+        default.store(false, Ordering::SeqCst);
+        let mut forwarder = SignalForwarder {
+            ids: Vec::new(),
+            default,
+        };
+        for sig in FORWARDED_SIGNALS {
+            // kill is async-signal-safe
+            let id = unsafe {
+                signal_hook::low_level::register(sig, move || {
+                    libc::kill(-(pid as libc::pid_t), sig);
+                })?
+            };
+            forwarder.ids.push(id);
+        }
+        Ok(forwarder)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SignalForwarder {
+    fn drop(&mut self) {
+        for id in self.ids.drain(..) {
+            signal_hook::low_level::unregister(id);
+        }
+        self.default.store(true, Ordering::SeqCst);
+    }
+}
+
+// The flag to run the default action of the forwarded signals.
+// signal-hook keeps its own handler installed after unregistering actions, so the signals would be
+// ignored without this.
+#[cfg(unix)]
+fn default_action() -> Result<Arc<AtomicBool>> {
+    static DEFAULT: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+
+    let mut default = DEFAULT.lock().unwrap();
+    if let Some(ref flag) = *default {
+        return Ok(flag.clone());
+    }
+    let flag = Arc::new(AtomicBool::new(true));
+    for sig in FORWARDED_SIGNALS {
+        signal_hook::flag::register_conditional_default(sig, flag.clone())?;
+    }
+    *default = Some(flag.clone());
+    Ok(flag)
+}
+
+#[cfg(not(unix))]
+pub struct SignalForwarder;
+
+#[cfg(not(unix))]
+impl SignalForwarder {
+    pub fn new(_pid: u32) -> Result<Self> {
+        Ok(SignalForwarder)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(unix)]
+pub fn spawn_group(command: &mut Command) -> &mut Command {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0)
+}
+
+#[cfg(not(unix))]
+pub fn spawn_group(command: &mut Command) -> &mut Command {
+    command
+}
+
+// The exit code of the child like shells, which is 128+N if it was killed by signal N
+#[cfg(unix)]
+pub fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status
+        .code()
+        .or_else(|| status.signal().map(|x| 128 + x))
+        .unwrap_or(1)
+}
+
+#[cfg(not(unix))]
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_code() {
+        let status = Command::new("sh").args(["-c", "exit 3"]).status().unwrap();
+        assert_eq!(exit_code(status), 3);
+        let status = Command::new("sh")
+            .args(["-c", "kill -TERM $$"])
+            .status()
+            .unwrap();
+        assert_eq!(exit_code(status), 128 + 15);
+    }
+
+    // The signals are raised in a child process running this test again, not to kill the harness
+    #[cfg(unix)]
+    #[test]
+    fn test_forward() {
+        if std::env::var_os("PIPECOLOR_TEST_FORWARD").is_some() {
+            let mut child = spawn_group(Command::new("sleep").arg("10"))
+                .spawn()
+                .unwrap();
+            let forwarder = SignalForwarder::new(child.id()).unwrap();
+            unsafe {
+                libc::kill(libc::getpid(), libc::SIGTERM);
+            }
+            let status = child.wait().unwrap();
+            drop(forwarder);
+            println!("forwarded: {}", exit_code(status));
+            // The default action terminates this process
+            unsafe {
+                libc::kill(libc::getpid(), libc::SIGTERM);
+            }
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            return;
+        }
+
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "signal::tests::test_forward", "--nocapture"])
+            .env("PIPECOLOR_TEST_FORWARD", "1")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("forwarded: 143"), "{}", stdout);
+        assert_eq!(exit_code(output.status), 128 + 15);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::colorize::{strip_sgr, strip_sgr_bytes};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, ErrorKind, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Whether the reader of stdout exited like `pipecolor file | head`
+static BROKEN_PIPE: AtomicBool = AtomicBool::new(false);
+
+// -------------------------------------------------------------------------------------------------
+// Sink
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    // A JSON object per line like `{"line":"..."}`
+    Json,
+}
+
+// A destination of output.
+// Sinks receive the output with color sequences, and remove them if color is disabled for it.
+pub trait Sink {
+    fn color(&self) -> bool;
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+pub struct WriterSink<W: Write> {
+    inner: W,
+    color: bool,
+    format: Format,
+    line: Vec<u8>,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(inner: W, color: bool, format: Format) -> Self {
+        WriterSink {
+            inner,
+            color,
+            format,
+            line: Vec::new(),
+        }
+    }
+
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.color {
+            self.inner.write_all(buf)
+        } else {
+            self.inner.write_all(&strip_sgr_bytes(buf))
+        }
+    }
+
+    fn write_json(&mut self) -> io::Result<()> {
+        let line = String::from_utf8_lossy(&self.line);
+        let line = line.trim_end_matches(&['\n', '\r'][..]);
+        let line = if self.color {
+            line.to_string()
+        } else {
+            strip_sgr(line)
+        };
+        let json = serde_json::json!({ "line": line });
+        self.line.clear();
+        writeln!(self.inner, "{}", json)
+    }
+}
+
+impl<W: Write> Sink for WriterSink<W> {
+    fn color(&self) -> bool {
+        self.color
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self.format {
+            Format::Text => self.write_text(buf),
+            Format::Json => {
+                // Buffer until the end of line because a record can't be split
+                for x in buf.split_inclusive(|x| *x == b'\n') {
+                    self.line.extend_from_slice(x);
+                    if x.ends_with(b"\n") {
+                        self.write_json()?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for WriterSink<W> {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            let _ = self.write_json();
+        }
+        let _ = self.inner.flush();
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// PipeWriter
+// -------------------------------------------------------------------------------------------------
+
+// Record the broken pipe of stdout to stop reading input.
+// SIGPIPE is kept ignored not to be killed by the sockets of --tee and --listen.
+pub struct PipeWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> PipeWriter<W> {
+    pub fn new(inner: W) -> Self {
+        PipeWriter { inner }
+    }
+}
+
+impl<W: Write> Write for PipeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).map_err(check_broken_pipe)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().map_err(check_broken_pipe)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Fanout
+// -------------------------------------------------------------------------------------------------
+
+// Write the output to all sinks
+pub struct Fanout {
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl Fanout {
+    pub fn new(sinks: Vec<Box<dyn Sink>>) -> Self {
+        Fanout { sinks }
+    }
+}
+
+impl Write for Fanout {
+    // The first error is returned after writing to all sinks
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut ret = Ok(buf.len());
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.write(buf) {
+                if ret.is_ok() {
+                    ret = Err(e);
+                }
+            }
+        }
+        ret
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut ret = Ok(());
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.flush() {
+                if ret.is_ok() {
+                    ret = Err(e);
+                }
+            }
+        }
+        ret
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+fn check_broken_pipe(e: io::Error) -> io::Error {
+    if e.kind() == ErrorKind::BrokenPipe {
+        BROKEN_PIPE.store(true, Ordering::Relaxed);
+    }
+    e
+}
+
+// Whether the output is discarded because the reader of stdout exited
+pub fn is_broken_pipe() -> bool {
+    BROKEN_PIPE.load(Ordering::Relaxed)
+}
+
+// Open the sink specified like `PATH[,color=always|never][,format=text|json]`.
+// `tcp://HOST:PORT` connects to the address instead of creating a file.
+pub fn open_sink(spec: &str) -> Result<Box<dyn Sink>> {
+    let mut parts = spec.split(',');
+    let target = parts.next().unwrap_or_default();
+    let mut color = false;
+    let mut format = Format::Text;
+    for x in parts {
+        match x.split_once('=') {
+            Some(("color", "always")) => color = true,
+            Some(("color", "never")) => color = false,
+            Some(("format", "text")) => format = Format::Text,
+            Some(("format", "json")) => format = Format::Json,
+            _ => anyhow::bail!("invalid option '{}' of '{}'", x, spec),
+        }
+    }
+    if target.is_empty() {
+        anyhow::bail!("no destination in '{}'", spec);
+    }
+
+    if let Some(addr) = target.strip_prefix("tcp://") {
+        let stream =
+            TcpStream::connect(addr).context(format!("failed to connect to '{}'", addr))?;
+        Ok(Box::new(WriterSink::new(stream, color, format)))
+    } else {
+        let file = File::create(target).context(format!("failed to create '{}'", target))?;
+        Ok(Box::new(WriterSink::new(file, color, format)))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_text() {
+        let mut sink = WriterSink::new(Vec::new(), false, Format::Text);
+        sink.write(b"\x1b[31mError\x1b[0m\n").unwrap();
+        assert_eq!(sink.inner, b"Error\n");
+
+        // Invalid UTF-8 is written as is without colors
+        let mut sink = WriterSink::new(Vec::new(), false, Format::Text);
+        sink.write(b"\x1b[31m\xffError\x1b[0m\n").unwrap();
+        assert_eq!(sink.inner, b"\xffError\n");
+
+        let mut sink = WriterSink::new(Vec::new(), true, Format::Text);
+        sink.write(b"\x1b[31mError\x1b[0m\n").unwrap();
+        assert_eq!(sink.inner, b"\x1b[31mError\x1b[0m\n");
+    }
+
+    #[test]
+    fn test_json() {
+        let mut sink = WriterSink::new(Vec::new(), false, Format::Json);
+        sink.write(b"[a] ").unwrap();
+        sink.write(b"\x1b[31m\"Error\"\x1b[0m\nWarn").unwrap();
+        assert_eq!(sink.inner, b"{\"line\":\"[a] \\\"Error\\\"\"}\n");
+        sink.write(b"ing\n").unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&sink.inner),
+            "{\"line\":\"[a] \\\"Error\\\"\"}\n{\"line\":\"Warning\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_open_sink() {
+        let path = std::env::temp_dir().join(format!("pipecolor_test_sink_{}", std::process::id()));
+        let spec = format!("{},format=json", path.to_string_lossy());
+        let mut fanout = Fanout::new(vec![open_sink(&spec).unwrap()]);
+        fanout.write_all(b"\x1b[31mError\x1b[0m").unwrap();
+        drop(fanout);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"line\":\"Error\"}\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(open_sink("a.log,color=auto").is_err());
+        assert!(open_sink(",color=always").is_err());
+    }
+
+    #[test]
+    fn test_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let spec = format!("tcp://{},color=always", listener.local_addr().unwrap());
+        let mut sink = open_sink(&spec).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        sink.write(b"\x1b[31mError\x1b[0m\n").unwrap();
+        drop(sink);
+        let mut s = String::new();
+        stream.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "\x1b[31mError\x1b[0m\n");
+    }
+}
@@ -0,0 +1,116 @@
+use crate::colorize::{has_sgr, strip_sgr, Config};
+
+// The width which digit runs are zero-padded to
+const DIGITS: usize = 12;
+
+// -------------------------------------------------------------------------------------------------
+// SortKey
+// -------------------------------------------------------------------------------------------------
+
+// Extract the sortable key of lines from the named capture of rules.
+// Lines without the key take over the key of the previous line to stay after it.
+pub struct SortKey {
+    name: String,
+    last: String,
+}
+
+impl SortKey {
+    pub fn new(name: &str) -> Self {
+        SortKey {
+            name: name.to_string(),
+            last: String::new(),
+        }
+    }
+
+    pub fn get(&mut self, buf: &[u8], config: &Config) -> &str {
+        let s = String::from_utf8_lossy(buf);
+        let s = if has_sgr(&s) {
+            strip_sgr(&s)
+        } else {
+            s.into_owned()
+        };
+        let pats = config
+            .lines
+            .iter()
+            .flat_map(|x| std::iter::once(&x.pat).chain(x.tokens.iter().map(|x| &x.pat)));
+        for pat in pats {
+            if !pat.capture_names().any(|x| x == Some(&self.name)) {
+                continue;
+            }
+            if let Some(x) = pat.captures(&s).and_then(|x| x.name(&self.name)) {
+                self.last = normalize(x.as_str());
+                break;
+            }
+        }
+        &self.last
+    }
+}
+
+// Zero-pad digit runs so that byte order matches numeric order regardless of the locale.
+// Fractional digits after a decimal point are padded to the right.
+// Whitespaces are replaced not to be confused with the separator.
+pub fn normalize(s: &str) -> String {
+    let mut ret = String::new();
+    let mut digits = String::new();
+    for c in s.chars().chain(std::iter::once('\0')) {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        if !digits.is_empty() {
+            let fraction =
+                ret.ends_with('.') && ret[..ret.len() - 1].ends_with(|x: char| x.is_ascii_digit());
+            if fraction {
+                ret.push_str(&format!("{:0<width$}", digits, width = DIGITS));
+            } else {
+                ret.push_str(&format!("{:0>width$}", digits, width = DIGITS));
+            }
+            digits.clear();
+        }
+        match c {
+            '\0' => (),
+            x if x.is_whitespace() => ret.push('_'),
+            x => ret.push(x),
+        }
+    }
+    ret
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("12:5"), "000000000012:000000000005");
+        assert_eq!(normalize("a 1"), "a_000000000001");
+        assert!(normalize("9.5") < normalize("10.1"));
+        assert!(normalize("3.25") < normalize("3.3"));
+    }
+
+    #[test]
+    fn test_sort_key() {
+        let config: Config = toml::from_str(
+            r#"
+            [[lines]]
+            pat = "^\\[(?P<ts>[0-9.]+)\\] .*"
+            colors = ["Green"]
+            "#,
+        )
+        .unwrap();
+        let mut key = SortKey::new("ts");
+        assert_eq!(key.get(b"before\n", &config), "");
+        assert_eq!(
+            key.get(b"\x1b[1m[3.25]\x1b[m boot\n", &config),
+            "000000000003.250000000000"
+        );
+        assert_eq!(
+            key.get(b"  continued\n", &config),
+            "000000000003.250000000000"
+        );
+    }
+}
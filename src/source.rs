@@ -0,0 +1,965 @@
+use crate::compress;
+use crate::docker;
+use crate::follow::{self, seek_last_lines, FifoReader, FollowReader};
+use crate::kube::{self, Pod};
+use crate::mmap::MmapReader;
+use crate::pidof;
+#[cfg(all(
+    target_os = "linux",
+    target_arch = "x86_64",
+    any(target_env = "gnu", target_env = "musl")
+))]
+use crate::pidof::ProcessReader;
+use crate::read_timeout::read_until_timeout;
+#[cfg(unix)]
+use crate::read_timeout::PollReader;
+#[cfg(unix)]
+use crate::serial::SerialReader;
+use crate::syslog::{self, SyslogReader};
+#[cfg(all(
+    target_os = "linux",
+    target_arch = "x86_64",
+    any(target_env = "gnu", target_env = "musl")
+))]
+use crate::trace;
+use crate::walk::Walker;
+use crate::Opt;
+use anyhow::{Context, Result};
+#[cfg(all(
+    target_os = "linux",
+    target_arch = "x86_64",
+    any(target_env = "gnu", target_env = "musl")
+))]
+use proc_reader::ProcReader;
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{self, stdin, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::net::{TcpListener, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(unix)]
+use std::time::Duration;
+
+// -------------------------------------------------------------------------------------------------
+// Source
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SourceKind {
+    Stdin,
+    File,
+    Process,
+    Container,
+    Listener,
+    Connection,
+    Syslog,
+    Serial,
+}
+
+// An input of lines. Follow mode, labels and hooks are implemented for any source through this.
+pub trait Source {
+    fn name(&self) -> String;
+
+    // The key to tell the source from others, which is the name unless the name may be duplicated
+    fn key(&self) -> String {
+        self.name()
+    }
+
+    fn kind(&self) -> SourceKind;
+
+    // Whether the source can be opened again from the beginning
+    fn restartable(&self) -> bool;
+
+    // Whether the source may grow after EOF, which is only checkable by reopening it
+    fn can_follow(&self) -> bool {
+        self.restartable()
+    }
+
+    fn open(&self, opt: &Opt) -> Result<Box<dyn BufRead + Send>>;
+
+    // Sources appearing from the source later (e.g. child processes)
+    fn children(&self) -> Vec<Box<dyn Source>> {
+        Vec::new()
+    }
+}
+
+pub struct StdinSource;
+
+impl Source for StdinSource {
+    fn name(&self) -> String {
+        String::from("-")
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Stdin
+    }
+
+    // Stdin can't be followed because it doesn't grow after EOF
+    fn restartable(&self) -> bool {
+        false
+    }
+
+    fn open(&self, opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        // A partial line is returned by the timeout
+        let mut reader = get_reader_stdin(
+            opt.flush_partial
+                .map_or(opt.timeout, |x| x.min(opt.timeout)),
+        )?;
+        skip_bytes(&mut *reader, opt.skip_bytes.unwrap_or(0))?;
+        skip_lines(&mut *reader, opt.skip_lines)?;
+        Ok(reader)
+    }
+}
+
+pub struct FileSource {
+    pub path: PathBuf,
+}
+
+impl Source for FileSource {
+    fn name(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::File
+    }
+
+    fn restartable(&self) -> bool {
+        true
+    }
+
+    fn open(&self, opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        // The mapping doesn't grow with the file
+        let mmap = !opt.no_mmap && !opt.follow;
+        let mut reader = get_reader_file(
+            &self.path,
+            opt.follow || opt.follow_name,
+            opt.follow_name,
+            opt.lines,
+            opt.skip_bytes.unwrap_or(0),
+            opt.reopen_fifo,
+            mmap,
+        )?;
+        skip_lines(&mut *reader, opt.skip_lines)
+            .context(format!("failed to read '{}'", self.name()))?;
+        Ok(reader)
+    }
+}
+
+pub struct ProcessSource {
+    pub pid: i32,
+    attacher: Arc<dyn Attach>,
+}
+
+impl ProcessSource {
+    pub fn new(pid: i32, attacher: Arc<dyn Attach>) -> Self {
+        ProcessSource { pid, attacher }
+    }
+}
+
+impl Source for ProcessSource {
+    fn name(&self) -> String {
+        format!("pid {}", self.pid)
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Process
+    }
+
+    // The reader waits for output until the process exits
+    fn restartable(&self) -> bool {
+        false
+    }
+
+    fn open(&self, opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        self.attacher.attach(self.pid, &opt.process_stream)
+    }
+
+    fn children(&self) -> Vec<Box<dyn Source>> {
+        pidof::find_descendants(&[self.pid])
+            .into_iter()
+            .map(|x| Box::new(ProcessSource::new(x, self.attacher.clone())) as Box<dyn Source>)
+            .collect()
+    }
+}
+
+// A stream of an attached process by --process-stream tag, which is attached with the other stream
+pub struct StreamSource {
+    pid: i32,
+    stream: &'static str,
+    reader: RefCell<Option<Box<dyn BufRead + Send>>>,
+}
+
+impl Source for StreamSource {
+    fn name(&self) -> String {
+        format!("pid {} {}", self.pid, self.stream)
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Process
+    }
+
+    fn restartable(&self) -> bool {
+        false
+    }
+
+    fn open(&self, _opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        self.reader
+            .borrow_mut()
+            .take()
+            .context(format!("'{}' is already read", self.name()))
+    }
+}
+
+pub struct DockerSource {
+    pub container: String,
+}
+
+impl Source for DockerSource {
+    fn name(&self) -> String {
+        self.container.clone()
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Container
+    }
+
+    // The logs are followed by the Docker daemon until the container stops
+    fn restartable(&self) -> bool {
+        false
+    }
+
+    fn open(&self, opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        docker::logs(
+            &self.container,
+            opt.follow || opt.follow_name,
+            opt.since.as_deref(),
+        )
+    }
+}
+
+pub struct KubeSource {
+    pub pod: Pod,
+}
+
+impl Source for KubeSource {
+    fn name(&self) -> String {
+        self.pod.label()
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Container
+    }
+
+    // The logs are followed by the API server until the container stops
+    fn restartable(&self) -> bool {
+        false
+    }
+
+    fn open(&self, opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        kube::logs(
+            &self.pod,
+            opt.follow || opt.follow_name,
+            opt.since.as_deref(),
+        )
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+// Accept connections, and read each connection as a child source
+pub struct ListenSource {
+    addr: String,
+    listener: Listener,
+    // The number of accepted connections
+    count: Cell<usize>,
+}
+
+impl ListenSource {
+    pub fn tcp(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr).context(format!("failed to listen '{}'", addr))?;
+        listener.set_nonblocking(true)?;
+        Ok(ListenSource {
+            addr: addr.to_string(),
+            listener: Listener::Tcp(listener),
+            count: Cell::new(0),
+        })
+    }
+
+    #[cfg(unix)]
+    pub fn unix(path: &Path) -> Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        // Remove the socket left by the previous run
+        if std::fs::metadata(path).is_ok_and(|x| x.file_type().is_socket()) {
+            let _ = std::fs::remove_file(path);
+        }
+        let listener = UnixListener::bind(path)
+            .context(format!("failed to listen '{}'", path.to_string_lossy()))?;
+        listener.set_nonblocking(true)?;
+        Ok(ListenSource {
+            addr: path.to_string_lossy().into_owned(),
+            listener: Listener::Unix(listener),
+            count: Cell::new(0),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn unix(_path: &Path) -> Result<Self> {
+        anyhow::bail!("--listen-unix option is supported on unix only")
+    }
+
+    fn accept(&self) -> io::Result<ConnectionSource> {
+        let (name, stream): (String, Box<dyn Read + Send>) = match self.listener {
+            Listener::Tcp(ref x) => {
+                let (stream, addr) = x.accept()?;
+                stream.set_nonblocking(false)?;
+                (addr.to_string(), Box::new(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix(ref x) => {
+                let (stream, _) = x.accept()?;
+                stream.set_nonblocking(false)?;
+                // Peers of unix sockets are unnamed
+                (format!("unix#{}", self.count.get() + 1), Box::new(stream))
+            }
+        };
+        self.count.set(self.count.get() + 1);
+        Ok(ConnectionSource {
+            key: format!("{}#{}", self.addr, self.count.get()),
+            name,
+            stream: RefCell::new(Some(stream)),
+        })
+    }
+}
+
+impl Source for ListenSource {
+    fn name(&self) -> String {
+        self.addr.clone()
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Listener
+    }
+
+    fn restartable(&self) -> bool {
+        false
+    }
+
+    // Lines are read from the connections only, and the listener never ends
+    fn open(&self, _opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        Ok(Box::new(BufReader::new(PendingReader)))
+    }
+
+    fn children(&self) -> Vec<Box<dyn Source>> {
+        let mut ret: Vec<Box<dyn Source>> = Vec::new();
+        while let Ok(x) = self.accept() {
+            ret.push(Box::new(x));
+        }
+        ret
+    }
+}
+
+// A connection accepted by ListenSource.
+// The name is the peer address, which may be reused, so the connection is identified by the key
+// numbered by the listener.
+pub struct ConnectionSource {
+    key: String,
+    name: String,
+    stream: RefCell<Option<Box<dyn Read + Send>>>,
+}
+
+impl Source for ConnectionSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn key(&self) -> String {
+        self.key.clone()
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Connection
+    }
+
+    fn restartable(&self) -> bool {
+        false
+    }
+
+    fn open(&self, _opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        let stream = self
+            .stream
+            .borrow_mut()
+            .take()
+            .context(format!("connection '{}' is already read", self.name))?;
+        Ok(Box::new(BufReader::new(stream)))
+    }
+}
+
+// Receive syslog datagrams
+pub struct SyslogSource {
+    addr: String,
+    socket: UdpSocket,
+}
+
+impl SyslogSource {
+    pub fn new(addr: &str) -> Result<Self> {
+        let addr = syslog::bind_addr(addr);
+        let socket = UdpSocket::bind(&addr).context(format!("failed to listen '{}'", addr))?;
+        Ok(SyslogSource { addr, socket })
+    }
+}
+
+impl Source for SyslogSource {
+    fn name(&self) -> String {
+        self.addr.clone()
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Syslog
+    }
+
+    fn restartable(&self) -> bool {
+        false
+    }
+
+    fn open(&self, _opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        let socket = self.socket.try_clone()?;
+        Ok(Box::new(BufReader::new(SyslogReader::new(socket))))
+    }
+}
+
+pub struct SerialSource {
+    pub path: PathBuf,
+    pub baud: u32,
+}
+
+impl Source for SerialSource {
+    fn name(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    fn kind(&self) -> SourceKind {
+        SourceKind::Serial
+    }
+
+    fn restartable(&self) -> bool {
+        false
+    }
+
+    #[cfg(unix)]
+    fn open(&self, _opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        let reader = SerialReader::new(&self.path, self.baud)?;
+        Ok(Box::new(BufReader::new(reader)))
+    }
+
+    #[cfg(not(unix))]
+    fn open(&self, _opt: &Opt) -> Result<Box<dyn BufRead + Send>> {
+        anyhow::bail!("--serial option is supported on unix only")
+    }
+}
+
+// A reader blocking forever
+struct PendingReader;
+
+impl Read for PendingReader {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            std::thread::park();
+        }
+    }
+}
+
+// Attaching to processes is abstracted to be replaced in tests
+pub trait Attach {
+    fn attach(&self, pid: i32, stream: &str) -> Result<Box<dyn BufRead + Send>>;
+
+    // Attach to stdout and stderr of the process separately
+    fn attach_streams(&self, pid: i32) -> Result<[Box<dyn BufRead + Send>; 2]>;
+}
+
+// Attach to processes by ptrace through proc-reader
+pub struct PtraceAttach;
+
+impl Attach for PtraceAttach {
+    fn attach(&self, pid: i32, stream: &str) -> Result<Box<dyn BufRead + Send>> {
+        get_reader_proc(pid, stream)
+    }
+
+    fn attach_streams(&self, pid: i32) -> Result<[Box<dyn BufRead + Send>; 2]> {
+        get_reader_streams(pid)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Registry
+// -------------------------------------------------------------------------------------------------
+
+// Return the sources specified by the FILE argument, or None if the argument is not for the kind
+type Resolver = fn(&Path, &Opt, &mut Walker) -> Option<Result<Vec<Box<dyn Source>>>>;
+
+// Resolvers tried in order for each FILE argument
+const RESOLVERS: [Resolver; 2] = [resolve_stdin, resolve_file];
+
+pub fn get_sources(opt: &Opt) -> Result<Vec<Box<dyn Source>>> {
+    if let Some(ref x) = opt.syslog_udp {
+        return Ok(vec![Box::new(SyslogSource::new(x)?)]);
+    } else if let Some(ref x) = opt.serial {
+        return Ok(vec![Box::new(SerialSource {
+            path: x.clone(),
+            baud: opt.baud,
+        })]);
+    } else if !opt.listen.is_empty() || !opt.listen_unix.is_empty() {
+        let mut ret: Vec<Box<dyn Source>> = Vec::new();
+        for x in &opt.listen {
+            ret.push(Box::new(ListenSource::tcp(x)?));
+        }
+        for x in &opt.listen_unix {
+            ret.push(Box::new(ListenSource::unix(x)?));
+        }
+        return Ok(ret);
+    } else if !opt.docker.is_empty() {
+        return Ok(opt
+            .docker
+            .iter()
+            .map(|x| {
+                Box::new(DockerSource {
+                    container: x.clone(),
+                }) as Box<dyn Source>
+            })
+            .collect());
+    } else if !opt.kube.is_empty() {
+        let mut ret: Vec<Box<dyn Source>> = Vec::new();
+        for x in &opt.kube {
+            let mut pod = Pod::parse(x)?;
+            if pod.container.is_none() {
+                pod.container = opt.container.clone();
+            }
+            for pod in kube::containers(&pod)? {
+                ret.push(Box::new(KubeSource { pod }));
+            }
+        }
+        return Ok(ret);
+    } else if opt.files.is_empty() {
+        return Ok(vec![Box::new(StdinSource)]);
+    }
+
+    let mut walker = Walker::new(opt.follow_symlinks, opt.max_depth);
+    let mut ret = Vec::new();
+    for x in &opt.files {
+        for resolver in &RESOLVERS {
+            if let Some(sources) = resolver(x, opt, &mut walker) {
+                ret.extend(sources?);
+                break;
+            }
+        }
+    }
+    Ok(ret)
+}
+
+pub fn get_process_sources(pids: Vec<i32>, attacher: Arc<dyn Attach>) -> Vec<Box<dyn Source>> {
+    pids.into_iter()
+        .map(|x| Box::new(ProcessSource::new(x, attacher.clone())) as Box<dyn Source>)
+        .collect()
+}
+
+// Attach to stdout and stderr of the processes as separate sources for --process-stream tag
+pub fn get_stream_sources(
+    pids: Vec<i32>,
+    attacher: Arc<dyn Attach>,
+) -> Result<Vec<Box<dyn Source>>> {
+    let mut ret: Vec<Box<dyn Source>> = Vec::new();
+    for pid in pids {
+        let [stdout, stderr] = attacher.attach_streams(pid)?;
+        for (stream, reader) in [("stdout", stdout), ("stderr", stderr)] {
+            ret.push(Box::new(StreamSource {
+                pid,
+                stream,
+                reader: RefCell::new(Some(reader)),
+            }));
+        }
+    }
+    Ok(ret)
+}
+
+fn resolve_stdin(
+    path: &Path,
+    _opt: &Opt,
+    _walker: &mut Walker,
+) -> Option<Result<Vec<Box<dyn Source>>>> {
+    if path.as_os_str() == "-" {
+        Some(Ok(vec![Box::new(StdinSource)]))
+    } else {
+        None
+    }
+}
+
+fn resolve_file(
+    path: &Path,
+    opt: &Opt,
+    walker: &mut Walker,
+) -> Option<Result<Vec<Box<dyn Source>>>> {
+    Some(resolve_file_inner(path, opt, walker))
+}
+
+fn resolve_file_inner(path: &Path, opt: &Opt, walker: &mut Walker) -> Result<Vec<Box<dyn Source>>> {
+    let mut ret: Vec<Box<dyn Source>> = Vec::new();
+    for x in expand_glob(path)? {
+        let files = if !x.is_dir() {
+            vec![x]
+        } else if opt.recursive {
+            walker.walk(&x)?
+        } else {
+            Walker::new(false, Some(0)).walk(&x)?
+        };
+        for path in files {
+            ret.push(Box::new(FileSource { path }));
+        }
+    }
+    Ok(ret)
+}
+
+// Expand the glob pattern which is not expanded by the shell
+fn expand_glob(path: &Path) -> Result<Vec<PathBuf>> {
+    let s = path.to_string_lossy();
+    if path.exists() || !s.contains(&['*', '?', '['][..]) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut ret = Vec::new();
+    for x in glob::glob(&s).context(format!("failed to parse glob '{}'", s))? {
+        ret.push(x?);
+    }
+    if ret.is_empty() {
+        ret.push(path.to_path_buf());
+    }
+    Ok(ret)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+fn get_reader_file(
+    path: &Path,
+    follow: bool,
+    follow_name: bool,
+    lines: Option<usize>,
+    skip: u64,
+    reopen_fifo: bool,
+    mmap: bool,
+) -> Result<Box<dyn BufRead + Send>> {
+    let mut f = File::open(path).context(format!("failed to open '{}'", path.to_string_lossy()))?;
+    if follow::is_fifo(path) {
+        // FIFOs can't be seeked to detect compression or the last lines
+        let mut reader: Box<dyn BufRead + Send> = if reopen_fifo {
+            Box::new(BufReader::new(FifoReader::new(path.to_path_buf(), f)))
+        } else {
+            Box::new(BufReader::new(f))
+        };
+        skip_bytes(&mut *reader, skip)
+            .context(format!("failed to read '{}'", path.to_string_lossy()))?;
+        return Ok(reader);
+    }
+    if let Some(x) =
+        compress::detect(&mut f).context(format!("failed to read '{}'", path.to_string_lossy()))?
+    {
+        // Compressed files can't be followed or seeked from the end
+        if follow {
+            anyhow::bail!(
+                "--follow can't be used with the compressed file '{}'",
+                path.to_string_lossy()
+            );
+        }
+        let reader = compress::decoder(f, x)
+            .context(format!("failed to decompress '{}'", path.to_string_lossy()))?;
+        let mut reader = BufReader::new(reader);
+        skip_bytes(&mut reader, skip)
+            .context(format!("failed to decompress '{}'", path.to_string_lossy()))?;
+        return Ok(Box::new(reader));
+    }
+    if let Some(n) = lines {
+        seek_last_lines(&mut f, n)
+            .context(format!("failed to seek '{}'", path.to_string_lossy()))?;
+    } else if skip > 0 {
+        f.seek(SeekFrom::Start(skip))
+            .context(format!("failed to seek '{}'", path.to_string_lossy()))?;
+    }
+    if follow_name {
+        return Ok(Box::new(BufReader::new(FollowReader::new(
+            path.to_path_buf(),
+            f,
+        )?)));
+    }
+    if mmap {
+        if let Some(x) = MmapReader::new(&mut f) {
+            return Ok(Box::new(x));
+        }
+    }
+    Ok(Box::new(BufReader::new(f)))
+}
+
+// Discard the first bytes of the reader for --skip-bytes
+fn skip_bytes(reader: &mut dyn BufRead, mut n: u64) -> io::Result<()> {
+    while n > 0 {
+        let len = match reader.fill_buf() {
+            Ok(x) => x.len().min(n as usize),
+            // Stdin returns the timeout while it is idle
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if len == 0 {
+            break;
+        }
+        reader.consume(len);
+        n -= len as u64;
+    }
+    Ok(())
+}
+
+// Discard the first lines of the reader for --skip-lines
+fn skip_lines(reader: &mut dyn BufRead, n: usize) -> io::Result<()> {
+    let mut buf = Vec::new();
+    let mut skipped = 0;
+    while skipped < n {
+        buf.clear();
+        match read_until_timeout(reader, b'\n', &mut buf)? {
+            (0, false) => break,
+            _ if buf.ends_with(b"\n") => skipped += 1,
+            // The rest of the line is read by the next call
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn get_reader_stdin(timeout_millis: u64) -> Result<Box<dyn BufRead + Send>> {
+    Ok(Box::new(BufReader::new(PollReader::new(
+        stdin(),
+        Duration::from_millis(timeout_millis),
+    ))))
+}
+
+#[cfg(not(unix))]
+fn get_reader_stdin(_timeout_millis: u64) -> Result<Box<dyn BufRead + Send>> {
+    Ok(Box::new(BufReader::new(stdin())))
+}
+
+// Read the pipe of a wrapped command.
+// With the timeout, a partial line is returned when the command is idle like prompts.
+#[cfg(unix)]
+pub fn get_reader_pipe<R: Read + AsRawFd + Send + 'static>(
+    pipe: R,
+    timeout_millis: Option<u64>,
+) -> Box<dyn BufRead + Send> {
+    match timeout_millis {
+        Some(x) => Box::new(BufReader::new(PollReader::new(
+            pipe,
+            Duration::from_millis(x),
+        ))),
+        None => Box::new(BufReader::new(pipe)),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn get_reader_pipe<R: Read + Send + 'static>(
+    pipe: R,
+    _timeout_millis: Option<u64>,
+) -> Box<dyn BufRead + Send> {
+    Box::new(BufReader::new(pipe))
+}
+
+#[cfg(all(
+    target_os = "linux",
+    target_arch = "x86_64",
+    any(target_env = "gnu", target_env = "musl")
+))]
+fn get_reader_proc(pid: i32, stream: &str) -> Result<Box<dyn BufRead + Send>> {
+    let reader = match stream {
+        "stdout" => ProcReader::from_stdout(pid as u32),
+        "stderr" => ProcReader::from_stderr(pid as u32),
+        _ => ProcReader::from_stdany(pid as u32),
+    };
+    let reader = ProcessReader::new(reader, pid, crate::FOLLOW_INTERVAL);
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    target_arch = "x86_64",
+    any(target_env = "gnu", target_env = "musl")
+)))]
+fn get_reader_proc(_pid: i32, _stream: &str) -> Result<Box<dyn BufRead + Send>> {
+    anyhow::bail!("--process option is supported on linux only")
+}
+
+#[cfg(all(
+    target_os = "linux",
+    target_arch = "x86_64",
+    any(target_env = "gnu", target_env = "musl")
+))]
+fn get_reader_streams(pid: i32) -> Result<[Box<dyn BufRead + Send>; 2]> {
+    let [stdout, stderr] = trace::attach(pid)?;
+    Ok([
+        Box::new(BufReader::new(stdout)),
+        Box::new(BufReader::new(stderr)),
+    ])
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    target_arch = "x86_64",
+    any(target_env = "gnu", target_env = "musl")
+)))]
+fn get_reader_streams(_pid: i32) -> Result<[Box<dyn BufRead + Send>; 2]> {
+    anyhow::bail!("--process option is supported on linux only")
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_sources() {
+        let args = vec!["pipecolor", "sample/access_log", "-", "sample/maillog"];
+        let opt = Opt::from_iter(args.iter());
+        let sources = get_sources(&opt).unwrap();
+        let names: Vec<_> = sources.iter().map(|x| x.name()).collect();
+        assert_eq!(names, vec!["sample/access_log", "-", "sample/maillog"]);
+        let kinds: Vec<_> = sources.iter().map(|x| x.kind()).collect();
+        assert_eq!(
+            kinds,
+            vec![SourceKind::File, SourceKind::Stdin, SourceKind::File]
+        );
+        assert!(sources[0].restartable() && sources[0].can_follow());
+        assert!(!sources[1].restartable() && !sources[1].can_follow());
+
+        let args = vec!["pipecolor"];
+        let opt = Opt::from_iter(args.iter());
+        let sources = get_sources(&opt).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].kind(), SourceKind::Stdin);
+    }
+
+    #[test]
+    fn test_skip() {
+        let mut reader = BufReader::with_capacity(4, &b"abc\ndefgh\nij\nk"[..]);
+        skip_bytes(&mut reader, 2).unwrap();
+        skip_lines(&mut reader, 2).unwrap();
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "ij\nk");
+
+        let mut reader = BufReader::new(&b"abc\nd"[..]);
+        skip_lines(&mut reader, 3).unwrap();
+        skip_bytes(&mut reader, 3).unwrap();
+        assert!(reader.fill_buf().unwrap().is_empty());
+
+        let opt = Opt::from_iter(["pipecolor", "--skip-bytes", "4"].iter());
+        let source = FileSource {
+            path: PathBuf::from("sample/maillog"),
+        };
+        let mut buf = String::new();
+        source.open(&opt).unwrap().read_line(&mut buf).unwrap();
+        let text = std::fs::read_to_string("sample/maillog").unwrap();
+        assert_eq!(buf, text[4..].lines().next().unwrap().to_string() + "\n");
+    }
+
+    #[test]
+    fn test_follow_compressed() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("pipecolor_follow_{}", std::process::id()));
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(b"Error: test\n").unwrap();
+        std::fs::write(&path, enc.finish().unwrap()).unwrap();
+        let source = FileSource { path: path.clone() };
+
+        let opt = Opt::from_iter(["pipecolor"].iter());
+        let mut buf = String::new();
+        source.open(&opt).unwrap().read_line(&mut buf).unwrap();
+        assert_eq!(buf, "Error: test\n");
+        for arg in &["--follow", "--follow-name"] {
+            let opt = Opt::from_iter(["pipecolor", *arg].iter());
+            let err = source.open(&opt).err().unwrap();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "--follow can't be used with the compressed file '{}'",
+                    source.name()
+                )
+            );
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_docker_sources() {
+        let args = vec!["pipecolor", "--docker", "web", "--docker", "db"];
+        let opt = Opt::from_iter(args.iter());
+        let sources = get_sources(&opt).unwrap();
+        let names: Vec<_> = sources.iter().map(|x| x.name()).collect();
+        assert_eq!(names, vec!["web", "db"]);
+        assert_eq!(sources[0].kind(), SourceKind::Container);
+        assert!(!sources[0].can_follow());
+
+        let args = vec!["pipecolor", "--docker", "web", "sample/maillog"];
+        assert!(Opt::from_iter_safe(args.iter()).is_err());
+    }
+
+    #[test]
+    fn test_listen() {
+        use std::io::Write;
+        use std::net::TcpStream;
+
+        let source = ListenSource::tcp("127.0.0.1:0").unwrap();
+        let addr = match source.listener {
+            Listener::Tcp(ref x) => x.local_addr().unwrap(),
+            #[cfg(unix)]
+            _ => unreachable!(),
+        };
+        assert!(source.children().is_empty());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"Error\n").unwrap();
+        drop(client);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        let children = source.children();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].kind(), SourceKind::Connection);
+        assert_eq!(children[0].key(), "127.0.0.1:0#1");
+
+        let opt = Opt::from_iter(["pipecolor"].iter());
+        let mut s = String::new();
+        children[0]
+            .open(&opt)
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!(s, "Error\n");
+        assert!(children[0].open(&opt).is_err());
+    }
+
+    #[test]
+    fn test_process_sources() {
+        let sources = get_process_sources(vec![123, 456], Arc::new(PtraceAttach));
+        let names: Vec<_> = sources.iter().map(|x| x.name()).collect();
+        assert_eq!(names, vec!["pid 123", "pid 456"]);
+        assert_eq!(sources[0].kind(), SourceKind::Process);
+    }
+}
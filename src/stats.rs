@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// -------------------------------------------------------------------------------------------------
+// Record
+// -------------------------------------------------------------------------------------------------
+
+// Rule match counts of a run, stored as a line of JSON lines
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Record {
+    pub time: u64,
+    pub matches: Vec<RuleCount>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct RuleCount {
+    pub rule: String,
+    pub count: usize,
+}
+
+impl Record {
+    pub fn new(matches: Vec<RuleCount>) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+        Record { time, matches }
+    }
+
+    fn count(&self, rule: &str) -> usize {
+        self.matches
+            .iter()
+            .find(|x| x.rule == rule)
+            .map_or(0, |x| x.count)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+pub fn append(path: &Path, record: &Record) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("failed to open '{}'", path.to_string_lossy()))?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Vec<Record>> {
+    let file = File::open(path).context(format!("failed to open '{}'", path.to_string_lossy()))?;
+    let mut ret = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).context(format!(
+            "failed to parse '{}' at line {}",
+            path.to_string_lossy(),
+            i + 1
+        ))?;
+        ret.push(record);
+    }
+    Ok(ret)
+}
+
+// Show the counts of each rule in the last runs from old to new, and the change of the last run
+pub fn show(records: &[Record], last: usize, writer: &mut dyn Write) -> Result<()> {
+    let records = &records[records.len().saturating_sub(last)..];
+    let mut rules: Vec<&str> = Vec::new();
+    for record in records {
+        for x in &record.matches {
+            if !rules.contains(&x.rule.as_str()) {
+                rules.push(&x.rule);
+            }
+        }
+    }
+
+    let width = rules.iter().map(|x| x.len()).max().unwrap_or(0).max(4);
+    writeln!(
+        writer,
+        "{:width$}  {:>8}  runs (old -> new)",
+        "rule", "change"
+    )?;
+    for rule in rules {
+        let counts: Vec<_> = records.iter().map(|x| x.count(rule)).collect();
+        let change = match counts.len() {
+            0 | 1 => String::from("-"),
+            n => format!("{:+}", counts[n - 1] as i64 - counts[n - 2] as i64),
+        };
+        let counts: Vec<_> = counts.iter().map(|x| x.to_string()).collect();
+        writeln!(
+            writer,
+            "{:width$}  {:>8}  {}",
+            rule,
+            change,
+            counts.join(" ")
+        )?;
+    }
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(time: u64, matches: &[(&str, usize)]) -> Record {
+        let matches = matches
+            .iter()
+            .map(|(rule, count)| RuleCount {
+                rule: rule.to_string(),
+                count: *count,
+            })
+            .collect();
+        Record { time, matches }
+    }
+
+    #[test]
+    fn test_append_load() {
+        let path =
+            std::env::temp_dir().join(format!("pipecolor_test_stats_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        append(&path, &record(1, &[("Error", 3)])).unwrap();
+        append(&path, &record(2, &[("Error", 1), ("Warning", 2)])).unwrap();
+        let records = load(&path).unwrap();
+        assert_eq!(records[0], record(1, &[("Error", 3)]));
+        assert_eq!(records[1], record(2, &[("Error", 1), ("Warning", 2)]));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_show() {
+        let records = vec![
+            record(1, &[("Error", 5)]),
+            record(2, &[("Error", 3)]),
+            record(3, &[("Error", 4), ("Warning", 2)]),
+        ];
+        let mut writer = Vec::new();
+        show(&records, 2, &mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "rule       change  runs (old -> new)\n\
+             Error          +1  3 4\n\
+             Warning        +2  0 2\n"
+        );
+    }
+}
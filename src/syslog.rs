@@ -0,0 +1,317 @@
+use crate::colorize::{Config, Line, StyleDef};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::UdpSocket;
+
+// -------------------------------------------------------------------------------------------------
+// Message
+// -------------------------------------------------------------------------------------------------
+
+pub static SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+// A syslog message of RFC3164 or RFC5424
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: String,
+    pub host: String,
+    pub tag: String,
+    pub msg: String,
+}
+
+impl Message {
+    // The host is used if the message doesn't have it
+    pub fn parse(s: &str, host: &str) -> Self {
+        let (pri, rest) = parse_pri(s);
+        let mut ret = match rest.strip_prefix("1 ") {
+            Some(x) => parse_5424(x),
+            None => parse_3164(rest),
+        };
+        ret.facility = pri / 8;
+        ret.severity = pri % 8;
+        if ret.host.is_empty() || ret.host == "-" {
+            ret.host = host.to_string();
+        }
+        ret
+    }
+
+    // Format as a line led by the severity
+    pub fn format(&self) -> String {
+        format!(
+            "{:<7} {} {} {}: {}\n",
+            SEVERITIES[self.severity as usize], self.timestamp, self.host, self.tag, self.msg
+        )
+    }
+}
+
+// The default priority is user.notice
+fn parse_pri(s: &str) -> (u8, &str) {
+    let pri = s
+        .strip_prefix('<')
+        .and_then(|x| x.split_once('>'))
+        .and_then(|(pri, rest)| pri.parse::<u8>().ok().map(|x| (x, rest)))
+        .filter(|(pri, _)| *pri < 192);
+    pri.unwrap_or((13, s))
+}
+
+// TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+fn parse_5424(s: &str) -> Message {
+    let mut parts = s.splitn(6, ' ');
+    let timestamp = parts.next().unwrap_or("-").to_string();
+    let host = parts.next().unwrap_or("-").to_string();
+    let app = parts.next().unwrap_or("-");
+    let procid = parts.next().unwrap_or("-");
+    let _msgid = parts.next();
+    let rest = parts.next().unwrap_or("");
+    let msg = skip_structured_data(rest);
+    let msg = msg.strip_prefix('\u{feff}').unwrap_or(msg);
+    let tag = if procid == "-" {
+        app.to_string()
+    } else {
+        format!("{}[{}]", app, procid)
+    };
+    Message {
+        facility: 0,
+        severity: 0,
+        timestamp,
+        host,
+        tag,
+        msg: msg.trim_end().to_string(),
+    }
+}
+
+fn skip_structured_data(s: &str) -> &str {
+    if let Some(x) = s.strip_prefix('-') {
+        return x.trim_start();
+    }
+    let b = s.as_bytes();
+    let mut i = 0;
+    while b.get(i) == Some(&b'[') {
+        while i < b.len() && b[i] != b']' {
+            if b[i] == b'\\' {
+                i += 1;
+            } else if b[i] == b'"' {
+                // Skip the quoted value which may include ']'
+                i += 1;
+                while i < b.len() && b[i] != b'"' {
+                    if b[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            i += 1;
+        }
+        i += 1;
+    }
+    s[i.min(s.len())..].trim_start()
+}
+
+// Mmm dd hh:mm:ss HOSTNAME TAG: MSG
+fn parse_3164(s: &str) -> Message {
+    let has_timestamp = s.len() >= 16
+        && s.is_char_boundary(15)
+        && s.as_bytes()[15] == b' '
+        && s[..15].split(':').count() == 3;
+    let (timestamp, rest) = if has_timestamp {
+        (s[..15].to_string(), &s[16..])
+    } else {
+        (String::from("-"), s)
+    };
+    let (host, rest) = if has_timestamp {
+        rest.split_once(' ').unwrap_or(("", rest))
+    } else {
+        ("", rest)
+    };
+    let (tag, msg) = match rest.split_once(": ") {
+        Some((tag, msg)) if !tag.contains(' ') => (tag, msg),
+        _ => ("-", rest),
+    };
+    Message {
+        facility: 0,
+        severity: 0,
+        timestamp,
+        host: host.to_string(),
+        tag: tag.to_string(),
+        msg: msg.trim_end().to_string(),
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// SyslogReader
+// -------------------------------------------------------------------------------------------------
+
+// Receive syslog datagrams, and read them as formatted lines
+pub struct SyslogReader {
+    socket: UdpSocket,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl SyslogReader {
+    pub fn new(socket: UdpSocket) -> Self {
+        SyslogReader {
+            socket,
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for SyslogReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            let mut datagram = [0; 65536];
+            let (len, addr) = self.socket.recv_from(&mut datagram)?;
+            let s = String::from_utf8_lossy(&datagram[..len]);
+            let msg = Message::parse(&s, &addr.ip().to_string());
+            self.line = msg.format().into_bytes();
+            self.pos = 0;
+        }
+        let len = buf.len().min(self.line.len() - self.pos);
+        buf[..len].copy_from_slice(&self.line[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// The address of --syslog-udp, which may be a port only
+pub fn bind_addr(s: &str) -> String {
+    if s.parse::<u16>().is_ok() {
+        format!("0.0.0.0:{}", s)
+    } else {
+        s.to_string()
+    }
+}
+
+// Line rules colorizing lines by the severity.
+// The named style `syslog_SEVERITY` is used if it is defined.
+pub fn rules(styles: &HashMap<String, StyleDef>) -> Result<Vec<Line>> {
+    let defaults = [
+        "LightRed+Bold",
+        "LightRed+Bold",
+        "LightRed+Bold",
+        "Red",
+        "Yellow",
+        "Cyan",
+        "Green",
+        "LightBlack",
+    ];
+    let mut toml = String::new();
+    for (severity, default) in SEVERITIES.iter().zip(defaults.iter()) {
+        let style = format!("syslog_{}", severity);
+        let color = if styles.contains_key(&style) {
+            format!("@{}", style)
+        } else {
+            default.to_string()
+        };
+        toml.push_str(&format!(
+            "[[lines]]\npat = '^({}) .*'\ncolors = ['{}', '+Bold']\n",
+            severity, color
+        ));
+    }
+    let config: Config = toml::from_str(&toml)?;
+    Ok(config.lines)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_3164() {
+        let msg = Message::parse(
+            "<34>Oct 11 22:14:15 mymachine su[123]: 'su root' failed on /dev/pts/8\n",
+            "10.0.0.1",
+        );
+        assert_eq!(
+            msg,
+            Message {
+                facility: 4,
+                severity: 2,
+                timestamp: String::from("Oct 11 22:14:15"),
+                host: String::from("mymachine"),
+                tag: String::from("su[123]"),
+                msg: String::from("'su root' failed on /dev/pts/8"),
+            }
+        );
+        assert_eq!(
+            msg.format(),
+            "crit    Oct 11 22:14:15 mymachine su[123]: 'su root' failed on /dev/pts/8\n"
+        );
+
+        let msg = Message::parse("no header", "10.0.0.1");
+        assert_eq!(msg.format(), "notice  - 10.0.0.1 -: no header\n");
+    }
+
+    #[test]
+    fn test_5424() {
+        let msg = Message::parse(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 \
+             [exampleSDID@32473 iut=\"3\" eventID=\"1011\" x=\"a]b\"] An application event",
+            "10.0.0.1",
+        );
+        assert_eq!(msg.facility, 20);
+        assert_eq!(msg.severity, 5);
+        assert_eq!(msg.timestamp, "2003-10-11T22:14:15.003Z");
+        assert_eq!(msg.host, "mymachine.example.com");
+        assert_eq!(msg.tag, "evntslog");
+        assert_eq!(msg.msg, "An application event");
+
+        let msg = Message::parse("<11>1 - - app 42 - - disk full", "10.0.0.1");
+        assert_eq!(msg.format(), "err     - 10.0.0.1 app[42]: disk full\n");
+    }
+
+    #[test]
+    fn test_reader() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .send_to(b"<12>1 - host app - - - low disk", addr)
+            .unwrap();
+        client.send_to(b"<14>1 - host app - - - ok", addr).unwrap();
+
+        let mut reader = BufReader::new(SyslogReader::new(socket));
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "warning - host app: low disk\n");
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "info    - host app: ok\n");
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut styles = HashMap::new();
+        styles.insert(
+            String::from("syslog_err"),
+            StyleDef {
+                fg: Some(String::from("Magenta")),
+                bold: false,
+                italic: false,
+                underline: false,
+            },
+        );
+        let lines = rules(&styles).unwrap();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[3].colors, vec!["@syslog_err", "+Bold"]);
+        assert_eq!(lines[4].colors, vec!["Yellow", "+Bold"]);
+        assert!(lines[4].pat.is_match("warning - host app: low disk\n"));
+        assert_eq!(bind_addr("514"), "0.0.0.0:514");
+        assert_eq!(bind_addr("127.0.0.1:5514"), "127.0.0.1:5514");
+    }
+}
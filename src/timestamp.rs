@@ -0,0 +1,217 @@
+use crate::colorize::strip_sgr;
+use regex::{Captures, Regex};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+// -------------------------------------------------------------------------------------------------
+// Since
+// -------------------------------------------------------------------------------------------------
+
+// Skip lines until the first line whose timestamp is not older than the time.
+// Lines without the timestamp before it are skipped too because they belong to the skipped lines.
+pub struct Since {
+    timestamp: Regex,
+    time: u64,
+    now: u64,
+    started: bool,
+}
+
+impl Since {
+    pub fn new(timestamp: Regex, time: u64, now: u64) -> Self {
+        Since {
+            timestamp,
+            time,
+            now,
+            started: false,
+        }
+    }
+
+    pub fn is_before(&mut self, buf: &[u8]) -> bool {
+        if self.started {
+            return false;
+        }
+        let s = strip_sgr(&String::from_utf8_lossy(buf));
+        let time = self
+            .timestamp
+            .captures(&s)
+            .and_then(|x| parse(&x, self.now, local_offset));
+        self.started = time.is_some_and(|x| x >= self.time);
+        !self.started
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// Convert the named captures year, month, day, hour, minute, second and offset to the UNIX time.
+// The current year is assumed without year like syslog, and the local offset without offset.
+pub fn parse(caps: &Captures, now: u64, offset: fn(i64) -> i64) -> Option<u64> {
+    let get = |name: &str| caps.name(name).map(|x| x.as_str());
+    let num = |name: &str| get(name).map_or(Some(0), |x| x.parse::<i64>().ok());
+
+    let month = match get("month")? {
+        x if x.chars().all(|x| x.is_ascii_digit()) => x.parse().ok()?,
+        x => {
+            let x = x.get(..3)?.to_ascii_lowercase();
+            MONTHS.iter().position(|y| *y == x)? as i64 + 1
+        }
+    };
+    let day: i64 = get("day")?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let secs = num("hour")? * 3600 + num("minute")? * 60 + num("second")?;
+
+    let at = |year: i64| {
+        let local = days_from_civil(year, month, day) * 86400 + secs;
+        match get("offset") {
+            Some(x) => parse_offset(x).map(|x| local - x),
+            None => Some(local - offset(local)),
+        }
+    };
+    let time = match get("year") {
+        Some(x) => at(x.parse().ok()?)?,
+        None => {
+            let year = civil_from_days(now as i64 / 86400).0;
+            // Lines of the last year appear in January
+            let time = at(year)?;
+            if time > now as i64 + 86400 {
+                at(year - 1)?
+            } else {
+                time
+            }
+        }
+    };
+    Some(time.max(0) as u64)
+}
+
+// Parse the offset like Z, +09:00 or -0500 to seconds
+fn parse_offset(s: &str) -> Option<i64> {
+    if s.eq_ignore_ascii_case("z") {
+        return Some(0);
+    }
+    let sign = match s.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let digits: String = s[1..].chars().filter(|x| *x != ':').collect();
+    if digits.len() != 4 || !digits.chars().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let hour: i64 = digits[..2].parse().ok()?;
+    let minute: i64 = digits[2..].parse().ok()?;
+    Some(sign * (hour * 3600 + minute * 60))
+}
+
+// Days since 1970-01-01 of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = if month <= 2 {
+        yoe + era * 400 + 1
+    } else {
+        yoe + era * 400
+    };
+    (year, month, day)
+}
+
+// The offset of the local timezone at the local time.
+// The offset at the UTC time is close enough except around the transition of DST.
+#[cfg(unix)]
+fn local_offset(time: i64) -> i64 {
+    let t = time as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(not(unix))]
+fn local_offset(_time: i64) -> i64 {
+    0
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(_time: i64) -> i64 {
+        0
+    }
+
+    fn parse_str(pat: &str, s: &str, now: u64) -> Option<u64> {
+        let re = Regex::new(pat).unwrap();
+        parse(&re.captures(s).unwrap(), now, utc)
+    }
+
+    #[test]
+    fn test_parse() {
+        let iso = r"(?P<year>\d{4})-(?P<month>\d\d)-(?P<day>\d\d)T(?P<hour>\d\d):(?P<minute>\d\d):(?P<second>\d\d)(?P<offset>Z|[+-]\d\d:\d\d)?";
+        assert_eq!(parse_str(iso, "1970-01-02T00:00:00Z", 0), Some(86400));
+        assert_eq!(parse_str(iso, "2021-03-04T05:06:07Z", 0), Some(1614834367));
+        assert_eq!(
+            parse_str(iso, "2021-03-04T14:06:07+09:00", 0),
+            Some(1614834367)
+        );
+        assert_eq!(parse_str(iso, "2021-03-04T05:06:07", 0), Some(1614834367));
+        assert_eq!(parse_str(iso, "2021-13-04T05:06:07", 0), None);
+
+        // syslog without year
+        let syslog =
+            r"^(?P<month>\w{3}) +(?P<day>\d+) (?P<hour>\d\d):(?P<minute>\d\d):(?P<second>\d\d)";
+        let now = 1614834367;
+        assert_eq!(parse_str(syslog, "Mar  4 05:06:07 host", now), Some(now));
+        // December lines in January belong to the last year
+        assert_eq!(
+            parse_str(syslog, "Dec 31 00:00:00 host", 1609459200),
+            Some(1609372800)
+        );
+        assert_eq!(parse_str(syslog, "Foo  4 05:06:07 host", now), None);
+    }
+
+    #[test]
+    fn test_civil() {
+        for days in &[-719468, -1, 0, 18690, 2932896] {
+            let (y, m, d) = civil_from_days(*days);
+            assert_eq!(days_from_civil(y, m, d), *days);
+        }
+        assert_eq!(civil_from_days(18690), (2021, 3, 4));
+    }
+
+    #[test]
+    fn test_since() {
+        let re =
+            Regex::new(r"^(?P<year>\d{4})-(?P<month>\d\d)-(?P<day>\d\d)(?P<offset>Z)").unwrap();
+        let mut since = Since::new(re, 86400, 0);
+        assert!(since.is_before(b"1970-01-01Z a\n"));
+        assert!(since.is_before(b"  continued\n"));
+        assert!(!since.is_before(b"1970-01-02Z b\n"));
+        assert!(!since.is_before(b"1970-01-01Z c\n"));
+        assert!(!since.is_before(b"  continued\n"));
+    }
+}
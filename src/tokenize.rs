@@ -0,0 +1,339 @@
+use serde_derive::Deserialize;
+use std::ops::Range;
+
+// -------------------------------------------------------------------------------------------------
+// Tokenizer
+// -------------------------------------------------------------------------------------------------
+
+// Split a line into named fields before matching rules.
+// Positional fields are named `field.N` (1-origin), and key-value fields are named `kv.KEY`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tokenizer {
+    Whitespace,
+    Logfmt,
+    Json,
+    Csv,
+}
+
+// The name and the byte range of each field in the line
+pub type Fields = Vec<(String, Range<usize>)>;
+
+impl Tokenizer {
+    pub fn tokenize(self, s: &str) -> Fields {
+        let s = s.trim_end_matches(&['\n', '\r'][..]);
+        match self {
+            Tokenizer::Whitespace => whitespace(s),
+            Tokenizer::Logfmt => logfmt(s),
+            Tokenizer::Json => json(s),
+            Tokenizer::Csv => csv(s),
+        }
+    }
+}
+
+pub fn get_field(fields: &Fields, name: &str) -> Option<Range<usize>> {
+    fields
+        .iter()
+        .find(|(x, _)| x == name)
+        .map(|(_, r)| r.clone())
+}
+
+fn positional(ranges: Vec<Range<usize>>) -> Fields {
+    ranges
+        .into_iter()
+        .enumerate()
+        .map(|(i, r)| (format!("field.{}", i + 1), r))
+        .collect()
+}
+
+fn whitespace(s: &str) -> Fields {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (i, c) in s.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(x)) => {
+                ranges.push(x..i);
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => (),
+        }
+    }
+    if let Some(x) = start {
+        ranges.push(x..s.len());
+    }
+    positional(ranges)
+}
+
+fn csv(s: &str) -> Fields {
+    let b = s.as_bytes();
+    let mut ranges = Vec::new();
+    let mut i = 0;
+    loop {
+        if b.get(i) == Some(&b'"') {
+            let (range, end) = quoted(b, i);
+            ranges.push(range);
+            i = end;
+            while i < b.len() && b[i] != b',' {
+                i += 1;
+            }
+        } else {
+            let start = i;
+            while i < b.len() && b[i] != b',' {
+                i += 1;
+            }
+            ranges.push(start..i);
+        }
+        if i >= b.len() {
+            break;
+        }
+        i += 1;
+    }
+    positional(ranges)
+}
+
+fn logfmt(s: &str) -> Fields {
+    let b = s.as_bytes();
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < b.len() {
+        while i < b.len() && b[i] == b' ' {
+            i += 1;
+        }
+        let start = i;
+        while i < b.len() && b[i] != b'=' && b[i] != b' ' {
+            i += 1;
+        }
+        let key = &s[start..i];
+        if b.get(i) != Some(&b'=') {
+            continue;
+        }
+        i += 1;
+        let range = if b.get(i) == Some(&b'"') {
+            let (range, end) = quoted(b, i);
+            i = end;
+            range
+        } else {
+            let start = i;
+            while i < b.len() && b[i] != b' ' {
+                i += 1;
+            }
+            start..i
+        };
+        if !key.is_empty() {
+            ret.push((format!("kv.{}", key), range));
+        }
+    }
+    ret
+}
+
+// Top-level keys of a JSON object only
+fn json(s: &str) -> Fields {
+    let b = s.as_bytes();
+    let mut ret = Vec::new();
+    let mut i = skip_space(b, 0);
+    if b.get(i) != Some(&b'{') {
+        return ret;
+    }
+    i += 1;
+    loop {
+        i = skip_space(b, i);
+        if b.get(i) != Some(&b'"') {
+            break;
+        }
+        let (range, end) = quoted(b, i);
+        let key = &s[range];
+        i = skip_space(b, end);
+        if b.get(i) != Some(&b':') {
+            break;
+        }
+        i = skip_space(b, i + 1);
+        let (range, end) = json_value(b, i);
+        ret.push((format!("kv.{}", key), range));
+        i = skip_space(b, end);
+        if b.get(i) != Some(&b',') {
+            break;
+        }
+        i += 1;
+    }
+    ret
+}
+
+// Return the range of the value without quotes, and the position after the value
+fn json_value(b: &[u8], start: usize) -> (Range<usize>, usize) {
+    let mut i = start;
+    match b.get(i) {
+        Some(b'"') => quoted(b, i),
+        Some(b'{') | Some(b'[') => {
+            let mut depth = 0;
+            while i < b.len() {
+                match b[i] {
+                    b'"' => {
+                        i = quoted_end(b, i);
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            i += 1;
+                            break;
+                        }
+                    }
+                    _ => (),
+                }
+                i += 1;
+            }
+            (start..i, i)
+        }
+        _ => {
+            while i < b.len() && !matches!(b[i], b',' | b'}' | b' ' | b'\t') {
+                i += 1;
+            }
+            (start..i, i)
+        }
+    }
+}
+
+fn skip_space(b: &[u8], mut i: usize) -> usize {
+    while i < b.len() && b[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+// Return the range inside the quotes of the string starting at `start`, and the end of it
+fn quoted(b: &[u8], start: usize) -> (Range<usize>, usize) {
+    let end = quoted_end(b, start);
+    let stop = if end > start + 1 && b[end - 1] == b'"' {
+        end - 1
+    } else {
+        end
+    };
+    (start + 1..stop, end)
+}
+
+// Return the position after the closing quote of the string starting at `start`.
+// An unterminated string continues to the end without cutting a multi-byte character.
+fn quoted_end(b: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < b.len() {
+        match b[i] {
+            b'\\' => i += 1,
+            b'"' => return i + 1,
+            _ => (),
+        }
+        i += 1;
+    }
+    b.len()
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values<'a>(s: &'a str, fields: &Fields) -> Vec<(String, &'a str)> {
+        fields
+            .iter()
+            .map(|(k, r)| (k.clone(), &s[r.clone()]))
+            .collect()
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let s = "GET  /index.html 200\n";
+        let fields = Tokenizer::Whitespace.tokenize(s);
+        assert_eq!(
+            values(s, &fields),
+            vec![
+                (String::from("field.1"), "GET"),
+                (String::from("field.2"), "/index.html"),
+                (String::from("field.3"), "200"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let s = "a,\"b,c\",,d\n";
+        let fields = Tokenizer::Csv.tokenize(s);
+        assert_eq!(
+            values(s, &fields),
+            vec![
+                (String::from("field.1"), "a"),
+                (String::from("field.2"), "b,c"),
+                (String::from("field.3"), ""),
+                (String::from("field.4"), "d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_logfmt() {
+        let s = "level=error msg=\"disk full\" status=500 flag";
+        let fields = Tokenizer::Logfmt.tokenize(s);
+        assert_eq!(
+            values(s, &fields),
+            vec![
+                (String::from("kv.level"), "error"),
+                (String::from("kv.msg"), "disk full"),
+                (String::from("kv.status"), "500"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let s = r#"{"level": "warn", "code":404, "ctx": {"a": [1, "}"]}, "ok": true}"#;
+        let fields = Tokenizer::Json.tokenize(s);
+        assert_eq!(
+            values(s, &fields),
+            vec![
+                (String::from("kv.level"), "warn"),
+                (String::from("kv.code"), "404"),
+                (String::from("kv.ctx"), r#"{"a": [1, "}"]}"#),
+                (String::from("kv.ok"), "true"),
+            ]
+        );
+        assert_eq!(get_field(&fields, "kv.code"), Some(25..28));
+        assert_eq!(Tokenizer::Json.tokenize("plain text"), vec![]);
+    }
+
+    #[test]
+    fn test_multibyte() {
+        let s = "msg=\"ディスク満杯\" user=山田 note=\"未終了😀";
+        let fields = Tokenizer::Logfmt.tokenize(s);
+        assert_eq!(
+            values(s, &fields),
+            vec![
+                (String::from("kv.msg"), "ディスク満杯"),
+                (String::from("kv.user"), "山田"),
+                (String::from("kv.note"), "未終了😀"),
+            ]
+        );
+
+        let s = r#"{"名前": "太郎", "絵": "🍣"#;
+        let fields = Tokenizer::Json.tokenize(s);
+        assert_eq!(
+            values(s, &fields),
+            vec![
+                (String::from("kv.名前"), "太郎"),
+                (String::from("kv.絵"), "🍣"),
+            ]
+        );
+
+        let s = "東京,\"大阪";
+        let fields = Tokenizer::Csv.tokenize(s);
+        assert_eq!(
+            values(s, &fields),
+            vec![
+                (String::from("field.1"), "東京"),
+                (String::from("field.2"), "大阪"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+// -------------------------------------------------------------------------------------------------
+// TraceReader
+// -------------------------------------------------------------------------------------------------
+
+// A reader of the bytes written to a stream of the traced process.
+// EOF is returned after the process exits.
+pub struct TraceReader {
+    rx: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for TraceReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            match self.rx.recv() {
+                Ok(x) => {
+                    self.buf = x;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.buf.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+// Attach to the process by ptrace, and return the readers of stdout and stderr.
+// proc-reader can't be used for this because it merges writes to both streams.
+pub fn attach(pid: i32) -> Result<[TraceReader; 2]> {
+    let (tx_out, rx_out) = mpsc::channel();
+    let (tx_err, rx_err) = mpsc::channel();
+    let (tx_ready, rx_ready) = mpsc::channel();
+    // All ptrace requests must be issued by the thread attached to the process
+    thread::spawn(move || trace(pid, [tx_out, tx_err], tx_ready));
+    rx_ready
+        .recv()
+        .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::Other)))
+        .context(format!("failed to attach to process ({})", pid))?;
+    let reader = |rx| TraceReader {
+        rx,
+        buf: Vec::new(),
+        pos: 0,
+    };
+    Ok([reader(rx_out), reader(rx_err)])
+}
+
+fn trace(pid: i32, txs: [Sender<Vec<u8>>; 2], ready: Sender<io::Result<()>>) {
+    let attached = ptrace(libc::PTRACE_ATTACH, pid, 0)
+        .and_then(|_| wait_attached(pid))
+        .and_then(|_| {
+            ptrace(
+                libc::PTRACE_SETOPTIONS,
+                pid,
+                libc::PTRACE_O_TRACESYSGOOD as usize,
+            )
+        })
+        .and_then(|_| ptrace(libc::PTRACE_SYSCALL, pid, 0));
+    let failed = attached.is_err();
+    let _ = ready.send(attached);
+    if failed {
+        return;
+    }
+
+    // Syscall stops alternate between the entry and the exit
+    let mut entry = true;
+    let mut closed = [false, false];
+    // The stream of each file descriptor, which follows redirections like `echo >&2` of shells
+    let mut fds = HashMap::from([(1, 0), (2, 1)]);
+    loop {
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, libc::__WALL) } < 0 {
+            return;
+        }
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            return;
+        }
+        let mut sig = 0;
+        if libc::WIFSTOPPED(status) {
+            if libc::WSTOPSIG(status) == libc::SIGTRAP | 0x80 {
+                if let Some(regs) = get_regs(pid) {
+                    if entry {
+                        send_write(pid, &regs, &fds, &txs, &mut closed);
+                    } else {
+                        update_fds(&regs, &mut fds);
+                    }
+                }
+                entry = !entry;
+            } else {
+                // Signals are delivered to the process as is
+                sig = libc::WSTOPSIG(status);
+            }
+        }
+        // Nobody reads the output anymore
+        if closed == [true, true] {
+            let _ = ptrace(libc::PTRACE_DETACH, pid, sig as usize);
+            return;
+        }
+        if ptrace(libc::PTRACE_SYSCALL, pid, sig as usize).is_err() {
+            return;
+        }
+    }
+}
+
+// Wait for the stop by the attach, and deliver other signals arriving before it
+fn wait_attached(pid: i32) -> io::Result<()> {
+    loop {
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, libc::__WALL) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if !libc::WIFSTOPPED(status) {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        if libc::WSTOPSIG(status) == libc::SIGSTOP {
+            return Ok(());
+        }
+        ptrace(libc::PTRACE_CONT, pid, libc::WSTOPSIG(status) as usize)?;
+    }
+}
+
+fn get_regs(pid: i32) -> Option<libc::user_regs_struct> {
+    let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETREGS,
+            pid,
+            ptr::null_mut::<libc::c_void>(),
+            &mut regs as *mut libc::user_regs_struct,
+        )
+    };
+    (ret >= 0).then_some(regs)
+}
+
+// Send the bytes of write(2) to stdout or stderr at the entry of the syscall
+fn send_write(
+    pid: i32,
+    regs: &libc::user_regs_struct,
+    fds: &HashMap<u64, usize>,
+    txs: &[Sender<Vec<u8>>; 2],
+    closed: &mut [bool; 2],
+) {
+    if regs.orig_rax != libc::SYS_write as u64 {
+        return;
+    }
+    let i = match fds.get(&regs.rdi) {
+        Some(x) => *x,
+        None => return,
+    };
+
+    let mut buf = vec![0u8; regs.rdx as usize];
+    let local = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: regs.rsi as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let len = unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+    if len > 0 {
+        buf.truncate(len as usize);
+        closed[i] = txs[i].send(buf).is_err();
+    }
+}
+
+// Follow the duplication and close of file descriptors at the exit of the syscall
+fn update_fds(regs: &libc::user_regs_struct, fds: &mut HashMap<u64, usize>) {
+    let ret = regs.rax as i64;
+    if ret < 0 {
+        return;
+    }
+    let (src, dst) = match regs.orig_rax as i64 {
+        libc::SYS_dup => (regs.rdi, ret as u64),
+        libc::SYS_dup2 | libc::SYS_dup3 => (regs.rdi, regs.rsi),
+        libc::SYS_fcntl
+            if regs.rsi == libc::F_DUPFD as u64 || regs.rsi == libc::F_DUPFD_CLOEXEC as u64 =>
+        {
+            (regs.rdi, ret as u64)
+        }
+        libc::SYS_close => {
+            fds.remove(&regs.rdi);
+            return;
+        }
+        _ => return,
+    };
+    match fds.get(&src) {
+        Some(&x) => fds.insert(dst, x),
+        None => fds.remove(&dst),
+    };
+}
+
+#[cfg(target_env = "gnu")]
+type Request = libc::c_uint;
+
+#[cfg(target_env = "musl")]
+type Request = libc::c_int;
+
+fn ptrace(request: Request, pid: i32, data: usize) -> io::Result<()> {
+    let ret = unsafe {
+        libc::ptrace(
+            request,
+            pid,
+            ptr::null_mut::<libc::c_void>(),
+            data as *mut libc::c_void,
+        )
+    };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn test_attach() {
+        // The child writes after attached because it waits for the input
+        let mut child = Command::new("sh")
+            .args(["-c", "read x; echo out; echo err >&2; echo out"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let [mut stdout, mut stderr] = attach(child.id() as i32).unwrap();
+        child.stdin.take().unwrap().write_all(b"\n").unwrap();
+
+        let mut s = String::new();
+        stdout.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "out\nout\n");
+        let mut s = String::new();
+        stderr.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "err\n");
+        // The exit status is taken by the tracer
+        let _ = child.wait();
+
+        assert!(attach(0x7fff_fff0).is_err());
+    }
+}
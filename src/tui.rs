@@ -0,0 +1,588 @@
+use crate::colorize::{self, has_sgr, spans, strip_sgr, term_color, ColorDepth, Config};
+use crate::source::Source;
+use crate::{
+    decode, get_delimiter, get_label, open_source, spawn_reader, Opt, FOLLOW_INTERVAL, LABEL_COLORS,
+};
+use anyhow::Result;
+use atty::Stream;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::sync::mpsc;
+
+// The number of lines kept for scrollback
+const SCROLLBACK: usize = 100_000;
+
+// The width of the stats sidebar
+const SIDEBAR: usize = 32;
+
+// The colors of the basic 16 colors in the order of SGR 30-37 and 90-97
+const ANSI16_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Gray,
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+    Color::White,
+];
+
+// -------------------------------------------------------------------------------------------------
+// Screen
+// -------------------------------------------------------------------------------------------------
+
+// The terminal in raw mode with the alternate screen, restored when dropped.
+// crossterm reads keys from the controlling terminal even if stdin is the piped input.
+struct Screen {
+    terminal: DefaultTerminal,
+}
+
+impl Screen {
+    fn new() -> Result<Self> {
+        let terminal = ratatui::try_init()?;
+        Ok(Screen { terminal })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Key
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Esc,
+    Backspace,
+}
+
+fn to_key(event: KeyEvent) -> Option<Key> {
+    if event.kind == KeyEventKind::Release {
+        return None;
+    }
+    let key = match event.code {
+        KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => Key::Char('\u{3}'),
+        KeyCode::Char(x) => Key::Char(x),
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Esc => Key::Esc,
+        KeyCode::Backspace => Key::Backspace,
+        _ => return None,
+    };
+    Some(key)
+}
+
+// -------------------------------------------------------------------------------------------------
+// View
+// -------------------------------------------------------------------------------------------------
+
+// The scrollback of lines with the state of scroll, search and rule toggles
+struct View {
+    labels: Vec<Span<'static>>,
+    lines: VecDeque<(usize, String)>,
+    counts: Vec<usize>,
+    enabled: Vec<bool>,
+    // The config with the enabled rules only
+    active: Config,
+    top: usize,
+    follow: bool,
+    search: String,
+    searching: bool,
+    sidebar: bool,
+}
+
+impl View {
+    fn new(labels: Vec<Span<'static>>, config: &Config) -> Self {
+        View {
+            labels,
+            lines: VecDeque::new(),
+            counts: vec![0; config.lines.len()],
+            enabled: vec![true; config.lines.len()],
+            active: config.clone(),
+            top: 0,
+            follow: true,
+            search: String::new(),
+            searching: false,
+            sidebar: true,
+        }
+    }
+
+    fn push(&mut self, i: usize, buf: &[u8], config: &Config) -> Result<()> {
+        let s = String::from_utf8_lossy(buf);
+        let s = s.trim_end_matches(&['\r', '\n'][..]);
+        let s = if has_sgr(s) {
+            strip_sgr(s)
+        } else {
+            s.to_string()
+        };
+        if let Some(x) = spans(&s, config)?.rule {
+            self.counts[x] += 1;
+        }
+        self.lines.push_back((i, s));
+        if self.lines.len() > SCROLLBACK {
+            self.lines.pop_front();
+            self.top = self.top.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    fn toggle(&mut self, i: usize, config: &Config) {
+        if i >= self.enabled.len() {
+            return;
+        }
+        self.enabled[i] = !self.enabled[i];
+        self.active.lines = config
+            .lines
+            .iter()
+            .zip(&self.enabled)
+            .filter(|(_, x)| **x)
+            .map(|(x, _)| x.clone())
+            .collect();
+        self.active.build_line_set();
+    }
+
+    fn max_top(&self, height: usize) -> usize {
+        self.lines.len().saturating_sub(height)
+    }
+
+    fn top(&self, height: usize) -> usize {
+        if self.follow {
+            self.max_top(height)
+        } else {
+            self.top.min(self.max_top(height))
+        }
+    }
+
+    fn scroll_to(&mut self, top: isize, height: usize) {
+        let max = self.max_top(height);
+        self.top = top.clamp(0, max as isize) as usize;
+        self.follow = self.top == max;
+    }
+
+    fn is_match(&self, i: usize) -> bool {
+        !self.search.is_empty() && self.lines[i].1.contains(&self.search)
+    }
+
+    // Scroll to the next match after the line, or the previous match before it
+    fn find(&mut self, from: usize, forward: bool, height: usize) {
+        let found = if forward {
+            (from..self.lines.len()).find(|x| self.is_match(*x))
+        } else {
+            (0..from.min(self.lines.len()))
+                .rev()
+                .find(|x| self.is_match(*x))
+        };
+        if let Some(x) = found {
+            self.scroll_to(x as isize, height);
+        }
+    }
+
+    // Return true to quit
+    fn handle(&mut self, key: Key, height: usize, config: &Config) -> bool {
+        let top = self.top(height) as isize;
+        let page = height as isize;
+        if self.searching {
+            match key {
+                Key::Char(x) if !x.is_control() => self.search.push(x),
+                Key::Backspace => {
+                    self.search.pop();
+                }
+                Key::Enter => self.searching = false,
+                Key::Esc | Key::Char(_) => {
+                    self.searching = false;
+                    self.search.clear();
+                }
+                _ => (),
+            }
+            if self.searching {
+                // Incremental search from the current position
+                self.find(top as usize, true, height);
+            }
+            return false;
+        }
+        match key {
+            Key::Char('q') | Key::Char('\u{3}') => return true,
+            Key::Char('j') | Key::Down | Key::Enter => self.scroll_to(top + 1, height),
+            Key::Char('k') | Key::Up => self.scroll_to(top - 1, height),
+            Key::Char(' ') | Key::PageDown => self.scroll_to(top + page, height),
+            Key::Char('b') | Key::PageUp => self.scroll_to(top - page, height),
+            Key::Char('g') | Key::Home => self.scroll_to(0, height),
+            Key::Char('G') | Key::End => self.scroll_to(isize::MAX, height),
+            Key::Char('/') => {
+                self.searching = true;
+                self.search.clear();
+            }
+            Key::Char('n') => self.find(top as usize + 1, true, height),
+            Key::Char('N') => self.find(top as usize, false, height),
+            Key::Char('s') => self.sidebar = !self.sidebar,
+            Key::Char(x @ '1'..='9') => self.toggle(x as usize - '1' as usize, config),
+            _ => (),
+        }
+        false
+    }
+
+    fn render(&self, frame: &mut Frame, config: &Config) -> Result<()> {
+        let area = frame.area();
+        let sidebar = self.sidebar && area.width as usize >= SIDEBAR * 2;
+        let [main, bar] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area);
+        let side = if sidebar { SIDEBAR as u16 + 1 } else { 0 };
+        let [pane, side] =
+            Layout::horizontal([Constraint::Min(0), Constraint::Length(side)]).areas(main);
+        let body = main.height as usize;
+        let top = self.top(body);
+
+        let mut lines = Vec::new();
+        for i in top..(top + body).min(self.lines.len()) {
+            let (source, ref s) = self.lines[i];
+            let gutter = if self.is_match(i) {
+                Span::styled(" ", Style::new().add_modifier(Modifier::REVERSED))
+            } else {
+                Span::raw(" ")
+            };
+            let mut line = vec![gutter, self.labels[source].clone()];
+            line.extend(styled_spans(s, &self.active)?);
+            lines.push(Line::from(line));
+        }
+        frame.render_widget(Paragraph::new(lines), pane);
+
+        if sidebar {
+            let block = Block::new().borders(Borders::LEFT);
+            frame.render_widget(
+                Paragraph::new(self.render_sidebar(config)?).block(block),
+                side,
+            );
+        }
+
+        let status = if self.searching {
+            format!("/{}", self.search)
+        } else {
+            format!(
+                " {}-{}/{}{}  q:quit /:search n/N:next/prev 1-9:toggle rule s:sidebar",
+                (top + 1).min(self.lines.len()),
+                (top + body).min(self.lines.len()),
+                self.lines.len(),
+                if self.follow { " (follow)" } else { "" },
+            )
+        };
+        let style = Style::new().add_modifier(Modifier::REVERSED);
+        frame.render_widget(Paragraph::new(status).style(style), bar);
+        Ok(())
+    }
+
+    // Match counts of each rule, and whether it is enabled
+    fn render_sidebar(&self, config: &Config) -> Result<Vec<Line<'static>>> {
+        let mut ret = vec![Line::raw(" rules")];
+        for (i, line) in config.lines.iter().enumerate() {
+            let name = line
+                .description
+                .clone()
+                .unwrap_or_else(|| line.pat.as_str().to_string());
+            let mark = if self.enabled[i] { "x" } else { " " };
+            let name: String = name.chars().take(SIDEBAR - 15).collect();
+            let text = format!(
+                " {} [{}] {:<w$} {:>6}",
+                i + 1,
+                mark,
+                name,
+                self.counts[i],
+                w = SIDEBAR - 15
+            );
+            let style = if self.enabled[i] {
+                Style::new()
+            } else {
+                tui_style(&colorize::Style::from_spec("LightBlack")?, config.depth)?
+            };
+            ret.push(Line::styled(text, style));
+        }
+        Ok(ret)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Style
+// -------------------------------------------------------------------------------------------------
+
+// The spans of ratatui converted from the spans of the colorize engine
+fn styled_spans<'a>(s: &'a str, config: &Config) -> Result<Vec<Span<'a>>> {
+    let spans = spans(s, config)?;
+    let mut ret = Vec::new();
+    let mut inserts = spans.inserts.into_iter().peekable();
+    for (i, (range, style)) in spans.spans.into_iter().enumerate() {
+        while let Some((_, text)) = inserts.next_if(|x| x.0 <= i) {
+            ret.push(Span::raw(text));
+        }
+        ret.push(Span::styled(&s[range], tui_style(&style, config.depth)?));
+    }
+    ret.extend(inserts.map(|(_, text)| Span::raw(text)));
+    Ok(ret)
+}
+
+fn tui_style(style: &colorize::Style, depth: ColorDepth) -> Result<Style> {
+    let mut ret = Style::new();
+    if depth == ColorDepth::Dumb {
+        return Ok(ret);
+    }
+    ret = ret.fg(tui_color(&style.color, depth)?);
+    if let Some(ref bg) = style.bg {
+        ret = ret.bg(tui_color(bg, depth)?);
+    }
+    if style.attrs.bold {
+        ret = ret.add_modifier(Modifier::BOLD);
+    }
+    if style.attrs.dim {
+        ret = ret.add_modifier(Modifier::DIM);
+    }
+    if style.attrs.italic {
+        ret = ret.add_modifier(Modifier::ITALIC);
+    }
+    if style.attrs.underline {
+        ret = ret.add_modifier(Modifier::UNDERLINED);
+    }
+    Ok(ret)
+}
+
+fn tui_color(name: &str, depth: ColorDepth) -> Result<Color> {
+    let ret = match term_color(name, depth)? {
+        colorize::Color::Reset => Color::Reset,
+        colorize::Color::Ansi16(x) => ANSI16_COLORS[x as usize],
+        colorize::Color::Ansi256(x) => Color::Indexed(x),
+        colorize::Color::Rgb((r, g, b)) => Color::Rgb(r, g, b),
+    };
+    Ok(ret)
+}
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+pub fn run(sources: Vec<Box<dyn Source>>, config: &Config, opt: &Opt) -> Result<()> {
+    if !atty::is(Stream::Stdout) {
+        anyhow::bail!("--tui requires a terminal");
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut labels = Vec::new();
+    let mut skipped = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        labels.push(if sources.len() > 1 {
+            let color = LABEL_COLORS[i % LABEL_COLORS.len()];
+            let style = tui_style(&colorize::Style::from_spec(color)?, config.depth)?;
+            Span::styled(get_label(&**source, i, false, config)?, style)
+        } else {
+            Span::raw("")
+        });
+        if let Some(reader) = open_source(&**source, opt, &mut skipped)? {
+            let follow = (opt.follow || opt.follow_name) && source.can_follow();
+            spawn_reader(
+                i,
+                decode(reader, opt),
+                config.record_start.clone(),
+                get_delimiter(opt),
+                follow,
+                tx.clone(),
+            );
+        }
+    }
+    drop(tx);
+
+    let mut screen = Screen::new()?;
+    let mut view = View::new(labels, config);
+    let mut dirty = true;
+    loop {
+        // Limit lines per frame to keep keys responsive under heavy input
+        for _ in 0..10000 {
+            match rx.try_recv() {
+                Ok((i, line)) => {
+                    if let Some(buf) = line? {
+                        view.push(i, &buf, config)?;
+                        dirty = true;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+
+        if event::poll(FOLLOW_INTERVAL)? {
+            match event::read()? {
+                Event::Key(x) => {
+                    // The last row is the status line
+                    let height = screen.terminal.size()?.height.saturating_sub(1) as usize;
+                    if let Some(key) = to_key(x) {
+                        if view.handle(key, height, config) {
+                            return Ok(());
+                        }
+                    }
+                }
+                Event::Resize(_, _) => (),
+                _ => continue,
+            }
+            dirty = true;
+        }
+
+        if dirty {
+            let mut ret = Ok(());
+            screen
+                .terminal
+                .draw(|frame| ret = view.render(frame, config))?;
+            ret?;
+            dirty = false;
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::buffer::Buffer;
+    use ratatui::Terminal;
+
+    fn config() -> Config {
+        let mut config: Config = toml::from_str(
+            r#"
+            [[lines]]
+            pat = "(Error).*"
+            colors = ["Red"]
+            [[lines]]
+            pat = "(Info).*"
+            colors = ["Green"]
+            description = "info"
+            "#,
+        )
+        .unwrap();
+        config.depth = ColorDepth::Ansi16;
+        config
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    // The text of the row of the rendered buffer
+    fn row(buf: &Buffer, y: u16) -> String {
+        (0..buf.area.width).map(|x| buf[(x, y)].symbol()).collect()
+    }
+
+    #[test]
+    fn test_to_key() {
+        assert_eq!(to_key(key(KeyCode::Char('q'))), Some(Key::Char('q')));
+        assert_eq!(to_key(key(KeyCode::PageDown)), Some(Key::PageDown));
+        assert_eq!(to_key(key(KeyCode::Esc)), Some(Key::Esc));
+        assert_eq!(
+            to_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            Some(Key::Char('\u{3}'))
+        );
+        assert_eq!(to_key(key(KeyCode::F(1))), None);
+    }
+
+    #[test]
+    fn test_view() {
+        let config = config();
+        let mut view = View::new(vec![Span::raw("")], &config);
+        for i in 0..10 {
+            let line = if i % 3 == 0 {
+                "Error: a\n"
+            } else {
+                "Info: b\n"
+            };
+            view.push(0, line.as_bytes(), &config).unwrap();
+        }
+        assert_eq!(view.counts, vec![4, 6]);
+        assert_eq!(view.top(4), 6);
+
+        // Search moves to the match, and scrolling stops following
+        view.handle(Key::Char('g'), 4, &config);
+        assert_eq!(view.top(4), 0);
+        for key in [
+            Key::Char('/'),
+            Key::Char('E'),
+            Key::Char('r'),
+            Key::Char('r'),
+            Key::Enter,
+        ] {
+            view.handle(key, 4, &config);
+        }
+        assert_eq!(view.top(4), 0);
+        view.handle(Key::Char('n'), 4, &config);
+        assert_eq!(view.top(4), 3);
+        view.handle(Key::Char('N'), 4, &config);
+        assert_eq!(view.top(4), 0);
+        assert!(!view.follow);
+        view.handle(Key::End, 4, &config);
+        assert!(view.follow);
+
+        view.handle(Key::Char('2'), 4, &config);
+        assert_eq!(view.enabled, vec![true, false]);
+        assert_eq!(view.active.lines.len(), 1);
+        assert!(view.handle(Key::Char('q'), 4, &config));
+    }
+
+    #[test]
+    fn test_render() {
+        let config = config();
+        let mut view = View::new(vec![Span::raw("")], &config);
+        view.push(0, b"Error: disk full\n", &config).unwrap();
+        view.push(0, b"\x1b[1mInfo: ok\x1b[m\n", &config).unwrap();
+        view.sidebar = false;
+        let mut terminal = Terminal::new(TestBackend::new(12, 3)).unwrap();
+        terminal
+            .draw(|frame| view.render(frame, &config).unwrap())
+            .unwrap();
+        let buf = terminal.backend().buffer();
+        assert_eq!(row(buf, 0), " Error: disk");
+        assert_eq!(row(buf, 1), " Info: ok   ");
+        assert_eq!(row(buf, 2), " 1-2/2 (foll");
+        assert_eq!(buf[(1, 0)].fg, Color::Red);
+        assert_eq!(buf[(1, 1)].fg, Color::Green);
+        assert!(!buf[(1, 1)].modifier.contains(Modifier::BOLD));
+        assert!(buf[(0, 2)].modifier.contains(Modifier::REVERSED));
+
+        view.sidebar = true;
+        view.toggle(1, &config);
+        let mut terminal = Terminal::new(TestBackend::new(80, 4)).unwrap();
+        terminal
+            .draw(|frame| view.render(frame, &config).unwrap())
+            .unwrap();
+        let buf = terminal.backend().buffer();
+        assert!(row(buf, 0).ends_with("│ rules                          "));
+        assert!(row(buf, 1).starts_with(" Info: ok  "));
+        assert_eq!(buf[(1, 1)].fg, Color::Reset);
+        assert!(row(buf, 1).ends_with("│ 1 [x] (Error).*              1 "));
+        assert!(row(buf, 2).ends_with("│ 2 [ ] info                   1 "));
+        assert_eq!(buf[(50, 2)].fg, Color::DarkGray);
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// -------------------------------------------------------------------------------------------------
+// Walker
+// -------------------------------------------------------------------------------------------------
+
+pub struct Walker {
+    pub follow_symlinks: bool,
+    pub max_depth: Option<usize>,
+    visited: HashSet<FileKey>,
+}
+
+impl Walker {
+    pub fn new(follow_symlinks: bool, max_depth: Option<usize>) -> Self {
+        Walker {
+            follow_symlinks,
+            max_depth,
+            visited: HashSet::new(),
+        }
+    }
+
+    // Collect files under the directory in sorted order.
+    // Directories and files already visited are skipped to avoid symlink loops and double processing.
+    pub fn walk(&mut self, path: &Path) -> Result<Vec<PathBuf>> {
+        let mut ret = Vec::new();
+        self.walk_dir(path, 0, &mut ret)?;
+        Ok(ret)
+    }
+
+    fn walk_dir(&mut self, path: &Path, depth: usize, ret: &mut Vec<PathBuf>) -> Result<()> {
+        if !self.visit(path) {
+            return Ok(());
+        }
+
+        let mut entries = Vec::new();
+        for entry in
+            fs::read_dir(path).context(format!("failed to read '{}'", path.to_string_lossy()))?
+        {
+            entries.push(entry?.path());
+        }
+        entries.sort();
+
+        for entry in entries {
+            let meta = fs::symlink_metadata(&entry)?;
+            if meta.file_type().is_symlink() && !self.follow_symlinks {
+                continue;
+            }
+            if entry.is_dir() {
+                if self.max_depth.is_none_or(|x| depth < x) {
+                    self.walk_dir(&entry, depth + 1, ret)?;
+                }
+            } else if self.visit(&entry) {
+                ret.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn visit(&mut self, path: &Path) -> bool {
+        match file_key(path) {
+            Some(key) => self.visited.insert(key),
+            None => true,
+        }
+    }
+}
+
+#[cfg(unix)]
+type FileKey = (u64, u64);
+
+#[cfg(unix)]
+fn file_key(path: &Path) -> Option<FileKey> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|x| (x.dev(), x.ino()))
+}
+
+#[cfg(not(unix))]
+type FileKey = PathBuf;
+
+#[cfg(not(unix))]
+fn file_key(path: &Path) -> Option<FileKey> {
+    fs::canonicalize(path).ok()
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk() {
+        use std::os::unix::fs::symlink;
+
+        let root = std::env::temp_dir().join(format!("pipecolor_walk_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("x.log"), b"").unwrap();
+        fs::write(root.join("a/y.log"), b"").unwrap();
+        fs::write(root.join("a/b/z.log"), b"").unwrap();
+        symlink(&root, root.join("a/loop")).unwrap();
+        symlink(root.join("x.log"), root.join("a/x.link")).unwrap();
+
+        let names = |x: Vec<PathBuf>| -> Vec<String> {
+            x.iter()
+                .map(|x| {
+                    x.strip_prefix(&root)
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect()
+        };
+
+        let ret = Walker::new(false, None).walk(&root).unwrap();
+        assert_eq!(names(ret), vec!["a/b/z.log", "a/y.log", "x.log"]);
+
+        let ret = Walker::new(true, None).walk(&root).unwrap();
+        assert_eq!(names(ret), vec!["a/b/z.log", "a/x.link", "a/y.log"]);
+
+        let ret = Walker::new(false, Some(1)).walk(&root).unwrap();
+        assert_eq!(names(ret), vec!["a/y.log", "x.log"]);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,255 @@
+use assert_cmd::Command;
+use std::fs;
+use std::path::PathBuf;
+
+// -------------------------------------------------------------------------------------------------
+// Functions
+// -------------------------------------------------------------------------------------------------
+
+fn check(name: &str, args: &[&str]) {
+    check_stdin(name, args, "");
+}
+
+// Run pipecolor with the arguments, and compare stdout and stderr with `tests/golden/NAME.{out,err}`.
+// The golden files are rewritten instead if UPDATE_GOLDEN is set.
+fn check_stdin(name: &str, args: &[&str], stdin: &str) {
+    let output = Command::cargo_bin("pipecolor")
+        .unwrap()
+        .args(args)
+        .write_stdin(stdin)
+        .env_remove("NO_COLOR")
+        .env_remove("CLICOLOR_FORCE")
+        .env_remove("COLORTERM")
+        .env_remove("DOCKER_HOST")
+        .env_remove("RUST_BACKTRACE")
+        .env_remove("RUST_LIB_BACKTRACE")
+        .env("TERM", "xterm-256color")
+        .output()
+        .unwrap();
+
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for (ext, actual) in [("out", &output.stdout), ("err", &output.stderr)] {
+        let path = dir.join(format!("{}.{}", name, ext));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&path, actual).unwrap();
+            continue;
+        }
+        let expected = fs::read(&path).unwrap_or_default();
+        assert!(
+            *actual == expected,
+            "{} differs from '{}':\n{}",
+            ext,
+            path.to_string_lossy(),
+            String::from_utf8_lossy(actual)
+        );
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Test
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn default_rules() {
+    check(
+        "default_rules",
+        &["--no-user-config", "--mode", "always", "sample/maillog"],
+    );
+}
+
+#[test]
+fn config_file() {
+    check(
+        "config_file",
+        &[
+            "-c",
+            "sample/pipecolor.toml",
+            "--mode",
+            "always",
+            "sample/access_log",
+        ],
+    );
+}
+
+#[test]
+fn mode_disable() {
+    check(
+        "mode_disable",
+        &[
+            "-c",
+            "sample/pipecolor.toml",
+            "--mode",
+            "disable",
+            "sample/maillog",
+        ],
+    );
+}
+
+#[test]
+fn markers() {
+    check(
+        "markers",
+        &[
+            "-c",
+            "sample/pipecolor.toml",
+            "--mode",
+            "disable",
+            "--markers",
+            "sample/maillog",
+        ],
+    );
+}
+
+#[test]
+fn filename_prefix() {
+    check(
+        "filename_prefix",
+        &[
+            "-c",
+            "sample/pipecolor.toml",
+            "--mode",
+            "always",
+            "--filename",
+            "prefix",
+            "sample/access_log",
+            "sample/maillog",
+        ],
+    );
+}
+
+#[test]
+fn filename_header() {
+    check(
+        "filename_header",
+        &[
+            "-c",
+            "sample/pipecolor.toml",
+            "--mode",
+            "disable",
+            "sample/access_log",
+            "sample/maillog",
+        ],
+    );
+}
+
+#[test]
+fn zebra() {
+    check(
+        "zebra",
+        &[
+            "-c",
+            "sample/pipecolor.toml",
+            "--mode",
+            "always",
+            "--zebra",
+            "sample/access_log",
+        ],
+    );
+}
+
+#[test]
+fn lines_newline() {
+    check(
+        "lines_newline",
+        &[
+            "-c",
+            "sample/pipecolor.toml",
+            "--mode",
+            "disable",
+            "--lines",
+            "3",
+            "--newline",
+            "crlf",
+            "sample/maillog",
+        ],
+    );
+}
+
+#[test]
+fn dedupe_window() {
+    check_stdin(
+        "dedupe_window",
+        &[
+            "--no-user-config",
+            "--mode",
+            "always",
+            "--dedupe-window",
+            "2",
+        ],
+        "Error: a\nInfo: b\nError: a\nError: a\nWarning: c\nWarning: c\n",
+    );
+}
+
+#[test]
+fn verbose_names() {
+    check_stdin(
+        "verbose_names",
+        &[
+            "--config",
+            "tests/golden/verbose_names.toml",
+            "--mode",
+            "always",
+            "--verbose",
+        ],
+        "GET /a 500\nPOST /b 503\nGET /c 200\n",
+    );
+}
+
+#[test]
+fn missing_file() {
+    check("missing_file", &["--no-user-config", "sample/not_found"]);
+}
+
+// The output stops quietly if the reader of the pipe exits like `head`
+#[cfg(unix)]
+#[test]
+fn broken_pipe() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{self, Stdio};
+
+    let mut child = process::Command::new(assert_cmd::cargo::cargo_bin("pipecolor"))
+        .args(["--no-user-config", "--", "yes", "Error: x"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    assert_eq!(line, "Error: x\n");
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn match_status() {
+    let run = |args: &[&str], stdin: &str| {
+        Command::cargo_bin("pipecolor")
+            .unwrap()
+            .arg("--no-user-config")
+            .args(args)
+            .write_stdin(stdin)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--match-status"], "a\nError: b\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\nError: b\n");
+    let output = run(&["--match-status"], "a\nb\n");
+    assert_eq!(output.status.code(), Some(1));
+    let output = run(&["--match-status", "no_such_file"], "");
+    assert_eq!(output.status.code(), Some(2));
+    // The exit code is not changed without the flag
+    let output = run(&[], "a\nb\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run(&["-q"], "a\nError: b\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    let output = run(&["--quiet"], "a\nb\n");
+    assert_eq!(output.status.code(), Some(1));
+}